@@ -0,0 +1,56 @@
+//! Detection of original function addresses that are off by a few bytes.
+//!
+//! Disassembling from the middle of an instruction usually produces a few instructions VC6
+//! never emits before the decoder resynchronizes.
+//!
+//! Also the alignment of function starts, which depends on the compiler and linker settings.
+
+use super::disasm::Instruction;
+
+/// Number of leading instructions checked for implausible mnemonics.
+const LEADING_INSTRUCTIONS: usize = 5;
+
+/// Longest possible x86 instruction, a function cut off at its end may stop this early.
+const MAX_INSTRUCTION_LEN: u64 = 15;
+
+const IMPLAUSIBLE_MNEMONICS: &[&str] = &[
+    "aaa", "aad", "aam", "aas", "arpl", "bound", "cli", "daa", "das", "hlt", "in", "insb", "insd",
+    "into", "iretd", "lds", "les", "out", "outsb", "outsd", "retf", "salc", "sti",
+];
+
+/// Largest alignment reported by `start_alignment`, VC6 aligns functions to 16 bytes at most.
+const MAX_FUNCTION_ALIGNMENT: u64 = 16;
+
+/// Mnemonics a VC6 function commonly starts with.
+const PROLOGUE_MNEMONICS: &[&str] = &["push", "sub", "mov", "xor", "lea", "jmp", "cmp", "test"];
+
+/// Whether the disassembly of `size` bytes looks like it started mid-instruction.
+pub fn looks_misaligned(insns: &[Instruction], size: usize) -> bool {
+    let decoded = match (insns.first(), insns.last()) {
+        (Some(first), Some(last)) => last.ip - first.start,
+        _ => return size > 0,
+    };
+    if decoded + MAX_INSTRUCTION_LEN < size as u64 {
+        return true;
+    }
+
+    let has_prologue = PROLOGUE_MNEMONICS.contains(&mnemonic(&insns[0]));
+    let implausible = insns
+        .iter()
+        .take(LEADING_INSTRUCTIONS)
+        .any(|insn| IMPLAUSIBLE_MNEMONICS.contains(&mnemonic(insn)));
+    !has_prologue && implausible
+}
+
+/// Largest power of two up to 16 the address is a multiple of.
+pub fn start_alignment(addr: u64) -> u64 {
+    if addr == 0 {
+        MAX_FUNCTION_ALIGNMENT
+    } else {
+        (1 << addr.trailing_zeros()).min(MAX_FUNCTION_ALIGNMENT)
+    }
+}
+
+fn mnemonic(insn: &Instruction) -> &str {
+    insn.text.split(' ').next().unwrap_or("")
+}
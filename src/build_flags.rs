@@ -0,0 +1,42 @@
+//! Detection of differences caused by compiler flags instead of the code.
+//!
+//! Stack cookies (`/GS`, `xor eax, esp` in the prologue) and the esp checks of VC6 debug builds
+//! (`/GZ`, `cmp esi, esp` followed by a call to `__chkesp`) show up as differing lines, but
+//! mean that both sides were built with different settings.
+
+type Check = fn(&[&str]) -> bool;
+
+/// Notes about build setting idioms only found on one side.
+pub fn build_setting_notes(orig: &[&str], compare: &[&str]) -> Vec<String> {
+    let checks: [(&str, Check); 2] = [
+        (
+            "stack cookie checks (`xor ..., esp`), the builds probably differ in /GS",
+            has_stack_cookie,
+        ),
+        (
+            "esp checks (`cmp ..., esp` and a call to __chkesp), the builds probably differ in /GZ",
+            has_esp_check,
+        ),
+    ];
+
+    checks
+        .iter()
+        .filter_map(|(description, check)| match (check(orig), check(compare)) {
+            (true, false) => Some(format!("only the original contains {}", description)),
+            (false, true) => Some(format!("only devilution contains {}", description)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn has_stack_cookie(lines: &[&str]) -> bool {
+    lines
+        .iter()
+        .any(|line| line.starts_with("xor e") && line.ends_with(", esp"))
+}
+
+fn has_esp_check(lines: &[&str]) -> bool {
+    lines.windows(2).any(|pair| {
+        (pair[0] == "cmp ebp, esp" || pair[0] == "cmp esi, esp") && pair[1].starts_with("call ")
+    })
+}
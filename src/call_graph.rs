@@ -0,0 +1,86 @@
+//! Comparison of the functions called directly or indirectly by both sides.
+//!
+//! A callee missing on one side usually got inlined there, or the other side calls a helper
+//! the original code didn't have. Functions are matched by name, so calls to addresses without
+//! a known symbol are left out.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::disasm::*;
+
+/// Names of the known functions called by every reached function, `None` for functions that
+/// couldn't be disassembled.
+pub type CallGraph = BTreeMap<String, Option<BTreeSet<String>>>;
+
+/// Follows the calls from `root` up to `depth` levels deep, `disasm_fn` disassembles a function
+/// by name.
+pub fn call_graph(
+    root: &str,
+    depth: usize,
+    symbols: &SymbolMap,
+    mut disasm_fn: impl FnMut(&str) -> Option<Vec<Instruction>>,
+) -> CallGraph {
+    let mut graph = CallGraph::new();
+    let mut level = vec![root.to_owned()];
+
+    for _ in 0..depth {
+        let mut next_level = Vec::new();
+        for name in level {
+            if graph.contains_key(&name) {
+                continue;
+            }
+
+            let callees = disasm_fn(&name).map(|insns| {
+                insns
+                    .iter()
+                    .filter_map(|insn| insn.call_target)
+                    .filter_map(|target| symbols.get(&target).cloned())
+                    .collect::<BTreeSet<String>>()
+            });
+            if let Some(callees) = &callees {
+                next_level.extend(callees.iter().cloned());
+            }
+            graph.insert(name, callees);
+        }
+        level = next_level;
+    }
+
+    graph
+}
+
+/// Notes about the functions reached on both sides whose callees differ.
+pub fn call_graph_notes(orig: &CallGraph, compare: &CallGraph) -> Vec<String> {
+    let mut notes = Vec::new();
+
+    for (name, orig_callees) in orig {
+        let (orig_callees, compare_callees) = match (orig_callees, compare.get(name)) {
+            (Some(orig_callees), Some(Some(compare_callees))) => (orig_callees, compare_callees),
+            _ => continue,
+        };
+
+        let only_orig: Vec<&str> = orig_callees
+            .difference(compare_callees)
+            .map(String::as_str)
+            .collect();
+        let only_compare: Vec<&str> = compare_callees
+            .difference(orig_callees)
+            .map(String::as_str)
+            .collect();
+        if !only_orig.is_empty() {
+            notes.push(format!(
+                "{}: only the original calls {}, inlined in devilution?",
+                name,
+                only_orig.join(", ")
+            ));
+        }
+        if !only_compare.is_empty() {
+            notes.push(format!(
+                "{}: only devilution calls {}, inlined in the original?",
+                name,
+                only_compare.join(", ")
+            ));
+        }
+    }
+
+    notes
+}
@@ -0,0 +1,64 @@
+//! Detection of functions or calls whose calling conventions differ between both sides.
+//!
+//! A callee cleaning up its arguments (stdcall, `ret 0x8`) on one side and leaving that to the
+//! caller (cdecl, `add esp, 0x8` after the call) on the other side usually means the function
+//! is declared with the wrong calling convention in devilution.
+
+use super::diff::DiffOp;
+
+/// Notes about differing argument cleanups, orig/compare lines are numbered from 1.
+pub fn calling_convention_notes(orig: &[&str], compare: &[&str], ops: &[DiffOp]) -> Vec<String> {
+    let mut notes = Vec::new();
+
+    if let (Some(orig_ret), Some(compare_ret)) = (last_ret(orig), last_ret(compare)) {
+        if orig_ret != compare_ret && (orig_ret == "ret" || compare_ret == "ret") {
+            notes.push(format!(
+                "the original returns with `{}`, devilution with `{}`, the function is probably \
+                 declared with the wrong calling convention (stdcall/cdecl)",
+                orig_ret, compare_ret
+            ));
+        }
+    }
+
+    for op in ops {
+        if let DiffOp::Equal(i, j) = *op {
+            if !orig[i].starts_with("call ") {
+                continue;
+            }
+
+            let orig_cleanup = orig.get(i + 1).filter(|line| is_stack_cleanup(line));
+            let compare_cleanup = compare.get(j + 1).filter(|line| is_stack_cleanup(line));
+            match (orig_cleanup, compare_cleanup) {
+                (Some(cleanup), None) => notes.push(format!(
+                    "only the original cleans up the stack after `{}` (orig line {}, `{}`), the \
+                     callee is probably declared with the wrong calling convention",
+                    orig[i],
+                    i + 1,
+                    cleanup
+                )),
+                (None, Some(cleanup)) => notes.push(format!(
+                    "only devilution cleans up the stack after `{}` (compare line {}, `{}`), the \
+                     callee is probably declared with the wrong calling convention",
+                    compare[j],
+                    j + 1,
+                    cleanup
+                )),
+                _ => {}
+            }
+        }
+    }
+
+    notes
+}
+
+fn last_ret<'a>(lines: &[&'a str]) -> Option<&'a str> {
+    lines
+        .iter()
+        .rev()
+        .find(|line| line.starts_with("ret"))
+        .cloned()
+}
+
+fn is_stack_cleanup(line: &str) -> bool {
+    line.starts_with("add esp, ")
+}
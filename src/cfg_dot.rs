@@ -0,0 +1,51 @@
+//! Writing the control flow graph of a function in the GraphViz DOT format.
+
+use std::io::{Error, Write};
+
+use super::disasm::*;
+
+/// Writes one node per basic block, labeled with its instructions, and one edge per jump and
+/// fall through. Jumps leaving the function are left out.
+pub fn write_cfg_dot(
+    writer: &mut impl Write,
+    name: &str,
+    insns: &[Instruction],
+) -> Result<(), Error> {
+    let mut starts = vec![0];
+    starts.extend(block_starts(insns));
+    let blocks: Vec<&[Instruction]> = starts
+        .iter()
+        .enumerate()
+        .map(|(k, &start)| &insns[start..starts.get(k + 1).cloned().unwrap_or(insns.len())])
+        .filter(|block| !block.is_empty())
+        .collect();
+    let block_at = |addr: u64| blocks.iter().position(|block| block[0].start == addr);
+
+    writeln!(writer, "digraph \"{}\" {{", escape(name))?;
+    writeln!(writer, "    node [shape=box, fontname=monospace];")?;
+    for (k, block) in blocks.iter().enumerate() {
+        let label: String = block
+            .iter()
+            .map(|insn| format!("{}\\l", escape(&insn.text)))
+            .collect();
+        writeln!(writer, "    b{} [label=\"{}\"];", k, label)?;
+    }
+
+    for (k, block) in blocks.iter().enumerate() {
+        let last = &block[block.len() - 1];
+        let mnemonic = last.text.split(' ').next().unwrap_or("");
+        if let Some(target) = last.branch_target.and_then(block_at) {
+            writeln!(writer, "    b{} -> b{};", k, target)?;
+        }
+        let falls_through = mnemonic != "jmp" && !mnemonic.starts_with("ret");
+        if falls_through && k + 1 < blocks.len() {
+            writeln!(writer, "    b{} -> b{} [style=dashed];", k, k + 1)?;
+        }
+    }
+
+    writeln!(writer, "}}")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
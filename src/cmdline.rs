@@ -1,42 +1,199 @@
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use std::ffi::{OsStr, OsString};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use super::{Command, CompareCommandInfo, CompareOpts, DisasmOpts, GenerateFullCommandInfo};
+use regex::Regex;
+
+use super::compare::FileFormat;
+use super::compare_all::{OutputFormat, SortOrder};
+use super::comparer_config::{parse_number, AddressExpr, ComparerConfig, Profile};
+use super::highlight::RegisterHighlight;
+use super::prepare::PrepareOpts;
+use super::{
+    AddrFormat, Command, CompareAllCommandInfo, CompareBuildsCommandInfo, CompareCommandInfo,
+    CompareGoldenCommandInfo, CompareNasmCommandInfo, CompareOpts, CompareStdinCommandInfo,
+    CompareSymbolsCommandInfo, DisasmOpts, ExtractCommandInfo, GenerateFullCommandInfo, LineEnding,
+};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+const DIABLO_FILE_HELP: &str = "Path to the original Diablo.exe to use. It may be gzip compressed \
+                                 (.gz) or a zip archive containing only the binary.";
 
 pub fn parse_cmdline() -> Command {
     let diablo_file = Arg::with_name("DIABLO_FILE")
-        .help("Path to the original Diablo.exe to use")
-        .required(true);
+        .help(DIABLO_FILE_HELP)
+        .required_unless("print-config-schema");
 
     let devilution_file = Arg::with_name("DEVILUTION_FILE")
         .help(
             "Sets the debug binary file to use. \
              The respective .pdb file needs to exist in the same folder as well. \
-             Currently for files generated by VC6 only.",
+             Currently for files generated by VC6 only. `auto` uses the newest binary_name \
+             found in the build_dir of comparer-config.toml.",
         )
-        .required(true);
+        .required_unless("print-config-schema");
 
     let debug_symbol = Arg::with_name("DEBUG_SYMBOL")
         .help(
             "Function name/debug symbol to compare. This has to be defined for the original \
              binary in the comparer-config.toml. Is the size attribute missing, devilution-comparer \
-             will use the size of the devilution function for the original binary as well.",
-        ).required(true);
+             will use the size of the devilution function for the original binary as well. \
+             Defaults to default_symbol from comparer-config.toml if that is set.",
+        );
 
     let watch = Arg::with_name("watch").short("w").long("watch").help(
         "Enable watching for changes to the PDB file, updating the output files \
          on change.",
     );
 
+    let notify = Arg::with_name("notify")
+        .long("notify")
+        .requires("watch")
+        .help(
+            "Ring the terminal bell when a rebuild makes the function fully match or lowers \
+             the match percentage.",
+        );
+
     let show_ip = Arg::with_name("show-ip")
         .short("i")
         .long("show-ip")
         .help("Shows leading addresses in the output.")
         .global(true);
 
+    let show_blocks = Arg::with_name("show-blocks")
+        .long("show-blocks")
+        .help(
+            "Insert `; ----` separators at the start of basic blocks, i.e. after jumps and \
+             rets and before jump targets.",
+        )
+        .global(true);
+
+    let annotate_xrefs = Arg::with_name("annotate-xrefs")
+        .long("annotate-xrefs")
+        .help(
+            "Insert `; xref from 0x401abc` comments before jump targets, listing the jumps \
+             within the function leading there.",
+        )
+        .global(true);
+
+    let show_encoding_size = Arg::with_name("show-encoding-size")
+        .long("show-encoding-size")
+        .help("Shows the byte length of every instruction in front of it.")
+        .global(true);
+
+    let no_aliases = Arg::with_name("no-aliases")
+        .long("no-aliases")
+        .help(
+            "Print mnemonics with several aliases in one canonical form (`je` as `jz`, `sal` as \
+             `shl`), also in golden files.",
+        )
+        .global(true);
+
+    let show_padding = Arg::with_name("show-padding")
+        .long("show-padding")
+        .help(
+            "Write a `; --- padding ---` header before the int3/nop instructions following the \
+             final ret, i.e. the part of the PDB size that is padding.",
+        )
+        .global(true);
+
+    let show_access = Arg::with_name("show-access")
+        .long("show-access")
+        .help(
+            "Append whether the operands are read or written, e.g. `mov eax, dword ptr \
+             [ebp-0x04] ; eax=W, dword ptr [ebp-0x04]=R`. Conditional accesses are put in \
+             parentheses.",
+        )
+        .global(true);
+
+    let annotate_chars = Arg::with_name("annotate-immediates-as-chars")
+        .long("annotate-immediates-as-chars")
+        .help(
+            "Append immediates consisting of printable ASCII characters as a string, e.g. \
+             `mov eax, 0x6C6C6548 ; \"Hell\"`, to recognize inlined string constants.",
+        )
+        .global(true);
+
+    let align_tabs = Arg::with_name("align-tabs")
+        .long("align-tabs")
+        .help(
+            "Separate the mnemonics and operands with a tab instead of a space, so the operands \
+             line up.",
+        )
+        .global(true);
+
+    let resolve_globals = Arg::with_name("resolve-globals")
+        .long("resolve-globals")
+        .help(
+            "Replace addresses of global and static variables in the devilution binary with \
+             their names from the pdb file. The original binary has no such names.",
+        )
+        .global(true);
+
+    let profile = Arg::with_name("profile")
+        .long("profile")
+        .takes_value(true)
+        .value_name("NAME")
+        .help(
+            "Apply the flags of [profiles.NAME] in comparer-config.toml. Flags given here are \
+             added to them, values given here replace them. --no-FLAG turns a flag of the \
+             profile off, --mem-disp, --imms and --aliases undo --no-mem-disp, --no-imms and \
+             --no-aliases.",
+        )
+        .global(true);
+
+    let cvdump_timeout = Arg::with_name("cvdump-timeout")
+        .long("cvdump-timeout")
+        .takes_value(true)
+        .value_name("SECONDS")
+        .help(
+            "Kill CvDump if reading the PDB takes longer than this, instead of waiting forever on \
+             a corrupt PDB.",
+        )
+        .validator(is_valid_number)
+        .global(true);
+
+    let wine_path = Arg::with_name("wine-path")
+        .long("wine-path")
+        .takes_value(true)
+        .value_name("WINE")
+        .help(
+            "The wine executable used to run cvdump.exe on other systems than Windows. \
+             Defaults to `wine` from the PATH.",
+        )
+        .global(true);
+
+    let skipdata = Arg::with_name("skipdata")
+        .long("skipdata")
+        .help(
+            "Write bytes that can't be decoded as `db 0xXX` and continue with the next byte, \
+             instead of ending the disassembly there. Keeps both sides aligned past data in code.",
+        )
+        .global(true);
+
+    let addr_format = Arg::with_name("addr-format")
+        .long("addr-format")
+        .takes_value(true)
+        .value_name("FORMAT")
+        .help(
+            "printf style format of the addresses shown with --show-ip, e.g. `0x%08x`. \
+             Defaults to `%X`.",
+        )
+        .validator(is_valid_addr_format)
+        .global(true);
+
+    let line_ending = Arg::with_name("line-ending")
+        .long("line-ending")
+        .takes_value(true)
+        .possible_values(&["lf", "crlf", "native"])
+        .default_value("native")
+        .help(
+            "Line ending used in the written files. `native`, the default, uses CRLF on Windows \
+             and LF elsewhere.",
+        )
+        .global(true);
+
     let no_mem_disp = Arg::with_name("no-mem-disp")
         .long("no-mem-disp")
         .help(
@@ -49,6 +206,25 @@ pub fn parse_cmdline() -> Command {
         .help("Hides all immediate values. Use with caution.")
         .global(true);
 
+    let resolve_symbols = Arg::with_name("resolve-symbols")
+        .long("resolve-symbols")
+        .help(
+            "Print call targets and immediate operands that are known function addresses as the \
+             function name. The original binary uses the config, the compared binary the pdb file.",
+        )
+        .global(true);
+
+    let strip_symbols_regex = Arg::with_name("strip-symbols-regex")
+        .long("strip-symbols-regex")
+        .takes_value(true)
+        .value_name("REGEX")
+        .help(
+            "Remove all matches of REGEX from the names printed by --resolve-symbols, e.g. \
+             `<.*>` to drop template arguments.",
+        )
+        .validator(is_valid_regex)
+        .global(true);
+
     let truncate_to_original = Arg::with_name("truncate-to-original")
         .long("truncate-to-original")
         .help(
@@ -57,6 +233,342 @@ pub fn parse_cmdline() -> Command {
         )
         .global(true);
 
+    let truncate_to_devilution = Arg::with_name("truncate-to-devilution")
+        .long("truncate-to-devilution")
+        .help(
+            "Disassemble the original binary with the length of the devilution function in the \
+             pdb file instead of the size defined in the config.",
+        )
+        .conflicts_with("truncate-to-original");
+
+    let strict_size = Arg::with_name("strict-size").long("strict-size").help(
+        "Exit with an error if the size defined in the config differs from the size in the pdb \
+         file, instead of comparing functions of different lengths.",
+    );
+
+    let warn_on_indirect_calls = Arg::with_name("warn-on-indirect-calls")
+        .long("warn-on-indirect-calls")
+        .help(
+            "List the calls through registers or memory of both functions and warn if their \
+             numbers differ. These are usually calls of virtual functions.",
+        );
+
+    let warn_float_ops = Arg::with_name("warn-float-ops")
+        .long("warn-float-ops")
+        .help(
+            "Print the number of x87 stack pushes and pops of both functions if they differ, \
+             and warn if the stack is balanced differently.",
+        );
+
+    let no_tail_truncate_warning = Arg::with_name("no-tail-truncate-warning")
+        .long("no-tail-truncate-warning")
+        .help("Don't print a note when a truncate option changed the number of compared bytes.");
+
+    let reverse = Arg::with_name("reverse").long("reverse").help(
+        "Swap the output files, writing the devilution disassembly into orig.asm and the \
+         original one into compare.asm. Useful if you prefer devilution on the left side of a diff.",
+    );
+
+    let stats_only = Arg::with_name("stats-only")
+        .long("stats-only")
+        .help("Only print the comparison statistics, without writing any of the --format files.");
+
+    let format = Arg::with_name("format")
+        .long("format")
+        .takes_value(true)
+        .value_name("FORMATS")
+        .use_delimiter(true)
+        .possible_values(&["asm", "json"])
+        .default_value("asm")
+        .help(
+            "Files written from the comparison, several can be given like `asm,json`. `asm` \
+             writes orig.asm and compare.asm, `json` writes compare.json with the match \
+             statistics and the instructions of both sides.",
+        );
+
+    let operands_only = Arg::with_name("include-operands-only")
+        .long("include-operands-only")
+        .help(
+            "Print the instructions that have matching mnemonics but differing operands, \
+             leaving out everything else.",
+        );
+
+    let pager = Arg::with_name("pager").long("pager").help(
+        "Show the printed instructions in $PAGER, or less if it isn't set, when stdout is a \
+         terminal.",
+    );
+
+    let highlight_registers = Arg::with_name("highlight-registers")
+        .long("highlight-registers")
+        .takes_value(true)
+        .value_name("REGS")
+        .use_delimiter(true)
+        .help(
+            "Color the given registers, like esi,edi, in the printed instructions when stdout is \
+             a terminal.",
+        );
+
+    let side_by_side = Arg::with_name("side-by-side").long("side-by-side").help(
+        "Additionally write side_by_side.asm, showing both disassemblies next to each other \
+         with a summary at the bottom.",
+    );
+
+    let no_trailing_ret_padding = Arg::with_name("no-trailing-ret-padding")
+        .long("no-trailing-ret-padding")
+        .help(
+            "Leave out int3/nop padding after the final ret when comparing, so functions \
+             only differing in their alignment padding are reported as matching.",
+        )
+        .global(true);
+
+    let no_interactive = Arg::with_name("no-interactive")
+        .long("no-interactive")
+        .help(
+            "Don't ask to choose from similar symbols if DEBUG_SYMBOL isn't found in the config.",
+        );
+
+    let cfg_dot = Arg::with_name("cfg-dot").long("cfg-dot").help(
+        "Also write the control flow graphs of both functions as orig.dot and compare.dot for \
+         GraphViz, with one node per basic block.",
+    );
+
+    let dump_bytes = Arg::with_name("dump-bytes")
+        .long("dump-bytes")
+        .help("Also write the raw bytes that get disassembled into orig.bin and compare.bin.");
+
+    let insns_json = Arg::with_name("insns-json")
+        .long("insns-json")
+        .takes_value(true)
+        .value_name("FILE")
+        .help(
+            "Also write every instruction of both functions as JSON: the address, size and text, \
+             the registers read and written including the implicit ones, and the groups jump, \
+             call, ret and interrupt.",
+        );
+
+    let dump_relocations = Arg::with_name("dump-relocations")
+        .long("dump-relocations")
+        .help(
+            "List the base relocations within both functions with their types and targets. The \
+             relocated operands hold absolute addresses, which are expected to differ.",
+        );
+
+    let verify_roundtrip = Arg::with_name("verify-roundtrip")
+        .long("verify-roundtrip")
+        .help(
+            "Reassemble every instruction of both functions with nasm and list the ones whose \
+             bytes differ, to catch disassembly text that is ambiguous. Instructions with hidden \
+             operands like indirect calls are skipped.",
+        );
+
+    let nasm = Arg::with_name("nasm")
+        .long("nasm")
+        .takes_value(true)
+        .value_name("NASM")
+        .default_value("nasm")
+        .help("The nasm executable used by --verify-roundtrip, from the PATH by default.");
+
+    let allow_reorder = Arg::with_name("allow-reorder").long("allow-reorder").help(
+        "Experimental: treat up to 4 adjacent independent instructions as matching if they are \
+         a permutation of the original ones. This is a heuristic based on a simple dependency \
+         check of the operands. The instructions are written in their true order, only the diff \
+         pairs them.",
+    )
+    .global(true);
+
+    let jumps_by_index = Arg::with_name("jumps-by-index")
+        .long("jumps-by-index")
+        .help(
+            "Write jumps within the function as the number of instructions to their target, e.g. \
+         `jnz @-5`, instead of the relative address. Equivalent control flow then matches even \
+         if the code in between differs in size.",
+        )
+        .global(true);
+
+    let jumps_as_labels = Arg::with_name("relative-jumps-as-labels")
+        .long("relative-jumps-as-labels")
+        .conflicts_with("jumps-by-index")
+        .help(
+            "Write jumps within the function as jumps to generated labels, e.g. `jnz loc_2`, \
+             with a `loc_2:` line before the target. The labels are numbered in address order.",
+        )
+        .global(true);
+
+    let structural = Arg::with_name("structural").long("structural").help(
+        "Also compare the control flow structure: the number of basic blocks and branches and \
+         the called functions by name, printing a structural match percentage. Useful if the \
+         instructions differ too much to compare them line by line.",
+    );
+
+    let ignore_case = Arg::with_name("ignore-case").long("ignore-case").help(
+        "Find DEBUG_SYMBOL in comparer-config.toml ignoring case. Several functions matching \
+         this way are handled like an unknown symbol.",
+    );
+
+    let checksum = Arg::with_name("checksum").long("checksum").help(
+        "Print a hash of the compared lines of both sides. It only changes if the disassembly \
+         does, which lets scripts detect changes without diffing the files.",
+    );
+
+    let call_graph = Arg::with_name("call-graph")
+        .long("call-graph")
+        .takes_value(true)
+        .value_name("DEPTH")
+        .help(
+            "Follow the calls of both functions DEPTH levels deep and print the functions whose \
+             callees differ, e.g. because of inlining. Callees are matched by name, only \
+             functions of comparer-config.toml are followed.",
+        )
+        .validator(is_valid_number);
+
+    let split_at_ret = Arg::with_name("split-at-ret").long("split-at-ret").help(
+        "Also compare the parts of the function ending with a `ret` separately and print their \
+         match percentages, for PDB symbols covering several small functions.",
+    );
+
+    let first_diff = Arg::with_name("first-diff").long("first-diff").help(
+        "Only print the address and text of the first differing instruction of both sides, \
+         without writing the output files.",
+    );
+
+    let context_bytes = Arg::with_name("context-bytes")
+        .long("context-bytes")
+        .takes_value(true)
+        .value_name("BYTES")
+        .requires("first-diff")
+        .help(
+            "Also print the instructions starting at most BYTES bytes before or after the first \
+             difference of both sides next to each other. Replaces --first-diff-context, which \
+             counted instructions.",
+        )
+        .validator(is_valid_number);
+
+    let match_markers = Arg::with_name("match-markers").long("match-markers").help(
+        "Prefix every line of the .asm files with `=` if it matches, `~` if only its \
+             operands differ and `!` otherwise.",
+    );
+
+    let prologue = Arg::with_name("prologue")
+        .long("prologue")
+        .takes_value(true)
+        .value_name("N")
+        .help(
+            "Only compare and write the first N instructions of both functions. Together with \
+             --epilogue the instructions in between are left out.",
+        )
+        .validator(is_valid_number);
+
+    let epilogue = Arg::with_name("epilogue")
+        .long("epilogue")
+        .takes_value(true)
+        .value_name("N")
+        .help("Only compare and write the last N instructions of both functions.")
+        .validator(is_valid_number);
+
+    let binary_identical = Arg::with_name("binary-identical")
+        .long("binary-identical")
+        .help(
+            "Compare the raw bytes first and only report `identical` without disassembling \
+             if they match. The output files of a previous run are left untouched.",
+        );
+
+    let write_identical = Arg::with_name("write-identical")
+        .long("write-identical")
+        .requires("binary-identical")
+        .help("Still disassemble and write the output files if the bytes are identical.");
+
+    let combined = Arg::with_name("combined").long("combined").help(
+        "Write both disassemblies into a single compare.asm instead of orig.asm and \
+         compare.asm. The original function comes first, unless --reverse is given.",
+    );
+
+    let collapse_intrinsics = Arg::with_name("collapse-intrinsics")
+        .long("collapse-intrinsics")
+        .help(
+            "Treat a changed block as matching if one side contains an inlined memcpy/memset \
+             (rep movs/rep stos) and the other side calls the function instead. The calls are \
+             only recognized together with --resolve-symbols.",
+        );
+
+    let at_rva = Arg::with_name("at-rva")
+        .long("at-rva")
+        .takes_value(true)
+        .value_name("ADDRESS")
+        .conflicts_with("DEBUG_SYMBOL")
+        .help(
+            "Compare the devilution function containing this address, e.g. 0x401ABC or \
+             SymbolName+0x10, instead of specifying DEBUG_SYMBOL. The function still has to be \
+             defined in the config.",
+        )
+        .validator(is_valid_address_expr);
+
+    let size = Arg::with_name("size")
+        .long("size")
+        .takes_value(true)
+        .value_name("SIZE")
+        .help("Number of bytes to compare instead of the function size in the PDB.")
+        .validator(is_valid_number);
+
+    let emit_offsets_only = Arg::with_name("emit-offsets-only")
+        .long("emit-offsets-only")
+        .help(
+            "Only print the address and size of the devilution function as \
+             `offset=0x401abc size=0x120`, or as a JSON object with --format json, without \
+             disassembling or writing any files.",
+        );
+
+    let offset_search = Arg::with_name("interactive-offset-search")
+        .long("interactive-offset-search")
+        .takes_value(true)
+        .value_name("RANGE")
+        .validator(is_valid_number)
+        .help(
+            "Compare the original function at every address up to RANGE bytes before and after \
+             the configured one first, and print the best matching addresses. One of them can \
+             be selected for the comparison when running in a terminal.",
+        );
+
+    let max_diff_lines = Arg::with_name("max-diff-lines")
+        .long("max-diff-lines")
+        .takes_value(true)
+        .value_name("N")
+        .help(
+            "Give up on functions with more than N differing lines, they most likely have a \
+             wrong symbol or address. compare-all skips them with a warning.",
+        )
+        .validator(is_valid_number)
+        .global(true);
+
+    let name_by_symbol = Arg::with_name("name-by-symbol")
+        .long("name-by-symbol")
+        .help(
+            "Append the symbol name to the written files, e.g. orig_DrawPanel.asm and \
+         compare_DrawPanel.asm, so comparing another function doesn't overwrite them.",
+        );
+
+    let normalizer = Arg::with_name("normalizer")
+        .long("normalizer")
+        .takes_value(true)
+        .value_name("CMD")
+        .help(
+            "Pipe the disassembled lines of both functions through this shell command before \
+             comparing, e.g. `sed s/ebx/REG/`. It has to print exactly one line per input line.",
+        )
+        .global(true);
+
+    let metrics_file = Arg::with_name("metrics-file")
+        .long("metrics-file")
+        .takes_value(true)
+        .value_name("PATH")
+        .help(
+            "After each comparison, replace PATH with the match percentage and line counts in the \
+             Prometheus text format, e.g. for the node exporter's textfile collector.",
+        );
+
+    let print_config_schema = Arg::with_name("print-config-schema")
+        .long("print-config-schema")
+        .help("Print the expected structure of comparer-config.toml and exit.");
+
     let app = App::new("devilution-comparer")
         .setting(AppSettings::SubcommandsNegateReqs)
         .version(VERSION)
@@ -73,10 +585,75 @@ pub fn parse_cmdline() -> Command {
         .arg(devilution_file)
         .arg(debug_symbol)
         .arg(watch)
+        .arg(notify)
         .arg(show_ip)
+        .arg(show_blocks)
+        .arg(annotate_xrefs)
+        .arg(skipdata)
+        .arg(wine_path)
+        .arg(cvdump_timeout)
+        .arg(show_encoding_size)
+        .arg(no_aliases)
+        .arg(show_padding)
+        .arg(show_access)
+        .arg(annotate_chars)
+        .arg(align_tabs)
+        .arg(resolve_globals)
+        .arg(profile)
+        .args(&profile_flag_overrides())
+        .arg(addr_format)
+        .arg(line_ending)
         .arg(no_mem_disp)
         .arg(no_imms)
+        .arg(resolve_symbols)
+        .arg(strip_symbols_regex)
         .arg(truncate_to_original)
+        .arg(truncate_to_devilution)
+        .arg(no_tail_truncate_warning)
+        .arg(strict_size)
+        .arg(warn_on_indirect_calls)
+        .arg(warn_float_ops)
+        .arg(reverse)
+        .arg(stats_only)
+        .arg(format)
+        .arg(operands_only)
+        .arg(pager.clone())
+        .arg(highlight_registers.clone())
+        .arg(side_by_side)
+        .arg(no_trailing_ret_padding)
+        .arg(no_interactive)
+        .arg(dump_bytes)
+        .arg(insns_json)
+        .arg(dump_relocations)
+        .arg(verify_roundtrip)
+        .arg(nasm)
+        .arg(cfg_dot)
+        .arg(allow_reorder)
+        .arg(jumps_by_index)
+        .arg(jumps_as_labels)
+        .arg(structural)
+        .arg(ignore_case)
+        .arg(match_markers)
+        .arg(checksum)
+        .arg(split_at_ret)
+        .arg(call_graph)
+        .arg(first_diff)
+        .arg(context_bytes)
+        .arg(prologue)
+        .arg(epilogue)
+        .arg(binary_identical)
+        .arg(write_identical)
+        .arg(combined)
+        .arg(collapse_intrinsics)
+        .arg(at_rva)
+        .arg(size)
+        .arg(emit_offsets_only)
+        .arg(offset_search)
+        .arg(max_diff_lines)
+        .arg(name_by_symbol)
+        .arg(normalizer)
+        .arg(metrics_file)
+        .arg(print_config_schema)
         .subcommand(SubCommand::with_name("generate-full")
             .about("Generates a disassembly file with all functions defined in comparer-config.toml.")
             .arg(
@@ -91,18 +668,338 @@ pub fn parse_cmdline() -> Command {
                     .help(
                         "Generate the file for the original binary for all functions defined within \
                          comparer-config.toml, skipping functions without defined sizes.")
+            )
+            .arg(
+                Arg::with_name("functions-csv")
+                    .long("functions-csv")
+                    .takes_value(true)
+                    .value_name("CSV_FILE")
+                    .help(
+                        "Read the function definitions from a CSV file instead of comparer-config.toml. \
+                         The header row has to contain the columns name and addr, size and \
+                         devilution symbol are optional.")
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("max-lines-per-function")
+                    .long("max-lines-per-function")
+                    .takes_value(true)
+                    .value_name("N")
+                    .help(
+                        "Only write the first N instructions of every function, followed by a \
+                         `; ... truncated ...` line.")
+                    .validator(is_valid_number)
+            )
+            .arg(
+                Arg::with_name("include-done")
+                    .long("include-done")
+                    .help("Also write the functions marked with `done = true` in comparer-config.toml.")
+            )
+            .arg(
+                Arg::with_name("no-function-thunks")
+                    .long("no-function-thunks")
+                    .help(
+                        "Leave out compiler generated thunks and adjustors, i.e. functions \
+                         only consisting of a jump or with a thunk name.")
+            )
+            .arg(
+                Arg::with_name("compress")
+                    .long("compress")
+                    .help("Write the output gzip compressed, as orig_full.asm.gz/compare_full.asm.gz.")
+            )
+            .arg(
+                Arg::with_name("binary-identical")
+                    .long("binary-identical")
+                    .takes_value(true)
+                    .value_name("OTHER_FILE")
+                    .help(
+                        "Write only `; identical (0x120 bytes)` instead of the disassembly of the \
+                         functions with the same bytes in OTHER_FILE. That's the debug binary \
+                         with --orig-file, the original binary otherwise. Pass it to both runs \
+                         so the lines match.")
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("metadata")
+                    .long("metadata")
+                    .help(
+                        "Add the address and size of the original function and, without \
+                         --orig-file, of the devilution function to the comment above each \
+                         function.")
+            ))
+        .subcommand(SubCommand::with_name("compare-builds")
+            .about(
+                "Compares a function between two builds of the devilution binary, exiting with \
+                 an error if they are not byte-identical. Useful to check for reproducible builds.")
+            .arg(
+                Arg::with_name("FIRST_FILE")
+                    .required(true)
+                    .help("The first debug binary, the .pdb file has to exist next to it.")
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("SECOND_FILE")
+                    .required(true)
+                    .help("The second debug binary, the .pdb file has to exist next to it.")
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("DEBUG_SYMBOL")
+                    .required(true)
+                    .help("Function name/debug symbol to compare.")
+            ))
+        .subcommand(SubCommand::with_name("compare-golden")
+            .about(
+                "Compares a function of the devilution binary against a previously written \
+                 disassembly, e.g. a committed golden/DrawPanel.asm, exiting with an error on \
+                 differences. Use the same output flags as for writing the golden file.")
+            .arg(
+                Arg::with_name("DEVILUTION_FILE")
+                    .required(true)
+                    .help("The debug binary, the .pdb file has to exist next to it.")
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("DEBUG_SYMBOL")
+                    .required(true)
+                    .help("Function name/debug symbol to compare.")
+            )
+            .arg(
+                Arg::with_name("GOLDEN_FILE")
+                    .required(true)
+                    .help("The disassembly file to compare against.")
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("patch")
+                    .long("patch")
+                    .help(
+                        "On differences, write compare.patch, a unified diff that updates the \
+                         golden file to the current disassembly when applied with `patch -p0`.")
+            ))
+        .subcommand(SubCommand::with_name("compare-nasm")
+            .about(
+                "Assembles hand written NASM assembly at the address of a function of the \
+                 devilution binary and compares both, exiting with an error on differences. \
+                 `bits 32` and `org` are set by the comparer, the file must not set an origin.")
+            .arg(
+                Arg::with_name("DEVILUTION_FILE")
+                    .required(true)
+                    .help("The debug binary, the .pdb file has to exist next to it.")
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("DEBUG_SYMBOL")
+                    .required(true)
+                    .help("Function name/debug symbol to compare.")
+            )
+            .arg(
+                Arg::with_name("NASM_FILE")
+                    .required(true)
+                    .help("The assembly file to compare against.")
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("nasm")
+                    .long("nasm")
+                    .takes_value(true)
+                    .value_name("NASM")
+                    .default_value("nasm")
+                    .help("The nasm executable, from the PATH by default.")
+            ))
+        .subcommand(SubCommand::with_name("compare-symbols")
+            .about(
+                "Compares two functions of the devilution binary, e.g. to find duplicated code. \
+                 The second function is disassembled at the address of the first one.")
+            .arg(
+                Arg::with_name("DEVILUTION_FILE")
+                    .required(true)
+                    .help("The debug binary, the .pdb file has to exist next to it.")
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("FIRST_SYMBOL")
+                    .required(true)
+                    .help("Function name/debug symbol shown on the left.")
+            )
+            .arg(
+                Arg::with_name("SECOND_SYMBOL")
+                    .required(true)
+                    .help("Function name/debug symbol shown on the right.")
+            ))
+        .subcommand(SubCommand::with_name("extract")
+            .about(
+                "Writes the bytes of a function of the devilution binary to a file, and its \
+                 offset, size and base address to a .json file next to it.")
+            .arg(
+                Arg::with_name("DEVILUTION_FILE")
+                    .required(true)
+                    .help("The debug binary, the .pdb file has to exist next to it.")
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("DEBUG_SYMBOL")
+                    .required(true)
+                    .help("Function name/debug symbol to extract.")
+            )
+            .arg(
+                Arg::with_name("out")
+                    .long("out")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(true)
+                    .help("Where to write the bytes.")
+            ))
+        .subcommand(SubCommand::with_name("compare-stdin")
+            .about(
+                "Disassembles the bytes read from stdin and prints them to stdout, using the \
+                 same formatting as the comparisons.")
+            .arg(
+                Arg::with_name("base")
+                    .long("base")
+                    .takes_value(true)
+                    .value_name("ADDRESS")
+                    .default_value("0x401000")
+                    .help("Address of the first byte.")
+                    .validator(is_valid_number)
+            )
+            .arg(pager)
+            .arg(highlight_registers))
+        .subcommand(SubCommand::with_name("selftest")
+            .about(
+                "Checks the installation: the config file, cvdump.exe, the PDB parsing and the \
+                 disassembler, printing PASS or FAIL for each stage."))
+        .subcommand(SubCommand::with_name("compare-all")
+            .alias("compare-all-matching")
+            .about(
+                "Compares all functions of the PDB that are also defined in comparer-config.toml \
+                 and prints their match percentages.")
+            .arg(
+                Arg::with_name("DIABLO_FILE")
+                    .required(true)
+                    .help(DIABLO_FILE_HELP)
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("DEVILUTION_FILE")
+                    .required(true)
+                    .help("The debug binary, the .pdb file has to exist next to it.")
+                    .validator_os(file_exists)
+            )
+            .arg(
+                Arg::with_name("PATTERN")
+                    .help("Only compare the functions whose name matches this regular expression.")
+                    .validator(is_valid_regex)
+            )
+            .arg(
+                Arg::with_name("watch-all-symbols")
+                    .long("watch-all-symbols")
+                    .help(
+                        "Watch the PDB file for changes, comparing all functions again after \
+                         each rebuild and printing the ones whose match percentage changed.")
+            )
+            .arg(
+                Arg::with_name("summary-json")
+                    .long("summary-json")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .help(
+                        "Also write the results as JSON: the number of functions, how many \
+                         match fully, the average match percentage and per function the name, \
+                         match percentage and size delta.")
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .possible_values(&["text", "tracker"])
+                    .default_value("text")
+                    .help(
+                        "`tracker` prints a JSON array for the decompilation progress tracker \
+                         instead, with the symbol, match percentage and original size of every \
+                         function. Warnings and errors go to stderr then.")
+            )
+            .arg(
+                Arg::with_name("sort")
+                    .long("sort")
+                    .takes_value(true)
+                    .value_name("ORDER")
+                    .possible_values(&["worst-first", "best-first", "name", "size"])
+                    .help(
+                        "Order of the printed functions and of the --summary-json file, by match \
+                         percentage, name or devilution function size. The order of the config \
+                         by default.")
+            )
+            .arg(
+                Arg::with_name("count-only")
+                    .long("count-only")
+                    .help(
+                        "Only print the summary line with the number of matching functions and \
+                         the average match percentage.")
+            )
+            .arg(
+                Arg::with_name("check")
+                    .long("check")
+                    .conflicts_with("watch-all-symbols")
+                    .help(
+                        "Exit with an error if a function matches less than the `min_match` \
+                         percentage given in comparer-config.toml. A function marked as `done` \
+                         without a `min_match` has to match 100%, the threshold comes from the \
+                         config, not from earlier results. Functions with neither aren't \
+                         checked.")
             ));
 
     let matches = &app.get_matches();
 
+    if let Some(wine_path) = global_value_of_os(matches, "wine-path") {
+        super::pdb::set_wine_path(wine_path.into());
+    }
+    if let Some(timeout) = global_value_of_os(matches, "cvdump-timeout") {
+        let seconds = parse_number(&timeout.to_string_lossy()).unwrap();
+        super::pdb::set_cvdump_timeout(Duration::from_secs(seconds));
+    }
+
+    let profile = global_value_of_os(matches, "profile")
+        .map(|name| read_profile(&name.to_string_lossy()))
+        .unwrap_or_default();
+    let profile = &profile;
+
     if let Some(matches) = matches.subcommand_matches("generate-full") {
-        Command::GenerateFull(parse_generate_full_args(&matches))
+        Command::GenerateFull(parse_generate_full_args(matches, profile))
+    } else if let Some(matches) = matches.subcommand_matches("compare-all") {
+        Command::CompareAll(parse_compare_all_args(matches, profile))
+    } else if let Some(matches) = matches.subcommand_matches("compare-builds") {
+        Command::CompareBuilds(parse_compare_builds_args(matches, profile))
+    } else if let Some(matches) = matches.subcommand_matches("compare-golden") {
+        Command::CompareGolden(parse_compare_golden_args(matches, profile))
+    } else if let Some(matches) = matches.subcommand_matches("compare-nasm") {
+        Command::CompareNasm(parse_compare_nasm_args(matches, profile))
+    } else if let Some(matches) = matches.subcommand_matches("compare-symbols") {
+        Command::CompareSymbols(parse_compare_symbols_args(matches, profile))
+    } else if let Some(matches) = matches.subcommand_matches("extract") {
+        Command::Extract(parse_extract_args(matches))
+    } else if let Some(matches) = matches.subcommand_matches("compare-stdin") {
+        Command::CompareStdin(parse_compare_stdin_args(matches, profile))
+    } else if matches.subcommand_matches("selftest").is_some() {
+        Command::Selftest
+    } else if matches.is_present("print-config-schema") {
+        Command::PrintConfigSchema
     } else {
-        Command::Compare(parse_compare_args(&matches))
+        Command::Compare(parse_compare_args(matches, profile))
     }
 }
 
-fn parse_compare_args(matches: &ArgMatches) -> CompareCommandInfo {
+/// Global args given after a subcommand only end up in its matches.
+fn global_value_of_os<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a OsStr> {
+    matches.value_of_os(name).or_else(|| {
+        matches
+            .subcommand()
+            .1
+            .and_then(|matches| matches.value_of_os(name))
+    })
+}
+
+fn parse_compare_args(matches: &ArgMatches, profile: &Profile) -> CompareCommandInfo {
     let compare_file_path: PathBuf = matches.value_of_os("DEVILUTION_FILE").unwrap().into();
     let compare_pdb_file = compare_file_path.with_extension("pdb");
 
@@ -111,32 +1008,340 @@ fn parse_compare_args(matches: &ArgMatches) -> CompareCommandInfo {
             orig: matches.value_of_os("DIABLO_FILE").unwrap().into(),
             compare_file_path,
             compare_pdb_file,
-            debug_symbol: matches.value_of("DEBUG_SYMBOL").unwrap().into(),
+            // resolved from --at-rva or the config's default_symbol otherwise
+            debug_symbol: matches.value_of("DEBUG_SYMBOL").unwrap_or_default().into(),
         },
-        disasm_opts: parse_disasm_opts(&matches),
+        disasm_opts: parse_disasm_opts(matches, profile),
         enable_watcher: matches.is_present("watch"),
         last_offset_size: None,
+        notify: matches.is_present("notify"),
+        last_match_percent: None,
         truncate_to_original: matches.is_present("truncate-to-original"),
+        truncate_to_devilution: matches.is_present("truncate-to-devilution"),
+        truncate_warning: !matches.is_present("no-tail-truncate-warning"),
+        strict_size: matches.is_present("strict-size"),
+        reverse: matches.is_present("reverse"),
+        stats_only: matches.is_present("stats-only"),
+        formats: matches
+            .values_of("format")
+            .unwrap()
+            .map(|format| FileFormat::parse(format).unwrap())
+            .collect(),
+        operands_only: matches.is_present("include-operands-only"),
+        pager: matches.is_present("pager"),
+        highlight: parse_highlight(matches),
+        side_by_side: matches.is_present("side-by-side"),
+        interactive: !matches.is_present("no-interactive"),
+        ignore_case: matches.is_present("ignore-case"),
+        dump_bytes: matches.is_present("dump-bytes"),
+        insns_json: matches.value_of_os("insns-json").map(PathBuf::from),
+        dump_relocations: matches.is_present("dump-relocations"),
+        verify_roundtrip: if matches.is_present("verify-roundtrip") {
+            matches.value_of_os("nasm").map(PathBuf::from)
+        } else {
+            None
+        },
+        cfg_dot: matches.is_present("cfg-dot"),
+        structural: matches.is_present("structural"),
+        call_graph: matches
+            .value_of("call-graph")
+            .map(|depth| parse_number(depth).unwrap() as usize),
+        warn_on_indirect_calls: matches.is_present("warn-on-indirect-calls"),
+        warn_float_ops: matches.is_present("warn-float-ops"),
+        match_markers: matches.is_present("match-markers"),
+        checksum: matches.is_present("checksum"),
+        split_at_ret: matches.is_present("split-at-ret"),
+        first_diff: matches.is_present("first-diff"),
+        context_bytes: matches
+            .value_of("context-bytes")
+            .map_or(0, |n| parse_number(n).unwrap()),
+        binary_identical: matches.is_present("binary-identical"),
+        write_identical: matches.is_present("write-identical"),
+        combined: matches.is_present("combined"),
+        collapse_intrinsics: matches.is_present("collapse-intrinsics"),
+        at_rva: matches
+            .value_of("at-rva")
+            .map(|rva| AddressExpr::parse(rva).unwrap()),
+        size: matches
+            .value_of("size")
+            .map(|size| parse_number(size).unwrap() as usize),
+        emit_offsets_only: matches.is_present("emit-offsets-only"),
+        offset_search: matches
+            .value_of("interactive-offset-search")
+            .map(|range| parse_number(range).unwrap()),
+        max_diff_lines: parse_max_diff_lines(matches),
+        name_by_symbol: matches.is_present("name-by-symbol"),
+        prepare_opts: parse_prepare_opts(matches),
+        metrics_file: matches.value_of_os("metrics-file").map(PathBuf::from),
     }
 }
 
-fn parse_generate_full_args(matches: &ArgMatches) -> GenerateFullCommandInfo {
+fn parse_generate_full_args(matches: &ArgMatches, profile: &Profile) -> GenerateFullCommandInfo {
     GenerateFullCommandInfo {
         file_path: matches.value_of_os("FILE").unwrap().into(),
         orig_file: matches.is_present("orig-file"),
-        disasm_opts: parse_disasm_opts(&matches),
+        functions_csv: matches.value_of_os("functions-csv").map(PathBuf::from),
+        max_lines_per_function: matches
+            .value_of("max-lines-per-function")
+            .map(|max| parse_number(max).unwrap() as usize),
+        include_done: matches.is_present("include-done"),
+        metadata: matches.is_present("metadata"),
+        skip_thunks: matches.is_present("no-function-thunks"),
+        compress: matches.is_present("compress"),
+        binary_identical: matches.value_of_os("binary-identical").map(PathBuf::from),
+        disasm_opts: parse_disasm_opts(matches, profile),
         truncate_to_original: matches.is_present("truncate-to-original"),
     }
 }
 
-fn parse_disasm_opts(matches: &ArgMatches) -> DisasmOpts {
+fn parse_compare_builds_args(matches: &ArgMatches, profile: &Profile) -> CompareBuildsCommandInfo {
+    CompareBuildsCommandInfo {
+        first_file: matches.value_of_os("FIRST_FILE").unwrap().into(),
+        second_file: matches.value_of_os("SECOND_FILE").unwrap().into(),
+        debug_symbol: matches.value_of("DEBUG_SYMBOL").unwrap().into(),
+        disasm_opts: parse_disasm_opts(matches, profile),
+    }
+}
+
+fn parse_compare_golden_args(matches: &ArgMatches, profile: &Profile) -> CompareGoldenCommandInfo {
+    CompareGoldenCommandInfo {
+        compare_file_path: matches.value_of_os("DEVILUTION_FILE").unwrap().into(),
+        debug_symbol: matches.value_of("DEBUG_SYMBOL").unwrap().into(),
+        golden_file: matches.value_of_os("GOLDEN_FILE").unwrap().into(),
+        disasm_opts: parse_disasm_opts(matches, profile),
+        write_patch: matches.is_present("patch"),
+    }
+}
+
+fn parse_compare_nasm_args(matches: &ArgMatches, profile: &Profile) -> CompareNasmCommandInfo {
+    CompareNasmCommandInfo {
+        compare_file_path: matches.value_of_os("DEVILUTION_FILE").unwrap().into(),
+        debug_symbol: matches.value_of("DEBUG_SYMBOL").unwrap().into(),
+        nasm_file: matches.value_of_os("NASM_FILE").unwrap().into(),
+        nasm: matches.value_of_os("nasm").unwrap().into(),
+        disasm_opts: parse_disasm_opts(matches, profile),
+    }
+}
+
+fn parse_compare_symbols_args(
+    matches: &ArgMatches,
+    profile: &Profile,
+) -> CompareSymbolsCommandInfo {
+    CompareSymbolsCommandInfo {
+        compare_file_path: matches.value_of_os("DEVILUTION_FILE").unwrap().into(),
+        first_symbol: matches.value_of("FIRST_SYMBOL").unwrap().into(),
+        second_symbol: matches.value_of("SECOND_SYMBOL").unwrap().into(),
+        disasm_opts: parse_disasm_opts(matches, profile),
+    }
+}
+
+fn parse_extract_args(matches: &ArgMatches) -> ExtractCommandInfo {
+    ExtractCommandInfo {
+        compare_file_path: matches.value_of_os("DEVILUTION_FILE").unwrap().into(),
+        debug_symbol: matches.value_of("DEBUG_SYMBOL").unwrap().into(),
+        out: matches.value_of_os("out").unwrap().into(),
+    }
+}
+
+fn parse_compare_stdin_args(matches: &ArgMatches, profile: &Profile) -> CompareStdinCommandInfo {
+    CompareStdinCommandInfo {
+        base: parse_number(matches.value_of("base").unwrap()).unwrap(),
+        disasm_opts: parse_disasm_opts(matches, profile),
+        pager: matches.is_present("pager"),
+        highlight: parse_highlight(matches),
+    }
+}
+
+fn parse_highlight(matches: &ArgMatches) -> RegisterHighlight {
+    let registers: Vec<&str> = matches
+        .values_of("highlight-registers")
+        .map(|values| values.collect())
+        .unwrap_or_default();
+    RegisterHighlight::new(&registers)
+}
+
+fn parse_compare_all_args(matches: &ArgMatches, profile: &Profile) -> CompareAllCommandInfo {
+    CompareAllCommandInfo {
+        orig: matches.value_of_os("DIABLO_FILE").unwrap().into(),
+        compare_file_path: matches.value_of_os("DEVILUTION_FILE").unwrap().into(),
+        pattern: matches
+            .value_of("PATTERN")
+            .map(|pattern| Regex::new(pattern).unwrap()),
+        disasm_opts: parse_disasm_opts(matches, profile),
+        truncate_to_original: matches.is_present("truncate-to-original"),
+        watch: matches.is_present("watch-all-symbols"),
+        max_diff_lines: parse_max_diff_lines(matches),
+        prepare_opts: PrepareOpts {
+            prologue: None,
+            epilogue: None,
+            ..parse_prepare_opts(matches)
+        },
+        summary_json: matches.value_of_os("summary-json").map(PathBuf::from),
+        count_only: matches.is_present("count-only"),
+        check: matches.is_present("check"),
+        sort: matches
+            .value_of("sort")
+            .map(|order| SortOrder::parse(order).unwrap()),
+        format: OutputFormat::parse(matches.value_of("format").unwrap()).unwrap(),
+    }
+}
+
+fn parse_prepare_opts(matches: &ArgMatches) -> PrepareOpts {
+    PrepareOpts {
+        jumps_by_index: matches.is_present("jumps-by-index"),
+        jumps_as_labels: matches.is_present("relative-jumps-as-labels"),
+        normalizer: matches.value_of("normalizer").map(String::from),
+        allow_reorder: matches.is_present("allow-reorder"),
+        prologue: matches
+            .value_of("prologue")
+            .map(|n| parse_number(n).unwrap() as usize),
+        epilogue: matches
+            .value_of("epilogue")
+            .map(|n| parse_number(n).unwrap() as usize),
+        ignore_trailing_padding: matches.is_present("no-trailing-ret-padding"),
+    }
+}
+
+fn parse_max_diff_lines(matches: &ArgMatches) -> Option<usize> {
+    matches
+        .value_of("max-diff-lines")
+        .map(|max| parse_number(max).unwrap() as usize)
+}
+
+fn parse_disasm_opts(matches: &ArgMatches, profile: &Profile) -> DisasmOpts {
+    // --line-ending has a default value, only a given one replaces the profile's
+    let line_ending = match &profile.line_ending {
+        Some(line_ending) if matches.occurrences_of("line-ending") == 0 => line_ending.as_str(),
+        _ => matches.value_of("line-ending").unwrap(),
+    };
+
+    let resolve_symbols = profile_flag(matches, "resolve-symbols", profile.resolve_symbols);
+    // checked after merging, the profile may give one and the command line the other
+    if !resolve_symbols
+        && (matches.is_present("strip-symbols-regex") || profile.strip_symbols_regex.is_some())
+    {
+        println!("Error: --strip-symbols-regex requires --resolve-symbols.");
+        std::process::exit(1);
+    }
+
     DisasmOpts {
-        print_adresses: matches.is_present("show-ip"),
-        show_mem_disp: !matches.is_present("no-mem-disp"),
-        show_imms: !matches.is_present("no-imms"),
+        print_adresses: profile_flag(matches, "show-ip", profile.show_ip),
+        show_mem_disp: !profile_flag(matches, "no-mem-disp", profile.no_mem_disp),
+        show_imms: !profile_flag(matches, "no-imms", profile.no_imms),
+        addr_format: matches
+            .value_of("addr-format")
+            .or(profile.addr_format.as_deref())
+            .map(|format| AddrFormat::parse(format).unwrap())
+            .unwrap_or_default(),
+        line_ending: LineEnding::parse(line_ending).unwrap(),
+        resolve_symbols,
+        strip_symbols: matches
+            .value_of("strip-symbols-regex")
+            .or(profile.strip_symbols_regex.as_deref())
+            .map(|pattern| Regex::new(pattern).unwrap()),
+        show_blocks: profile_flag(matches, "show-blocks", profile.show_blocks),
+        annotate_xrefs: profile_flag(matches, "annotate-xrefs", profile.annotate_xrefs),
+        skip_data: profile_flag(matches, "skipdata", profile.skipdata),
+        show_encoding_size: profile_flag(matches, "show-encoding-size", profile.show_encoding_size),
+        no_aliases: profile_flag(matches, "no-aliases", profile.no_aliases),
+        show_padding: profile_flag(matches, "show-padding", profile.show_padding),
+        show_access: profile_flag(matches, "show-access", profile.show_access),
+        annotate_chars: profile_flag(
+            matches,
+            "annotate-immediates-as-chars",
+            profile.annotate_immediates_as_chars,
+        ),
+        align_tabs: profile_flag(matches, "align-tabs", profile.align_tabs),
+        resolve_globals: profile_flag(matches, "resolve-globals", profile.resolve_globals),
     }
 }
 
+/// The flags a profile can set and the flags turning them off again.
+const PROFILE_FLAGS: &[(&str, &str)] = &[
+    ("show-ip", "no-show-ip"),
+    ("no-mem-disp", "mem-disp"),
+    ("no-imms", "imms"),
+    ("resolve-symbols", "no-resolve-symbols"),
+    ("show-blocks", "no-show-blocks"),
+    ("annotate-xrefs", "no-annotate-xrefs"),
+    ("skipdata", "no-skipdata"),
+    ("show-encoding-size", "no-show-encoding-size"),
+    ("no-aliases", "aliases"),
+    ("show-padding", "no-show-padding"),
+    ("show-access", "no-show-access"),
+    (
+        "annotate-immediates-as-chars",
+        "no-annotate-immediates-as-chars",
+    ),
+    ("align-tabs", "no-align-tabs"),
+    ("resolve-globals", "no-resolve-globals"),
+];
+
+/// Hidden flags turning off a flag of the profile, described in the --profile help.
+fn profile_flag_overrides<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    PROFILE_FLAGS
+        .iter()
+        .map(|&(flag, negated)| {
+            Arg::with_name(negated)
+                .long(negated)
+                .conflicts_with(flag)
+                .hidden(true)
+                .global(true)
+        })
+        .collect()
+}
+
+/// A flag given on the command line, turned off there or taken from the profile.
+fn profile_flag(matches: &ArgMatches, flag: &str, profile_value: bool) -> bool {
+    let negated = PROFILE_FLAGS
+        .iter()
+        .find(|&&(name, _)| name == flag)
+        .map(|&(_, negated)| negated)
+        .unwrap();
+    if matches.is_present(flag) {
+        true
+    } else if matches.is_present(negated) {
+        false
+    } else {
+        profile_value
+    }
+}
+
+/// Reads a profile from the config, exiting if it is missing or contains invalid values.
+fn read_profile(name: &str) -> Profile {
+    let profile = match ComparerConfig::read_default() {
+        Ok(mut cfg) => cfg.profiles.remove(name),
+        Err(e) => {
+            super::comparer_config::print_error(&e);
+            std::process::exit(1);
+        }
+    };
+    let profile = match profile {
+        Some(profile) => profile,
+        None => {
+            println!("Error: comparer-config.toml has no [profiles.{}].", name);
+            std::process::exit(1);
+        }
+    };
+
+    let invalid = |e: String| -> ! {
+        println!("Error in [profiles.{}]: {}", name, e);
+        std::process::exit(1);
+    };
+    if let Some(format) = &profile.addr_format {
+        AddrFormat::parse(format).unwrap_or_else(|e| invalid(e));
+    }
+    if let Some(line_ending) = &profile.line_ending {
+        LineEnding::parse(line_ending)
+            .unwrap_or_else(|| invalid(format!("invalid line ending '{}'", line_ending)));
+    }
+    if let Some(pattern) = &profile.strip_symbols_regex {
+        Regex::new(pattern).unwrap_or_else(|e| invalid(e.to_string()));
+    }
+
+    profile
+}
+
 // #[allow(unknown_lints)]
 // #[allow(needless_pass_by_value)] // clap returns an owned string
 // fn is_vaild_number(v: String) -> Result<(), String> {
@@ -153,6 +1358,25 @@ fn parse_disasm_opts(matches: &ArgMatches) -> DisasmOpts {
 //     }
 // }
 
+#[allow(clippy::needless_pass_by_value)] // clap returns an owned string
+fn is_valid_addr_format(v: String) -> Result<(), String> {
+    AddrFormat::parse(&v).map(|_| ())
+}
+
+#[allow(clippy::needless_pass_by_value)] // clap returns an owned string
+fn is_valid_number(v: String) -> Result<(), String> {
+    parse_number(&v).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn is_valid_address_expr(v: String) -> Result<(), String> {
+    AddressExpr::parse(&v).map(|_| ())
+}
+
+#[allow(clippy::needless_pass_by_value)] // clap returns an owned string
+fn is_valid_regex(v: String) -> Result<(), String> {
+    Regex::new(&v).map(|_| ()).map_err(|e| e.to_string())
+}
+
 fn file_exists(path: &OsStr) -> Result<(), OsString> {
     let p = Path::new(path);
     if p.exists() && p.is_file() {
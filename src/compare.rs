@@ -1,16 +1,48 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{BufWriter, Read, Seek, SeekFrom};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
+use atty::Stream;
 use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use serde_derive::Serialize;
 
 use self::CompareError::*;
+use super::alignment::{looks_misaligned, start_alignment};
+use super::build_flags::build_setting_notes;
+use super::call_graph::*;
+use super::callconv::calling_convention_notes;
+use super::cfg_dot::write_cfg_dot;
 use super::comparer_config::*;
+use super::compressed::*;
+use super::diff::*;
 use super::disasm::*;
+use super::highlight::RegisterHighlight;
+use super::intrinsics::*;
+use super::metrics::write_metrics_file;
+use super::output_lock::OutputLock;
+use super::pager::Pager;
 use super::pdb::*;
-use super::CustomUpperHexFormat;
+use super::pe::*;
+use super::prepare::*;
+use super::roundtrip::*;
+use super::segments::segment_stats;
+use super::size_hint::suggested_orig_size;
+use super::structure::*;
+use super::x87::StackOps;
+use super::{CustomUpperHexFormat, LineEndingWriter};
+
+const MAX_SYMBOL_CANDIDATES: usize = 10;
+/// Names up to this many typos away from the symbol are suggested before the other matches.
+const MAX_SYMBOL_TYPOS: usize = 2;
+const MAX_OFFSET_CANDIDATES: usize = 10;
+/// DEVILUTION_FILE value for looking up the binary in the config's build_dir.
+const AUTO_BINARY: &str = "auto";
+const FILE_LOCKED_ATTEMPTS: u64 = 3;
+const FILE_LOCKED_RETRY_DELAY_MS: u64 = 200;
 
 #[derive(Debug)]
 pub struct CompareCommandInfo {
@@ -18,7 +50,69 @@ pub struct CompareCommandInfo {
     pub disasm_opts: super::DisasmOpts,
     pub last_offset_size: Option<(u64, usize)>,
     pub enable_watcher: bool,
+    pub notify: bool,
+    pub last_match_percent: Option<f64>,
     pub truncate_to_original: bool,
+    pub truncate_to_devilution: bool,
+    pub truncate_warning: bool,
+    /// Fail instead of truncating if the config and PDB sizes differ.
+    pub strict_size: bool,
+    pub reverse: bool,
+    pub stats_only: bool,
+    /// Files written from the comparison, several at once.
+    pub formats: Vec<FileFormat>,
+    pub operands_only: bool,
+    /// Show the operand differences in the pager if stdout is a terminal.
+    pub pager: bool,
+    /// Registers colored in the operand differences.
+    pub highlight: RegisterHighlight,
+    pub side_by_side: bool,
+    pub interactive: bool,
+    /// Look up the symbol in the config ignoring case.
+    pub ignore_case: bool,
+    pub dump_bytes: bool,
+    /// Also write every instruction of both functions with its registers and groups as JSON.
+    pub insns_json: Option<PathBuf>,
+    /// List the base relocations within both functions.
+    pub dump_relocations: bool,
+    /// Reassemble both functions with this nasm executable and list the instructions that
+    /// come out differently.
+    pub verify_roundtrip: Option<PathBuf>,
+    /// Also write the control flow graphs as orig.dot and compare.dot.
+    pub cfg_dot: bool,
+    pub structural: bool,
+    /// Compare the functions called up to this many levels deep.
+    pub call_graph: Option<usize>,
+    /// List the indirect calls of both functions, warning if their numbers differ.
+    pub warn_on_indirect_calls: bool,
+    /// Warn if the x87 stack pushes and pops of both functions differ.
+    pub warn_float_ops: bool,
+    pub match_markers: bool,
+    pub checksum: bool,
+    /// Print the match percentages of the parts ending with a `ret`.
+    pub split_at_ret: bool,
+    /// Only print the first differing instruction instead of writing the output files.
+    pub first_diff: bool,
+    /// How many bytes before and after the first difference the printed instructions may start.
+    pub context_bytes: u64,
+    /// Skip the disassembly if the bytes of both functions are identical.
+    pub binary_identical: bool,
+    /// Still write the output files for identical functions.
+    pub write_identical: bool,
+    pub combined: bool,
+    pub collapse_intrinsics: bool,
+    /// Look up the PDB symbol containing this address instead of using `debug_symbol`.
+    pub at_rva: Option<AddressExpr>,
+    /// Number of bytes to compare instead of the PDB function size.
+    pub size: Option<usize>,
+    pub emit_offsets_only: bool,
+    /// Rank the original addresses up to this many bytes around the configured one first.
+    pub offset_search: Option<u64>,
+    pub max_diff_lines: Option<usize>,
+    pub name_by_symbol: bool,
+    pub prepare_opts: PrepareOpts,
+    /// Written in the Prometheus text format after each comparison.
+    pub metrics_file: Option<PathBuf>,
 }
 
 #[derive(Debug)]
@@ -29,6 +123,88 @@ pub struct CompareOpts {
     pub debug_symbol: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    /// orig.asm and compare.asm, or the combined file.
+    Asm,
+    /// compare.json with the match statistics and the instructions of both sides.
+    Json,
+}
+
+impl FileFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "asm" => Some(FileFormat::Asm),
+            "json" => Some(FileFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// The address and size printed by `--emit-offsets-only`.
+#[derive(Serialize)]
+struct Offsets {
+    offset: u64,
+    size: usize,
+}
+
+/// The comparison written by `--format json`.
+#[derive(Serialize)]
+struct CompareJson<'a> {
+    symbol: &'a str,
+    orig_addr: u64,
+    compare_addr: u64,
+    match_percent: f64,
+    matching_lines: usize,
+    differing_lines: usize,
+    orig: Vec<InsnJson<'a>>,
+    compare: Vec<InsnJson<'a>>,
+}
+
+/// The instructions of both functions written by `--insns-json`.
+#[derive(Serialize)]
+struct InsnsJson<'a> {
+    orig: Vec<InsnJson<'a>>,
+    compare: Vec<InsnJson<'a>>,
+}
+
+#[derive(Serialize)]
+struct InsnJson<'a> {
+    address: u64,
+    size: u64,
+    text: &'a str,
+    regs_read: &'a [&'static str],
+    regs_written: &'a [&'static str],
+    groups: &'a [&'static str],
+}
+
+impl<'a> InsnJson<'a> {
+    fn of_all(insns: &'a [Instruction]) -> Vec<Self> {
+        insns
+            .iter()
+            .map(|insn| InsnJson {
+                address: insn.start,
+                size: insn.ip - insn.start,
+                text: &insn.text,
+                regs_read: &insn.detail.regs_read,
+                regs_written: &insn.detail.regs_written,
+                groups: &insn.detail.groups,
+            })
+            .collect()
+    }
+}
+
+struct CompareResult {
+    addr: u64,
+    size: usize,
+    /// Number of bytes of the original function that were compared.
+    compared_bytes: usize,
+    /// `None` if the bytes were identical and `--binary-identical` skipped the disassembly.
+    stats: Option<DiffStats>,
+    /// Instructions with the same mnemonic that only differ in their operands.
+    operand_diffs: Vec<(Instruction, Instruction)>,
+}
+
 #[derive(Debug)]
 pub enum CompareError {
     PdbError(super::pdb::PdbError),
@@ -38,29 +214,108 @@ pub enum CompareError {
     DisasmError(super::disasm::DisasmError),
     NotifyError(notify::Error),
     RequiredFunctionSizeNotFoundError(String),
+    RvaNotFound(u64),
+    FunctionUnrelated(usize),
+    NormalizerError(super::normalizer::NormalizerError),
+    OutputLockError(super::output_lock::OutputLockError),
+    RoundtripError(super::roundtrip::RoundtripError),
+    MetricsError(std::io::Error),
+    JsonError(serde_json::Error),
+    NoSymbolGiven,
+    BuildBinaryNotFound,
+    /// Sizes in the config and the PDB.
+    SizeMismatch(usize, usize),
 }
 
 pub fn print_error(e: &CompareError) {
     match e {
-        PdbError(e) => println!("PDB file error: {:#?}", e),
+        PdbError(e) => super::pdb::print_error(e),
         ConfigSymbolNotFound => println!("Could not find the specified symbol in the config."),
         SymbolNotFound => println!("Could not find the symbol in the PDB, skipping the file."),
         IoError(e) => println!("IO error: {:#?}", e),
-        DisasmError(e) => println!("Zydis disassembly engine error: {:#?}", e),
+        DisasmError(e) => super::disasm::print_error(e),
         NotifyError(e) => println!("Watcher error: {:#?}", e),
         RequiredFunctionSizeNotFoundError(e) => println!(
             "No size defined for the original function '{}', but truncate_to_original was specified.",
             e
         ),
+        RvaNotFound(rva) => println!("No function in the PDB contains the address {:#X}.", rva),
+        NormalizerError(e) => super::normalizer::print_error(e),
+        OutputLockError(e) => super::output_lock::print_error(e),
+        RoundtripError(e) => super::roundtrip::print_error(e),
+        MetricsError(e) => println!("Error writing the metrics file: {:#?}", e),
+        JsonError(e) => println!("Error writing the JSON file: {}", e),
+        BuildBinaryNotFound => println!(
+            "Could not find binary_name with a .pdb file in the build_dir of \
+             comparer-config.toml, check that both are set."
+        ),
+        SizeMismatch(config_size, pdb_size) => println!(
+            "The size in comparer-config.toml ({:#X}) differs from the size in the PDB ({:#X}).",
+            config_size, pdb_size
+        ),
+        NoSymbolGiven => println!(
+            "No DEBUG_SYMBOL given and no default_symbol defined in comparer-config.toml."
+        ),
+        FunctionUnrelated(max) => println!(
+            "The function appears unrelated (>{} differing lines), check the symbol and its \
+             address in the config.",
+            max
+        ),
     }
 }
 
 pub fn run(mut info: CompareCommandInfo, cfg: &ComparerConfig) -> Result<(), CompareError> {
-    let orig_fn = cfg
-        .func
-        .iter()
-        .find(|s| s.name == info.compare_opts.debug_symbol)
-        .ok_or(ConfigSymbolNotFound)?;
+    if info.compare_opts.compare_file_path == Path::new(AUTO_BINARY) {
+        let path = match (&cfg.build_dir, &cfg.binary_name) {
+            (Some(dir), Some(name)) => newest_binary(dir, name).map_err(IoError)?,
+            _ => None,
+        }
+        .ok_or(BuildBinaryNotFound)?;
+        println!("Using {}.", path.to_string_lossy());
+        info.compare_opts.compare_pdb_file = path.with_extension("pdb");
+        info.compare_opts.compare_file_path = path;
+    }
+
+    if let Some(rva) = &info.at_rva {
+        let pdb = Pdb::new(&info.compare_opts.compare_pdb_file).map_err(PdbError)?;
+        let rva = rva
+            .resolve(|name| {
+                pdb.parse_pdb()
+                    .find(|symbol| symbol.name == name)
+                    .map(|symbol| symbol.offset + PDB_SEGMENT_OFFSET)
+            })
+            .ok_or(SymbolNotFound)?;
+        let symbol = pdb
+            .parse_pdb()
+            .find(|symbol| {
+                let start = symbol.offset + PDB_SEGMENT_OFFSET;
+                start <= rva && rva < start + symbol.size as u64
+            })
+            .ok_or(RvaNotFound(rva))?;
+        println!("Address {:#X} is part of {}.", rva, symbol.name);
+        info.compare_opts.debug_symbol = symbol.name.into();
+    }
+
+    if info.compare_opts.debug_symbol.is_empty() {
+        info.compare_opts.debug_symbol = cfg.default_symbol.clone().ok_or(NoSymbolGiven)?;
+    }
+
+    if info.emit_offsets_only {
+        return print_offsets(&info);
+    }
+
+    let orig_fn = match find_config_symbol(cfg, &info.compare_opts.debug_symbol, info.ignore_case) {
+        Some(orig_fn) => {
+            info.compare_opts.debug_symbol = orig_fn.name.clone();
+            orig_fn
+        }
+        None if info.interactive && atty::is(Stream::Stdin) && atty::is(Stream::Stdout) => {
+            let orig_fn = pick_symbol(cfg, &info.compare_opts.debug_symbol)?;
+            info.compare_opts.debug_symbol = orig_fn.name.clone();
+            orig_fn
+        }
+        None => return Err(ConfigSymbolNotFound),
+    };
 
     if orig_fn.size == None {
         if info.truncate_to_original {
@@ -72,8 +327,20 @@ pub fn run(mut info: CompareCommandInfo, cfg: &ComparerConfig) -> Result<(), Com
         }
     }
 
+    let searched_fn;
+    let orig_fn = match info.offset_search {
+        Some(range) => match search_orig_offset(&mut info, cfg, orig_fn, range)? {
+            Some(func) => {
+                searched_fn = func;
+                &searched_fn
+            }
+            None => orig_fn,
+        },
+        None => orig_fn,
+    };
+
     // initial run
-    run_disassemble(&mut info, cfg.address_offset, orig_fn)?;
+    run_disassemble(&mut info, cfg, orig_fn)?;
 
     if !info.enable_watcher {
         return Ok(());
@@ -99,7 +366,7 @@ pub fn run(mut info: CompareCommandInfo, cfg: &ComparerConfig) -> Result<(), Com
     loop {
         match rx.recv() {
             Ok(DebouncedEvent::Create(_)) | Ok(DebouncedEvent::Write(_)) => {
-                if let Err(e) = run_disassemble(&mut info, cfg.address_offset, orig_fn) {
+                if let Err(e) = run_disassemble(&mut info, cfg, orig_fn) {
                     print_error(&e);
                 }
             }
@@ -112,13 +379,330 @@ pub fn run(mut info: CompareCommandInfo, cfg: &ComparerConfig) -> Result<(), Com
     }
 }
 
+/// Finds the most recently modified file called `name` in `dir` or its subdirectories that has
+/// a pdb file next to it.
+fn newest_binary(dir: &Path, name: &str) -> std::io::Result<Option<PathBuf>> {
+    let mut newest: Option<(SystemTime, PathBuf)> = None;
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.file_name() == Some(OsStr::new(name))
+                && path.with_extension("pdb").is_file()
+            {
+                let modified = path.metadata()?.modified()?;
+                match &newest {
+                    Some((time, _)) if *time >= modified => {}
+                    _ => newest = Some((modified, path)),
+                }
+            }
+        }
+    }
+    Ok(newest.map(|(_, path)| path))
+}
+
+/// Looks up the symbol in the config. With `ignore_case` an exact match is preferred, otherwise
+/// the symbol has to match a single function ignoring case.
+fn find_config_symbol<'a>(
+    cfg: &'a ComparerConfig,
+    symbol: &str,
+    ignore_case: bool,
+) -> Option<&'a FunctionDefinition> {
+    if let Some(func) = cfg.func.iter().find(|func| func.name == symbol) {
+        return Some(func);
+    }
+    if !ignore_case {
+        return None;
+    }
+
+    let candidates: Vec<&FunctionDefinition> = cfg
+        .func
+        .iter()
+        .filter(|func| func.name.eq_ignore_ascii_case(symbol))
+        .collect();
+    if candidates.len() > 1 {
+        println!(
+            "Note: {} matches {} functions ignoring case.",
+            symbol,
+            candidates.len()
+        );
+    }
+    if candidates.len() == 1 {
+        Some(candidates[0])
+    } else {
+        None
+    }
+}
+
+/// Lets the user choose from the config functions similar to the given symbol.
+fn pick_symbol<'a>(
+    cfg: &'a ComparerConfig,
+    symbol: &str,
+) -> Result<&'a FunctionDefinition, CompareError> {
+    let symbol = symbol.to_lowercase();
+    let mut ranked: Vec<((usize, usize), &FunctionDefinition)> = cfg
+        .func
+        .iter()
+        .filter_map(|func| symbol_rank(&func.name.to_lowercase(), &symbol).map(|rank| (rank, func)))
+        .collect();
+    ranked.sort_by_key(|&(rank, _)| rank);
+    let candidates: Vec<&FunctionDefinition> = ranked
+        .into_iter()
+        .map(|(_, func)| func)
+        .take(MAX_SYMBOL_CANDIDATES)
+        .collect();
+
+    if candidates.is_empty() {
+        return Err(ConfigSymbolNotFound);
+    }
+
+    println!("The symbol was not found in the config. Did you mean:");
+    for (i, func) in candidates.iter().enumerate() {
+        println!("  {}: {}", i + 1, func.name);
+    }
+    print!(
+        "Select a symbol [1-{}], anything else aborts: ",
+        candidates.len()
+    );
+    std::io::stdout().flush().map_err(IoError)?;
+
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).map_err(IoError)?;
+
+    input
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| i.checked_sub(1))
+        .and_then(|i| candidates.get(i))
+        .cloned()
+        .ok_or(ConfigSymbolNotFound)
+}
+
+/// Prints the original addresses up to `range` bytes around the configured one ranked by their
+/// match percentage. Returns the function at the selected address if one was picked.
+fn search_orig_offset(
+    info: &mut CompareCommandInfo,
+    cfg: &ComparerConfig,
+    orig_fn: &FunctionDefinition,
+    range: u64,
+) -> Result<Option<FunctionDefinition>, CompareError> {
+    let pdb = Pdb::new(&info.compare_opts.compare_pdb_file).map_err(PdbError)?;
+    let FunctionSymbol { offset, size, .. } = pdb
+        .parse_pdb()
+        .find(|symbol| symbol.name == info.compare_opts.debug_symbol)
+        .ok_or(SymbolNotFound)?;
+    let size = info.size.unwrap_or(size);
+    let orig_size = orig_fn.size.unwrap_or(size);
+
+    let mut compare_bytes = vec![0; size];
+    read_file_into(
+        &mut compare_bytes,
+        &info.compare_opts.compare_file_path,
+        offset + PDB_OFFSET_COMPARE_FILE,
+    )?;
+    mask_bytes(&mut compare_bytes, &orig_fn.mask);
+    let orig_bytes = read_binary(&info.compare_opts.orig).map_err(IoError)?;
+
+    let orig_symbols = function_symbols(&cfg.func);
+    let compare_addr = offset + PDB_SEGMENT_OFFSET;
+    let compare_insns = disasm(
+        &compare_bytes,
+        &mut info.disasm_opts,
+        compare_addr,
+        &orig_fn.mask,
+        &pdb.symbol_map(),
+    )
+    .map_err(DisasmError)?;
+
+    // nearest first, so the sort by percentage keeps them in front of equally good ones
+    let mut addrs: Vec<u64> =
+        (orig_fn.addr.saturating_sub(range)..=orig_fn.addr.saturating_add(range)).collect();
+    addrs.sort_by_key(|&addr| (addr as i64 - orig_fn.addr as i64).abs());
+
+    let mut candidates = Vec::new();
+    for addr in addrs {
+        let start = match addr.checked_sub(cfg.address_offset) {
+            Some(start) => start as usize,
+            None => continue,
+        };
+        let end = match start.checked_add(orig_size) {
+            Some(end) => end,
+            None => continue,
+        };
+        let mut bytes = match orig_bytes.get(start..end) {
+            Some(bytes) => bytes.to_vec(),
+            None => continue,
+        };
+        mask_bytes(&mut bytes, &orig_fn.mask);
+        let mut orig_insns = match disasm_until_error(
+            &bytes,
+            &mut info.disasm_opts,
+            addr,
+            &orig_fn.mask,
+            &orig_symbols,
+        )
+        .map_err(DisasmError)?
+        {
+            Disassembly {
+                insns,
+                decode_error: None,
+            } => insns,
+            // most addresses are in the middle of an instruction
+            _ => continue,
+        };
+        // scored like the comparison itself, so the best candidate is the best match there
+        let mut compare_insns = compare_insns.clone();
+        let prepared = prepare_insns(
+            &info.prepare_opts,
+            &cfg.canonicalize,
+            &mut orig_insns,
+            addr,
+            &mut compare_insns,
+            compare_addr,
+        )
+        .map_err(NormalizerError)?;
+        let orig_lines = diff_input(&orig_insns, prepared.orig_padding);
+        let compare_lines = diff_input(&compare_insns, prepared.compare_padding);
+        let (ops, _) = pair_reordered(
+            &info.prepare_opts,
+            diff_lines(&orig_lines, &compare_lines),
+            &orig_lines,
+            &compare_lines,
+        );
+        let stats = diff_stats(
+            &ops,
+            &orig_insns[..orig_lines.len()],
+            &compare_insns[..compare_lines.len()],
+        );
+        candidates.push((addr, stats.match_percent()));
+    }
+    candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    candidates.truncate(MAX_OFFSET_CANDIDATES);
+
+    println!("Original addresses ranked by their match percentage:");
+    for (i, &(addr, percent)) in candidates.iter().enumerate() {
+        let marker = if addr == orig_fn.addr {
+            " (config)"
+        } else {
+            ""
+        };
+        println!(
+            "  {}: {:#X} ({:+}) {:.2}%{}",
+            i + 1,
+            addr,
+            addr as i64 - orig_fn.addr as i64,
+            percent,
+            marker
+        );
+    }
+
+    if !(info.interactive && atty::is(Stream::Stdin) && atty::is(Stream::Stdout)) {
+        return Ok(None);
+    }
+    print!(
+        "Compare at an address [1-{}], anything else keeps the config address: ",
+        candidates.len()
+    );
+    std::io::stdout().flush().map_err(IoError)?;
+
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).map_err(IoError)?;
+
+    Ok(input
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| i.checked_sub(1))
+        .and_then(|i| candidates.get(i))
+        .map(|&(addr, _)| FunctionDefinition {
+            addr,
+            ..orig_fn.clone()
+        }))
+}
+
+/// Prints the address and size of the devilution function in a script friendly format.
+fn print_offsets(info: &CompareCommandInfo) -> Result<(), CompareError> {
+    let pdb = Pdb::new(&info.compare_opts.compare_pdb_file).map_err(PdbError)?;
+    let FunctionSymbol { offset, size, .. } = pdb
+        .parse_pdb()
+        .find(|symbol| symbol.name == info.compare_opts.debug_symbol)
+        .ok_or(SymbolNotFound)?;
+
+    let offsets = Offsets {
+        offset: offset + PDB_SEGMENT_OFFSET,
+        size: info.size.unwrap_or(size),
+    };
+    if info.formats.contains(&FileFormat::Json) {
+        println!("{}", serde_json::to_string(&offsets).map_err(JsonError)?);
+    } else {
+        println!("offset={:#x} size={:#x}", offsets.offset, offsets.size);
+    }
+    Ok(())
+}
+
+/// Sort key of `name` as a suggestion for the mistyped `symbol`, `None` if it isn't similar.
+/// Names with few typos come first by their edit distance, then the ones containing the
+/// characters of the symbol by their length difference.
+fn symbol_rank(name: &str, symbol: &str) -> Option<(usize, usize)> {
+    let typos = levenshtein(name, symbol);
+    if typos <= MAX_SYMBOL_TYPOS {
+        Some((0, typos))
+    } else if is_fuzzy_match(name, symbol) {
+        Some((
+            1,
+            name.len().max(symbol.len()) - name.len().min(symbol.len()),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Number of inserted, deleted or replaced characters turning `a` into `b`.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // distances of the processed prefix of `a` to each prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let replaced = diagonal + if ca == cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Whether all characters of `symbol` occur in `name` in the same order.
+fn is_fuzzy_match(name: &str, symbol: &str) -> bool {
+    let mut name_chars = name.chars();
+    symbol.chars().all(|c| name_chars.any(|n| n == c))
+}
+
 fn run_disassemble(
     info: &mut CompareCommandInfo,
-    orig_addr_offset: u64,
+    cfg: &ComparerConfig,
     orig_fn: &FunctionDefinition,
 ) -> Result<(), CompareError> {
-    match write_compare(info, orig_addr_offset, orig_fn) {
-        Ok((addr, size)) => {
+    let _lock = if info.stats_only || info.first_diff {
+        None
+    } else {
+        let curdir = std::env::current_dir().map_err(IoError)?;
+        Some(OutputLock::acquire(curdir).map_err(OutputLockError)?)
+    };
+
+    match write_compare(info, cfg, orig_fn) {
+        Ok(CompareResult {
+            addr,
+            size,
+            compared_bytes,
+            stats,
+            operand_diffs,
+        }) => {
             if let Some((old_addr, old_size)) = info.last_offset_size {
                 print!(
                     "Found {} at {:#X} ({:+#X}), size: {:#X} ({:+#X})",
@@ -141,6 +725,36 @@ fn run_disassemble(
                 println!();
             }
 
+            match stats {
+                Some(stats) => println!(
+                    "Match: {:.2}% ({} matching, {} differing lines; orig: {}, compare: {})",
+                    stats.match_percent(),
+                    stats.matching_lines,
+                    stats.differing_lines(),
+                    stats.orig_lines,
+                    stats.compare_lines,
+                ),
+                None => println!("Match: identical ({:#X} bytes)", compared_bytes),
+            }
+
+            let match_percent = stats.map_or(100.0, |stats| stats.match_percent());
+            if info.notify {
+                if let Some(old_percent) = info.last_match_percent {
+                    notify_match_change(old_percent, match_percent);
+                }
+            }
+            info.last_match_percent = Some(match_percent);
+
+            if let Some(path) = &info.metrics_file {
+                write_metrics_file(path, &info.compare_opts.debug_symbol, size, stats.as_ref())
+                    .map_err(MetricsError)?;
+            }
+
+            if info.operands_only && !operand_diffs.is_empty() {
+                print_operand_diffs(&operand_diffs, info.pager, &info.highlight)
+                    .map_err(IoError)?;
+            }
+
             info.last_offset_size = Some((addr, size));
             Ok(())
         }
@@ -148,90 +762,977 @@ fn run_disassemble(
     }
 }
 
+fn print_roundtrip(name: &str, nasm: &Path, bytes: &[u8], addr: u64) -> Result<(), CompareError> {
+    let Roundtrip {
+        checked,
+        skipped,
+        mismatches,
+    } = verify_roundtrip(nasm, bytes, addr).map_err(RoundtripError)?;
+    let rejected = mismatches
+        .iter()
+        .filter(|mismatch| mismatch.assembled.is_none())
+        .count();
+    println!(
+        "Roundtrip of the {} function: {} of {} reassembled instructions differ, {} rejected by \
+         nasm, {} skipped.",
+        name,
+        mismatches.len() - rejected,
+        checked,
+        rejected,
+        skipped.len()
+    );
+    for Mismatch {
+        insn,
+        bytes,
+        assembled,
+    } in &mismatches
+    {
+        match assembled {
+            Some(assembled) => println!(
+                "  {:X}: {} ; {} -> {}",
+                insn.start,
+                insn.text,
+                hex_bytes(bytes),
+                hex_bytes(assembled)
+            ),
+            None => println!(
+                "  {:X}: {} ; {}, rejected by nasm",
+                insn.start,
+                insn.text,
+                hex_bytes(bytes)
+            ),
+        }
+    }
+    for insn in &skipped {
+        println!(
+            "  {:X}: {} ; skipped, hidden operand",
+            insn.start, insn.text
+        );
+    }
+    Ok(())
+}
+
+fn print_operand_diffs(
+    operand_diffs: &[(Instruction, Instruction)],
+    pager: bool,
+    highlight: &RegisterHighlight,
+) -> std::io::Result<()> {
+    let mut pager = Pager::start(pager);
+    writeln!(pager, "Operand differences:")?;
+    for (orig, compare) in operand_diffs {
+        let (mnemonic, orig_operands) = split_mnemonic(&orig.text);
+        let (_, compare_operands) = split_mnemonic(&compare.text);
+        writeln!(
+            pager,
+            "  {:X}: {} {} | {}",
+            orig.ip,
+            mnemonic,
+            highlight.apply(orig_operands),
+            highlight.apply(compare_operands)
+        )?;
+    }
+    pager.finish()
+}
+
+fn print_structure(orig: &Structure, compare: &Structure) {
+    println!(
+        "Structure: {} blocks, {} branches, {} calls (orig) | {} blocks, {} branches, {} calls \
+         (compare)",
+        orig.blocks,
+        orig.branches,
+        orig.calls.len(),
+        compare.blocks,
+        compare.branches,
+        compare.calls.len()
+    );
+    if orig.calls != compare.calls {
+        println!("  orig calls:    {}", orig.calls.join(", "));
+        println!("  compare calls: {}", compare.calls.join(", "));
+    }
+    println!(
+        "Structural match: {:.2}%",
+        structural_similarity(orig, compare)
+    );
+}
+
+/// Prints the differences between the callees of both sides up to `depth` levels deep.
+fn print_call_graph(
+    info: &mut CompareCommandInfo,
+    cfg: &ComparerConfig,
+    pdb: &Pdb,
+    orig_insns: &[Instruction],
+    compare_insns: &[Instruction],
+    depth: usize,
+) -> Result<(), CompareError> {
+    let orig_bytes = read_binary(&info.compare_opts.orig).map_err(IoError)?;
+    let compare_bytes = std::fs::read(&info.compare_opts.compare_file_path).map_err(IoError)?;
+    let orig_symbols = function_symbols(&cfg.func);
+    // only functions of the config can be found in the original, leave out the others
+    let mut compare_symbols = pdb.symbol_map();
+    compare_symbols.retain(|_, name| cfg.func.iter().any(|func| func.name == *name));
+    let pdb_funcs: HashMap<&str, FunctionSymbol> =
+        pdb.parse_pdb().map(|func| (func.name, func)).collect();
+    // both sides use the same name for the compared function, so it matches up
+    let root = info.compare_opts.debug_symbol.clone();
+    let disasm_opts = &mut info.disasm_opts;
+
+    let orig_graph = call_graph(&root, depth, &orig_symbols, |name| {
+        if name == root {
+            return Some(orig_insns.to_vec());
+        }
+        let func = cfg.func.iter().find(|func| func.name == name)?;
+        let size = func
+            .size
+            .or_else(|| pdb_funcs.get(name).map(|pdb_func| pdb_func.size))?;
+        let offset = (func.addr - cfg.address_offset) as usize;
+        let bytes = orig_bytes.get(offset..offset + size)?;
+        disasm(bytes, disasm_opts, func.addr, &func.mask, &orig_symbols).ok()
+    });
+    let compare_graph = call_graph(&root, depth, &compare_symbols, |name| {
+        if name == root {
+            return Some(compare_insns.to_vec());
+        }
+        let pdb_func = pdb_funcs.get(name)?;
+        let offset = (pdb_func.offset + PDB_OFFSET_COMPARE_FILE) as usize;
+        let bytes = compare_bytes.get(offset..offset + pdb_func.size)?;
+        let addr = pdb_func.offset + PDB_SEGMENT_OFFSET;
+        disasm(bytes, disasm_opts, addr, &[], &compare_symbols).ok()
+    });
+
+    let notes = call_graph_notes(&orig_graph, &compare_graph);
+    if notes.is_empty() {
+        println!("Call graph (depth {}): the callees match.", depth);
+    } else {
+        println!("Call graph (depth {}):", depth);
+        for note in notes {
+            println!("  {}", note);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the base relocations of the binary `bytes` within the function at `addr`.
+fn print_relocations(side: &str, bytes: &[u8], addr: u64, size: usize) {
+    let relocations = match base_relocations(bytes) {
+        Some(relocations) => relocations,
+        None => {
+            println!(
+                "WARN: Could not read the relocations of the {} binary.",
+                side
+            );
+            return;
+        }
+    };
+
+    let end = addr + size as u64;
+    let relocations: Vec<&Relocation> = relocations
+        .iter()
+        .filter(|relocation| relocation.address >= addr && relocation.address < end)
+        .collect();
+    println!(
+        "Relocations in the {} function: {}",
+        side,
+        relocations.len()
+    );
+    for relocation in relocations {
+        let target = match relocation.target {
+            Some(target) => format!(" -> {:#X}", target),
+            None => String::new(),
+        };
+        println!(
+            "  {:#X} (+{:#X}) {}{}",
+            relocation.address,
+            relocation.address - addr,
+            relocation_kind_name(relocation.kind),
+            target
+        );
+    }
+}
+
+/// Calls through a register or memory, usually virtual functions or function pointers.
+fn indirect_calls(insns: &[Instruction]) -> Vec<&Instruction> {
+    insns
+        .iter()
+        .filter(|insn| insn.call_target.is_none() && insn.text.starts_with("call "))
+        .collect()
+}
+
+fn print_indirect_calls(orig_insns: &[Instruction], compare_insns: &[Instruction]) {
+    let orig_calls = indirect_calls(orig_insns);
+    let compare_calls = indirect_calls(compare_insns);
+    println!(
+        "Indirect calls: {} in the original, {} in devilution.",
+        orig_calls.len(),
+        compare_calls.len()
+    );
+    for (side, calls) in &[("orig", &orig_calls), ("compare", &compare_calls)] {
+        for insn in calls.iter() {
+            println!("  {} {:#X}: {}", side, insn.start, insn.text);
+        }
+    }
+    if orig_calls.len() != compare_calls.len() {
+        println!("WARN: The number of indirect calls differs, check the virtual functions.");
+    }
+}
+
+fn print_float_stack_ops(orig_insns: &[Instruction], compare_insns: &[Instruction]) {
+    let orig_ops = StackOps::of(orig_insns);
+    let compare_ops = StackOps::of(compare_insns);
+    if orig_ops == compare_ops {
+        return;
+    }
+
+    println!(
+        "x87 stack: the original pushes {} and pops {} (balance {:+}), devilution pushes {} and \
+         pops {} (balance {:+}).",
+        orig_ops.pushes,
+        orig_ops.pops,
+        orig_ops.balance(),
+        compare_ops.pushes,
+        compare_ops.pops,
+        compare_ops.balance()
+    );
+    if orig_ops.balance() != compare_ops.balance() {
+        println!("WARN: The x87 stack is balanced differently, a value may be left on it.");
+    }
+}
+
+/// Prints the match percentage of every part ending with a `ret`, lines are numbered from 1.
+fn print_segments(orig_lines: &[&str], compare_lines: &[&str]) {
+    let format = |range: &std::ops::Range<usize>| {
+        if range.start == range.end {
+            String::from("-")
+        } else {
+            format!("{}-{}", range.start + 1, range.end)
+        }
+    };
+
+    for (k, segment) in segment_stats(orig_lines, compare_lines).iter().enumerate() {
+        println!(
+            "Segment {}: orig lines {}, compare lines {}, match {:.2}%",
+            k + 1,
+            format(&segment.orig),
+            format(&segment.compare),
+            segment.stats.match_percent()
+        );
+    }
+}
+
+/// Prints the first differing instruction of both sides, or `-` for a side without one, and the
+/// rows around it side by side whose instructions start at most `context_bytes` away from it.
+fn print_first_diff(
+    ops: &[DiffOp],
+    orig_insns: &[Instruction],
+    compare_insns: &[Instruction],
+    disasm_opts: &DisasmOpts,
+    context_bytes: u64,
+) -> std::io::Result<()> {
+    let rows = side_by_side_rows(ops);
+    let first = match rows.iter().position(|row| !matches!(row, Row::Equal(..))) {
+        Some(first) => first,
+        None => {
+            println!("No differences.");
+            return Ok(());
+        }
+    };
+    let (orig, compare) = match rows[first] {
+        Row::Changed(i, j) => (Some(&orig_insns[i]), Some(&compare_insns[j])),
+        Row::LeftOnly(i) => (Some(&orig_insns[i]), None),
+        Row::RightOnly(j) => (None, Some(&compare_insns[j])),
+        Row::Equal(..) => unreachable!(),
+    };
+
+    let format = |insn: Option<&Instruction>| match insn {
+        Some(insn) => format!(
+            "{}: {}",
+            disasm_opts.addr_format.format(insn.start),
+            insn.text
+        ),
+        None => "-".into(),
+    };
+    println!("First difference:");
+    println!("  orig:    {}", format(orig));
+    println!("  compare: {}", format(compare));
+
+    if context_bytes > 0 {
+        let near = |insn: Option<&Instruction>, first: Option<&Instruction>| match (insn, first) {
+            (Some(insn), Some(first)) => {
+                insn.start.max(first.start) - insn.start.min(first.start) <= context_bytes
+            }
+            _ => false,
+        };
+        let in_context = |row: &Row| {
+            let (i, j) = match *row {
+                Row::Equal(i, j) | Row::Changed(i, j) => (Some(i), Some(j)),
+                Row::LeftOnly(i) => (Some(i), None),
+                Row::RightOnly(j) => (None, Some(j)),
+            };
+            near(i.map(|i| &orig_insns[i]), orig) || near(j.map(|j| &compare_insns[j]), compare)
+        };
+        let mut start = first;
+        while start > 0 && in_context(&rows[start - 1]) {
+            start -= 1;
+        }
+        let mut end = first + 1;
+        while end < rows.len() && in_context(&rows[end]) {
+            end += 1;
+        }
+
+        let orig_lines: Vec<String> = orig_insns.iter().map(|insn| format(Some(insn))).collect();
+        let compare_lines: Vec<String> = compare_insns
+            .iter()
+            .map(|insn| format(Some(insn)))
+            .collect();
+        let orig_lines: Vec<&str> = orig_lines.iter().map(String::as_str).collect();
+        let compare_lines: Vec<&str> = compare_lines.iter().map(String::as_str).collect();
+        let window = &rows[start..end];
+
+        println!("Context (orig on the left):");
+        let stdout = std::io::stdout();
+        write_side_by_side(&mut stdout.lock(), window, &orig_lines, &compare_lines)?;
+    }
+
+    Ok(())
+}
+
+/// 64 bit FNV-1a hash of the lines, stable between runs and versions.
+fn checksum(lines: &[&str]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+    lines
+        .iter()
+        .flat_map(|line| line.bytes().chain(Some(b'\n')))
+        .fold(FNV_OFFSET_BASIS, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(FNV_PRIME)
+        })
+}
+
+/// Rings the terminal bell if the function started to match fully or regressed.
+fn notify_match_change(old_percent: f64, new_percent: f64) {
+    if new_percent >= 100.0 && old_percent < 100.0 {
+        println!("\x07The function matches fully now.");
+    } else if new_percent < old_percent {
+        println!(
+            "\x07The match regressed from {:.2}% to {:.2}%.",
+            old_percent, new_percent
+        );
+    }
+}
+
 fn write_compare(
     info: &mut CompareCommandInfo,
-    orig_addr_offset: u64,
+    cfg: &ComparerConfig,
     orig_fn: &FunctionDefinition,
-) -> Result<(u64, usize), CompareError> {
-    let pdb = Pdb::new(&info.compare_opts.compare_pdb_file).map_err(PdbError)?;
+) -> Result<CompareResult, CompareError> {
+    let pdb = if info.enable_watcher {
+        Pdb::new_with_retry(&info.compare_opts.compare_pdb_file)
+    } else {
+        Pdb::new(&info.compare_opts.compare_pdb_file)
+    }
+    .map_err(PdbError)?;
     let FunctionSymbol { offset, size, .. } = pdb
         .parse_pdb()
         .find(|symbol| symbol.name == info.compare_opts.debug_symbol)
         .ok_or(SymbolNotFound)?;
+    if info.strict_size {
+        match orig_fn.size {
+            Some(orig_size) if orig_size != size => return Err(SizeMismatch(orig_size, size)),
+            _ => {}
+        }
+    }
+    let size = info.size.unwrap_or(size);
 
-    let mut orig_function_bytes = if let Some(orig_size) = orig_fn.size {
-        vec![0; orig_size]
+    let orig_size = match orig_fn.size {
+        Some(orig_size) if !info.truncate_to_devilution => orig_size,
+        _ => size,
+    };
+    let compare_size = if info.truncate_to_original {
+        orig_fn.size.expect(
+            "orig size is None even though truncate_to_original is set. Initial check was wrong!",
+        )
     } else {
-        vec![0; size]
+        size
     };
 
-    let mut compare_function_bytes = if info.truncate_to_original {
-        vec![0; orig_fn.size.expect("orig size is None even though truncate_to_original is set. Initial check was wrong!")]
+    // explains in the output files why the compared lengths differ from the defined ones
+    let orig_note = match orig_fn.size {
+        Some(defined_size) if defined_size != orig_size => Some(truncation_note(
+            "original",
+            defined_size,
+            orig_size,
+            "devilution",
+        )),
+        _ => None,
+    };
+    let compare_note = if compare_size != size {
+        Some(truncation_note(
+            "devilution",
+            size,
+            compare_size,
+            "original",
+        ))
     } else {
-        vec![0; size]
+        None
     };
+    if info.truncate_warning {
+        for note in orig_note.iter().chain(&compare_note) {
+            println!("Note: {}.", note);
+        }
+    }
 
-    let orig_offset = orig_fn.addr - orig_addr_offset;
+    let mut orig_function_bytes = vec![0; orig_size];
+    let mut compare_function_bytes = vec![0; compare_size];
 
-    read_file_into(
+    let orig_offset = orig_fn.addr - cfg.address_offset;
+
+    let read = if info.enable_watcher {
+        read_file_into_with_retry
+    } else {
+        read_file_into
+    };
+    read(
         &mut orig_function_bytes,
         &info.compare_opts.orig,
         orig_offset,
     )?;
-    read_file_into(
+    read(
         &mut compare_function_bytes,
         &info.compare_opts.compare_file_path,
         offset + PDB_OFFSET_COMPARE_FILE,
     )?;
 
-    let curdir = std::env::current_dir().map_err(IoError)?;
-
-    let mut path = curdir.clone();
-    path.push("orig.asm");
-    File::create(path)
-        .map(BufWriter::new)
-        .map_err(IoError)
-        .and_then(|mut buf_writer| {
-            write_disasm(
-                &mut buf_writer,
-                &orig_function_bytes,
-                &mut info.disasm_opts,
-                orig_fn.addr,
-            )
-            .map_err(DisasmError)?;
+    mask_bytes(&mut orig_function_bytes, &orig_fn.mask);
+    mask_bytes(&mut compare_function_bytes, &orig_fn.mask);
 
-            Ok(())
-        })?;
+    if info.dump_bytes {
+        let curdir = std::env::current_dir().map_err(IoError)?;
+        std::fs::write(
+            curdir.join(output_file_name(info, "orig", "bin")),
+            &orig_function_bytes,
+        )
+        .map_err(IoError)?;
+        std::fs::write(
+            curdir.join(output_file_name(info, "compare", "bin")),
+            &compare_function_bytes,
+        )
+        .map_err(IoError)?;
+    }
 
     let addr = offset + PDB_SEGMENT_OFFSET;
 
-    let mut path = curdir;
-    path.push("compare.asm");
-    File::create(path)
-        .map(BufWriter::new)
-        .map_err(IoError)
-        .and_then(|mut buf_writer| {
-            write_disasm(
-                &mut buf_writer,
-                &compare_function_bytes,
-                &mut info.disasm_opts,
+    let orig_alignment = start_alignment(orig_fn.addr);
+    let compare_alignment = start_alignment(addr);
+    if orig_alignment != compare_alignment {
+        println!(
+            "Note: the original starts {} byte aligned ({:#X}), devilution {} byte aligned \
+             ({:#X}), check the function alignment settings.",
+            orig_alignment, orig_fn.addr, compare_alignment, addr
+        );
+    }
+
+    if info.dump_relocations {
+        let orig_bytes = read_binary(&info.compare_opts.orig).map_err(IoError)?;
+        print_relocations("original", &orig_bytes, orig_fn.addr, orig_size);
+        let compare_bytes = std::fs::read(&info.compare_opts.compare_file_path).map_err(IoError)?;
+        print_relocations("devilution", &compare_bytes, addr, compare_size);
+    }
+
+    if let Some(nasm) = &info.verify_roundtrip {
+        print_roundtrip("original", nasm, &orig_function_bytes, orig_fn.addr)?;
+        print_roundtrip("devilution", nasm, &compare_function_bytes, addr)?;
+    }
+
+    if info.binary_identical && orig_function_bytes == compare_function_bytes {
+        if !info.write_identical {
+            return Ok(CompareResult {
                 addr,
+                size,
+                compared_bytes: orig_function_bytes.len(),
+                stats: None,
+                operand_diffs: Vec::new(),
+            });
+        }
+        println!(
+            "Note: The bytes are identical ({:#X} bytes).",
+            orig_function_bytes.len()
+        );
+    }
+
+    let orig_symbols = function_symbols(&cfg.func);
+    let mut compare_symbols = pdb.symbol_map();
+    if info.disasm_opts.resolve_globals {
+        let mut headers = vec![0; HEADERS_SIZE];
+        read(&mut headers, &info.compare_opts.compare_file_path, 0)?;
+        match section_addresses(&headers) {
+            Some(addresses) => compare_symbols.extend(pdb.data_symbol_map(&addresses)),
+            None => println!("WARN: Could not read the sections of the devilution binary."),
+        }
+    }
+    let mut orig_insns = disasm_until_error(
+        &orig_function_bytes,
+        &mut info.disasm_opts,
+        orig_fn.addr,
+        &orig_fn.mask,
+        &orig_symbols,
+    )
+    .map_err(DisasmError)?
+    .into_insns("the original function");
+    if looks_misaligned(&orig_insns, orig_function_bytes.len()) {
+        println!("WARN: The original offset may be misaligned, try +1..+3.");
+    }
+    let mut compare_insns = disasm_until_error(
+        &compare_function_bytes,
+        &mut info.disasm_opts,
+        addr,
+        &orig_fn.mask,
+        &compare_symbols,
+    )
+    .map_err(DisasmError)?
+    .into_insns("the devilution function");
+
+    if let Some(path) = &info.insns_json {
+        let writer = BufWriter::new(File::create(path).map_err(IoError)?);
+        let insns = InsnsJson {
+            orig: InsnJson::of_all(&orig_insns),
+            compare: InsnJson::of_all(&compare_insns),
+        };
+        serde_json::to_writer_pretty(writer, &insns).map_err(JsonError)?;
+    }
+    if info.structural {
+        print_structure(
+            &Structure::of(&orig_insns, &orig_symbols),
+            &Structure::of(&compare_insns, &compare_symbols),
+        );
+    }
+    if info.cfg_dot {
+        let curdir = std::env::current_dir().map_err(IoError)?;
+        for (base, insns) in &[("orig", &orig_insns), ("compare", &compare_insns)] {
+            File::create(curdir.join(output_file_name(info, base, "dot")))
+                .map(BufWriter::new)
+                .and_then(|mut writer| {
+                    write_cfg_dot(&mut writer, &info.compare_opts.debug_symbol, insns)
+                })
+                .map_err(IoError)?;
+        }
+    }
+    if let Some(depth) = info.call_graph {
+        print_call_graph(info, cfg, &pdb, &orig_insns, &compare_insns, depth)?;
+    }
+    if info.warn_on_indirect_calls {
+        print_indirect_calls(&orig_insns, &compare_insns);
+    }
+    if info.warn_float_ops {
+        print_float_stack_ops(&orig_insns, &compare_insns);
+    }
+
+    let Prepared {
+        orig_padding,
+        compare_padding,
+    } = prepare_insns(
+        &info.prepare_opts,
+        &cfg.canonicalize,
+        &mut orig_insns,
+        orig_fn.addr,
+        &mut compare_insns,
+        addr,
+    )
+    .map_err(NormalizerError)?;
+    if orig_padding != compare_padding {
+        println!(
+            "Note: ignoring differing trailing padding (orig: {} instructions, compare: {}).",
+            orig_padding, compare_padding
+        );
+    }
+
+    let mut orig_lines = diff_input(&orig_insns, orig_padding);
+    let mut compare_lines = diff_input(&compare_insns, compare_padding);
+    if info.checksum {
+        println!(
+            "Checksum: orig {:016x}, compare {:016x}",
+            checksum(&orig_lines),
+            checksum(&compare_lines)
+        );
+    }
+
+    let mut ops = match info.max_diff_lines {
+        Some(max) => {
+            diff_lines_limited(&orig_lines, &compare_lines, max).ok_or(FunctionUnrelated(max))?
+        }
+        None => diff_lines(&orig_lines, &compare_lines),
+    };
+    if info.collapse_intrinsics {
+        let (collapsed_ops, collapsed) = collapse_intrinsics(&ops, &orig_lines, &compare_lines);
+        if !collapsed.is_empty() {
+            println!(
+                "Note: treating {} inlined memcpy/memset block(s) as matching the call.",
+                collapsed.len()
+            );
+            ops = collapsed_ops;
+            annotate_inlined(&mut orig_insns, &mut compare_insns, &collapsed);
+            orig_lines = diff_input(&orig_insns, orig_padding);
+            compare_lines = diff_input(&compare_insns, compare_padding);
+        }
+    }
+    let (ops, reordered) = pair_reordered(&info.prepare_opts, ops, &orig_lines, &compare_lines);
+    if reordered > 0 {
+        println!(
+            "Note: matched {} run(s) of reordered independent instructions.",
+            reordered
+        );
+    }
+    let stats = diff_stats(
+        &ops,
+        &orig_insns[..orig_lines.len()],
+        &compare_insns[..compare_lines.len()],
+    );
+    if info.first_diff {
+        print_first_diff(
+            &ops,
+            &orig_insns,
+            &compare_insns,
+            &info.disasm_opts,
+            info.context_bytes,
+        )
+        .map_err(IoError)?;
+        return Ok(CompareResult {
+            addr,
+            size,
+            compared_bytes: orig_function_bytes.len(),
+            stats: Some(stats),
+            operand_diffs: Vec::new(),
+        });
+    }
+    for note in calling_convention_notes(&orig_lines, &compare_lines, &ops)
+        .into_iter()
+        .chain(build_setting_notes(&orig_lines, &compare_lines))
+    {
+        println!("Note: {}.", note);
+    }
+    // the trimmed prologue/epilogue are missing from the instructions
+    if info.prepare_opts.untrimmed() && orig_fn.size == Some(orig_size) && compare_size == size {
+        if let Some(suggested_size) =
+            suggested_orig_size(&ops, &orig_insns, &compare_insns, orig_size, compare_size)
+        {
+            println!(
+                "Note: only the end differs, the original size may be {:#X}, not {:#X}.",
+                suggested_size, orig_size
+            );
+        }
+    }
+    if info.split_at_ret {
+        print_segments(&orig_lines, &compare_lines);
+    }
+
+    let operand_diffs = if info.operands_only {
+        changed_pairs(&ops)
+            .into_iter()
+            .filter(|&(i, j)| split_mnemonic(orig_lines[i]).0 == split_mnemonic(compare_lines[j]).0)
+            .map(|(i, j)| (orig_insns[i].clone(), compare_insns[j].clone()))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    if !info.stats_only {
+        // the ignored trailing padding has no marker
+        let (orig_markers, compare_markers) = if info.match_markers {
+            line_markers(&ops, &orig_lines, &compare_lines)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        // with --reverse the devilution side is written into orig.asm, so diff tools
+        // show it as the left/expected side
+        let (orig_file_name, compare_file_name) = if info.reverse {
+            (
+                output_file_name(info, "compare", "asm"),
+                output_file_name(info, "orig", "asm"),
             )
-            .map_err(DisasmError)?;
+        } else {
+            (
+                output_file_name(info, "orig", "asm"),
+                output_file_name(info, "compare", "asm"),
+            )
+        };
 
-            Ok(())
-        })?;
+        let curdir = std::env::current_dir().map_err(IoError)?;
+        if info.formats.contains(&FileFormat::Json) {
+            let json = CompareJson {
+                symbol: &orig_fn.name,
+                orig_addr: orig_fn.addr,
+                compare_addr: addr,
+                match_percent: stats.match_percent(),
+                matching_lines: stats.matching_lines,
+                differing_lines: stats.differing_lines(),
+                orig: InsnJson::of_all(&orig_insns),
+                compare: InsnJson::of_all(&compare_insns),
+            };
+            let content = serde_json::to_vec_pretty(&json).map_err(JsonError)?;
+            write_if_changed(
+                &curdir.join(output_file_name(info, "compare", "json")),
+                &content,
+            )
+            .map_err(IoError)?;
+        }
+        if info.formats.contains(&FileFormat::Asm) {
+            if info.combined {
+                let orig_section = Section {
+                    title: "original",
+                    insns: &orig_insns,
+                    markers: &orig_markers,
+                    note: orig_note.as_ref(),
+                };
+                let compare_section = Section {
+                    title: "devilution",
+                    insns: &compare_insns,
+                    markers: &compare_markers,
+                    note: compare_note.as_ref(),
+                };
+                let sections = if info.reverse {
+                    [compare_section, orig_section]
+                } else {
+                    [orig_section, compare_section]
+                };
+                write_combined_file(
+                    curdir.join(output_file_name(info, "compare", "asm")),
+                    &orig_fn.name,
+                    &sections,
+                    &info.disasm_opts,
+                )?;
+            } else {
+                write_asm_file(
+                    curdir.join(orig_file_name),
+                    &orig_insns,
+                    &orig_markers,
+                    orig_note.as_ref(),
+                    &info.disasm_opts,
+                )?;
+                write_asm_file(
+                    curdir.join(compare_file_name),
+                    &compare_insns,
+                    &compare_markers,
+                    compare_note.as_ref(),
+                    &info.disasm_opts,
+                )?;
+            }
+        }
+
+        if info.side_by_side {
+            let rows = side_by_side_rows(&ops);
+            let (rows, left, right): (Vec<Row>, _, _) = if info.reverse {
+                (
+                    rows.into_iter().map(Row::mirrored).collect(),
+                    &compare_lines,
+                    &orig_lines,
+                )
+            } else {
+                (rows, &orig_lines, &compare_lines)
+            };
 
-    Ok((addr, size))
+            let mut content = Vec::new();
+            let mut writer = LineEndingWriter::new(&mut content, info.disasm_opts.line_ending);
+            write_side_by_side(&mut writer, &rows, left, right)
+                .and_then(|_| {
+                    write_summary_footer(
+                        &mut writer,
+                        &stats,
+                        orig_function_bytes.len(),
+                        compare_function_bytes.len(),
+                    )
+                })
+                .and_then(|_| {
+                    write_if_changed(
+                        &curdir.join(output_file_name(info, "side_by_side", "asm")),
+                        &content,
+                    )
+                })
+                .map_err(IoError)?;
+        }
+    }
+
+    Ok(CompareResult {
+        addr,
+        size,
+        compared_bytes: orig_function_bytes.len(),
+        stats: Some(stats),
+        operand_diffs,
+    })
 }
 
-fn read_file_into(
-    buffer: &mut [u8],
+fn write_summary_footer(
+    writer: &mut impl Write,
+    stats: &DiffStats,
+    orig_size: usize,
+    compare_size: usize,
+) -> std::io::Result<()> {
+    writeln!(writer, "\n;")?;
+    writeln!(
+        writer,
+        "; total lines: {} (orig: {}, compare: {})",
+        stats.orig_lines + stats.compare_lines,
+        stats.orig_lines,
+        stats.compare_lines
+    )?;
+    writeln!(writer, "; matching lines: {}", stats.matching_lines)?;
+    writeln!(writer, "; differing lines: {}", stats.differing_lines())?;
+    writeln!(writer, "; match: {:.2}%", stats.match_percent())?;
+    let delta = compare_size as i64 - orig_size as i64;
+    if delta == 0 {
+        writeln!(writer, "; length delta: 0")?;
+    } else {
+        writeln!(
+            writer,
+            "; length delta: {:+#X}",
+            CustomUpperHexFormat(delta)
+        )?;
+    }
+    writeln!(writer, ";")
+}
+
+fn write_asm_file(
     path: impl AsRef<Path>,
-    offset: u64,
+    insns: &[Instruction],
+    markers: &[char],
+    note: Option<&String>,
+    disasm_opts: &DisasmOpts,
+) -> Result<(), CompareError> {
+    let mut content = Vec::new();
+    let mut writer = LineEndingWriter::new(&mut content, disasm_opts.line_ending);
+    if let Some(note) = note {
+        writeln!(writer, "; {}", note).map_err(IoError)?;
+    }
+    write_marked_instructions(&mut writer, insns, markers, disasm_opts).map_err(DisasmError)?;
+    write_if_changed(path.as_ref(), &content).map_err(IoError)
+}
+
+/// Leaves the file untouched if it already has this content, editors would reload unchanged
+/// files after every rebuild in watch mode otherwise.
+fn write_if_changed(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    match std::fs::read(path) {
+        Ok(ref old_content) if old_content[..] == *content => Ok(()),
+        _ => std::fs::write(path, content),
+    }
+}
+
+/// e.g. `truncated devilution from 0x138 to 0x120 bytes to match the original`
+fn truncation_note(side: &str, from: usize, to: usize, other: &str) -> String {
+    format!(
+        "{} {} from {:#X} to {:#X} bytes to match the {}",
+        if to < from { "truncated" } else { "extended" },
+        side,
+        from,
+        to,
+        other
+    )
+}
+
+/// `<base>.<extension>`, or `<base>_<symbol>.<extension>` with --name-by-symbol. Characters
+/// that aren't allowed in file names, e.g. in decorated C++ names, are replaced with `_`.
+fn output_file_name(info: &CompareCommandInfo, base: &str, extension: &str) -> String {
+    if info.name_by_symbol {
+        let symbol: String = info
+            .compare_opts
+            .debug_symbol
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("{}_{}.{}", base, symbol, extension)
+    } else {
+        format!("{}.{}", base, extension)
+    }
+}
+
+/// One side of the combined file.
+struct Section<'a> {
+    title: &'a str,
+    insns: &'a [Instruction],
+    /// The `--match-markers` of the instructions, empty without them.
+    markers: &'a [char],
+    note: Option<&'a String>,
+}
+
+/// Writes all sections into one file, each below a `; <title>: <name>` header.
+fn write_combined_file(
+    path: impl AsRef<Path>,
+    name: &str,
+    sections: &[Section],
+    disasm_opts: &DisasmOpts,
 ) -> Result<(), CompareError> {
+    let mut content = Vec::new();
+    let mut writer = LineEndingWriter::new(&mut content, disasm_opts.line_ending);
+    for (k, section) in sections.iter().enumerate() {
+        if k > 0 {
+            writeln!(writer).map_err(IoError)?;
+        }
+        writeln!(writer, ";\n; {}: {}", section.title, name).map_err(IoError)?;
+        if let Some(note) = section.note {
+            writeln!(writer, "; {}", note).map_err(IoError)?;
+        }
+        writeln!(writer, ";\n").map_err(IoError)?;
+        write_marked_instructions(&mut writer, section.insns, section.markers, disasm_opts)
+            .map_err(DisasmError)?;
+    }
+    write_if_changed(path.as_ref(), &content).map_err(IoError)
+}
+
+fn read_file_into(buffer: &mut [u8], path: &Path, offset: u64) -> Result<(), CompareError> {
+    if is_compressed(path).map_err(IoError)? {
+        let bytes = read_binary(path).map_err(IoError)?;
+        let start = offset as usize;
+        let part = bytes.get(start..start + buffer.len()).ok_or_else(|| {
+            IoError(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ))
+        })?;
+        buffer.copy_from_slice(part);
+        return Ok(());
+    }
+
     File::open(path)
         .and_then(|mut f| f.seek(SeekFrom::Start(offset)).map(|_| f))
         .and_then(|mut f| f.read_exact(buffer))
         .map_err(IoError)
 }
+
+/// Same as `read_file_into`, but retries if the file is locked by another process,
+/// e.g. by the linker while it's still writing the binary.
+fn read_file_into_with_retry(
+    buffer: &mut [u8],
+    path: &Path,
+    offset: u64,
+) -> Result<(), CompareError> {
+    let mut attempt = 1;
+    loop {
+        match read_file_into(buffer, path, offset) {
+            Err(IoError(ref e)) if is_sharing_violation(e) && attempt < FILE_LOCKED_ATTEMPTS => {
+                std::thread::sleep(Duration::from_millis(FILE_LOCKED_RETRY_DELAY_MS * attempt));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levenshtein_distances() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("InitDiabloMsg", "InitDiabloMsg"), 0);
+        assert_eq!(levenshtein("InitDiabloMsg", "InitDaibloMsg"), 2);
+        assert_eq!(levenshtein("DrawInv", "DrawInvBelt"), 4);
+        assert_eq!(levenshtein("", "abc"), 3);
+    }
+
+    #[test]
+    fn ranks_typos_before_subsequence_matches() {
+        assert_eq!(symbol_rank("InitDiabloMsg", "InitDiabloMsh"), Some((0, 1)));
+        assert_eq!(symbol_rank("InitDiabloMsg", "IDMsg"), Some((1, 8)));
+        assert_eq!(symbol_rank("DrawInv", "CheckInvHLight"), None);
+    }
+}
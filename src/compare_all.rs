@@ -0,0 +1,535 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use regex::Regex;
+use serde_derive::Serialize;
+
+use self::CompareAllError::*;
+use super::comparer_config::*;
+use super::compressed::read_binary;
+use super::diff::*;
+use super::disasm::*;
+use super::pdb::*;
+use super::prepare::*;
+
+#[derive(Debug)]
+pub struct CompareAllCommandInfo {
+    pub orig: PathBuf,
+    pub compare_file_path: PathBuf,
+    pub pattern: Option<Regex>,
+    pub disasm_opts: super::DisasmOpts,
+    pub truncate_to_original: bool,
+    pub watch: bool,
+    pub max_diff_lines: Option<usize>,
+    /// Prologue and epilogue are ignored, every function is compared in full.
+    pub prepare_opts: PrepareOpts,
+    pub summary_json: Option<PathBuf>,
+    /// Only print the summary line, not every function.
+    pub count_only: bool,
+    /// Fail if a function is below its `min_match`.
+    pub check: bool,
+    /// Order of the results, the order of the config if `None`.
+    pub sort: Option<SortOrder>,
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// The results and a summary line for humans.
+    Text,
+    /// A JSON array of `TrackerEntry`, read by the decompilation progress tracker.
+    Tracker,
+}
+
+impl OutputFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "text" => Some(OutputFormat::Text),
+            "tracker" => Some(OutputFormat::Tracker),
+            _ => None,
+        }
+    }
+
+    /// Prints a message for humans, to stderr for the tracker format so stdout stays valid JSON.
+    pub fn print_message(self, msg: &str) {
+        match self {
+            OutputFormat::Text => println!("{}", msg),
+            OutputFormat::Tracker => eprintln!("{}", msg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SortOrder {
+    WorstFirst,
+    BestFirst,
+    Name,
+    /// Largest devilution function first.
+    Size,
+}
+
+impl SortOrder {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "worst-first" => Some(SortOrder::WorstFirst),
+            "best-first" => Some(SortOrder::BestFirst),
+            "name" => Some(SortOrder::Name),
+            "size" => Some(SortOrder::Size),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CompareAllError {
+    PdbError(super::pdb::PdbError),
+    IoError(std::io::Error),
+    DisasmError(super::disasm::DisasmError),
+    NormalizerError(super::normalizer::NormalizerError),
+    NotifyError(notify::Error),
+    SummaryJsonError(serde_json::Error),
+    TrackerOutputError(serde_json::Error),
+    /// Number of functions below their threshold.
+    BelowMinMatch(usize),
+}
+
+#[derive(Debug)]
+pub struct FunctionResult {
+    pub name: String,
+    pub orig_size: usize,
+    pub compare_size: usize,
+    pub stats: DiffStats,
+    /// `min_match` of the config, 100 for `done` functions.
+    pub min_match: Option<f64>,
+}
+
+/// Aggregated results, written by `--summary-json`.
+#[derive(Debug, Serialize)]
+pub struct Summary<'a> {
+    pub total_functions: usize,
+    pub matching_functions: usize,
+    pub average_match_percent: f64,
+    pub functions: Vec<FunctionSummary<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FunctionSummary<'a> {
+    pub name: &'a str,
+    pub match_percent: f64,
+    /// Compare size minus orig size.
+    pub size_delta: i64,
+}
+
+/// One function in the output of `--format tracker`.
+#[derive(Debug, Serialize)]
+pub struct TrackerEntry<'a> {
+    pub symbol: &'a str,
+    /// Match percentage rounded to two decimals.
+    pub percent: f64,
+    /// Size of the original function in bytes.
+    pub size: usize,
+}
+
+impl<'a> Summary<'a> {
+    pub fn from_results(results: &'a [FunctionResult]) -> Self {
+        let functions: Vec<FunctionSummary> = results
+            .iter()
+            .map(|res| FunctionSummary {
+                name: &res.name,
+                match_percent: res.stats.match_percent(),
+                size_delta: res.compare_size as i64 - res.orig_size as i64,
+            })
+            .collect();
+        let average_match_percent = if functions.is_empty() {
+            0.0
+        } else {
+            functions.iter().map(|func| func.match_percent).sum::<f64>() / functions.len() as f64
+        };
+
+        Summary {
+            total_functions: results.len(),
+            matching_functions: results
+                .iter()
+                .filter(|res| res.stats.differing_lines() == 0)
+                .count(),
+            average_match_percent,
+            functions,
+        }
+    }
+}
+
+pub fn run(mut info: CompareAllCommandInfo, cfg: &ComparerConfig) -> Result<(), CompareAllError> {
+    let mut results = compare_all(&mut info, cfg)?;
+    match info.format {
+        OutputFormat::Text => {
+            if !info.count_only {
+                print_results(&results);
+            }
+            print_summary(&results);
+        }
+        OutputFormat::Tracker => print_tracker_json(&results)?,
+    }
+    write_summary_json(&info, &results)?;
+    if info.check {
+        check_min_match(&results, info.format)?;
+    }
+
+    if !info.watch {
+        return Ok(());
+    }
+
+    let pdb_path = info.compare_file_path.with_extension("pdb");
+    let (tx, rx) = channel();
+
+    let mut watcher: RecommendedWatcher =
+        Watcher::new(tx, Duration::from_secs(2)).map_err(NotifyError)?;
+    watcher
+        .watch(&pdb_path, RecursiveMode::NonRecursive)
+        .map_err(NotifyError)?;
+
+    info.format.print_message(&format!(
+        "Started watching {} for changes. CTRL+C to quit.",
+        pdb_path.to_string_lossy()
+    ));
+
+    loop {
+        match rx.recv() {
+            Ok(DebouncedEvent::Create(_)) | Ok(DebouncedEvent::Write(_)) => {
+                match compare_all(&mut info, cfg) {
+                    Ok(new_results) => {
+                        if info.format == OutputFormat::Tracker {
+                            if let Err(e) = print_tracker_json(&new_results) {
+                                print_error(&e, info.format);
+                            }
+                        } else {
+                            print_changes(&results, &new_results);
+                            print_summary(&new_results);
+                        }
+                        if let Err(e) = write_summary_json(&info, &new_results) {
+                            print_error(&e, info.format);
+                        }
+                        results = new_results;
+                    }
+                    Err(e) => print_error(&e, info.format),
+                }
+            }
+            Err(e) => {
+                info.format
+                    .print_message(&format!("Watcher error: {:#?}", e));
+                std::process::exit(1);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Stable, so functions with the same percentage or size stay in the order of the config.
+fn sort_results(results: &mut [FunctionResult], order: SortOrder) {
+    let percent = |res: &FunctionResult| res.stats.match_percent();
+    match order {
+        SortOrder::WorstFirst => {
+            results.sort_by(|a, b| percent(a).partial_cmp(&percent(b)).unwrap())
+        }
+        SortOrder::BestFirst => {
+            results.sort_by(|a, b| percent(b).partial_cmp(&percent(a)).unwrap())
+        }
+        SortOrder::Name => results.sort_by(|a, b| a.name.cmp(&b.name)),
+        SortOrder::Size => results.sort_by_key(|res| Reverse(res.compare_size)),
+    }
+}
+
+fn print_results(results: &[FunctionResult]) {
+    let name_width = results.iter().map(|res| res.name.len()).max().unwrap_or(0);
+    for res in results {
+        println!(
+            "{:width$} {:>7.2}%  (orig size: {:#X}, compare size: {:#X})",
+            res.name,
+            res.stats.match_percent(),
+            res.orig_size,
+            res.compare_size,
+            width = name_width
+        );
+    }
+}
+
+/// Prints the functions whose match percentage changed between two runs.
+fn print_changes(old_results: &[FunctionResult], new_results: &[FunctionResult]) {
+    let old_percents: HashMap<&str, f64> = old_results
+        .iter()
+        .map(|res| (res.name.as_ref(), res.stats.match_percent()))
+        .collect();
+
+    let changes: Vec<(&str, Option<f64>, f64)> = new_results
+        .iter()
+        .map(|res| {
+            let name: &str = res.name.as_ref();
+            (
+                name,
+                old_percents.get(name).cloned(),
+                res.stats.match_percent(),
+            )
+        })
+        .filter(|&(_, old, new)| match old {
+            Some(old) => (old - new).abs() >= 0.005,
+            None => true,
+        })
+        .collect();
+
+    if changes.is_empty() {
+        println!("\nRebuild detected, no match percentages changed.");
+        return;
+    }
+
+    println!("\nRebuild detected, changed match percentages:");
+    let name_width = changes
+        .iter()
+        .map(|(name, ..)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, old, new) in changes {
+        match old {
+            Some(old) => println!(
+                "{:width$} {:>7.2}% -> {:>7.2}% ({:+.2})",
+                name,
+                old,
+                new,
+                new - old,
+                width = name_width
+            ),
+            None => println!(
+                "{:width$}      new -> {:>7.2}%",
+                name,
+                new,
+                width = name_width
+            ),
+        }
+    }
+}
+
+pub fn compare_all(
+    info: &mut CompareAllCommandInfo,
+    cfg: &ComparerConfig,
+) -> Result<Vec<FunctionResult>, CompareAllError> {
+    let pdb_path = info.compare_file_path.with_extension("pdb");
+    let pdb = if info.watch {
+        Pdb::new_with_retry(pdb_path)
+    } else {
+        Pdb::new(pdb_path)
+    }
+    .map_err(PdbError)?;
+    let orig_bytes = read_binary(&info.orig).map_err(IoError)?;
+    let compare_bytes = std::fs::read(&info.compare_file_path).map_err(IoError)?;
+
+    // keyed by name like in generate-full, so duplicate PDB symbols are only compared once
+    let pdb_funcs: HashMap<&str, FunctionSymbol> =
+        pdb.parse_pdb().map(|func| (func.name, func)).collect();
+    let orig_symbols = function_symbols(&cfg.func);
+    let compare_symbols = pdb.symbol_map();
+
+    let mut results = Vec::new();
+    let mut compared: HashSet<&str> = HashSet::new();
+    for orig_fn in &cfg.func {
+        // a function defined twice in the config is compared once
+        if !compared.insert(&orig_fn.name) {
+            continue;
+        }
+        if let Some(pattern) = &info.pattern {
+            if !pattern.is_match(&orig_fn.name) {
+                continue;
+            }
+        }
+        let pdb_func = match pdb_funcs.get(orig_fn.name.as_str()) {
+            Some(func) => func,
+            None => continue,
+        };
+
+        let orig_size = orig_fn.size.unwrap_or(pdb_func.size);
+        let compare_size = if info.truncate_to_original {
+            orig_size
+        } else {
+            pdb_func.size
+        };
+
+        let orig_func_bytes = orig_fn
+            .addr
+            .checked_sub(cfg.address_offset)
+            .and_then(|offset| bytes_at(&orig_bytes, offset as usize, orig_size));
+        let compare_offset = (pdb_func.offset + PDB_OFFSET_COMPARE_FILE) as usize;
+        let compare_func_bytes = bytes_at(&compare_bytes, compare_offset, compare_size);
+        let (mut orig_func_bytes, mut compare_func_bytes) =
+            match (orig_func_bytes, compare_func_bytes) {
+                (Some(orig), Some(compare)) => (orig.to_vec(), compare.to_vec()),
+                _ => {
+                    info.format.print_message(&format!(
+                        "WARN: The offset/size of {} are outside of the bounds of the input \
+                         file, skipping it.",
+                        orig_fn.name
+                    ));
+                    continue;
+                }
+            };
+
+        mask_bytes(&mut orig_func_bytes, &orig_fn.mask);
+        mask_bytes(&mut compare_func_bytes, &orig_fn.mask);
+
+        let compare_addr = pdb_func.offset + PDB_SEGMENT_OFFSET;
+        let orig = disasm_until_error(
+            &orig_func_bytes,
+            &mut info.disasm_opts,
+            orig_fn.addr,
+            &orig_fn.mask,
+            &orig_symbols,
+        )
+        .map_err(DisasmError)?;
+        let compare = disasm_until_error(
+            &compare_func_bytes,
+            &mut info.disasm_opts,
+            compare_addr,
+            &orig_fn.mask,
+            &compare_symbols,
+        )
+        .map_err(DisasmError)?;
+        if let Some(e) = orig.decode_error.as_ref().or(compare.decode_error.as_ref()) {
+            info.format.print_message(&format!(
+                "WARN: Skipping {}: {}",
+                orig_fn.name,
+                super::disasm::error_message(e)
+            ));
+            continue;
+        }
+        let (mut orig_insns, mut compare_insns) = (orig.insns, compare.insns);
+
+        let prepared = prepare_insns(
+            &info.prepare_opts,
+            &cfg.canonicalize,
+            &mut orig_insns,
+            orig_fn.addr,
+            &mut compare_insns,
+            compare_addr,
+        )
+        .map_err(NormalizerError)?;
+        let orig_lines = diff_input(&orig_insns, prepared.orig_padding);
+        let compare_lines = diff_input(&compare_insns, prepared.compare_padding);
+        let ops = match info.max_diff_lines {
+            Some(max) => match diff_lines_limited(&orig_lines, &compare_lines, max) {
+                Some(ops) => ops,
+                None => {
+                    info.format.print_message(&format!(
+                        "WARN: {} appears unrelated (>{} differing lines), check its address.",
+                        orig_fn.name, max
+                    ));
+                    continue;
+                }
+            },
+            None => diff_lines(&orig_lines, &compare_lines),
+        };
+        let (ops, _) = pair_reordered(&info.prepare_opts, ops, &orig_lines, &compare_lines);
+
+        results.push(FunctionResult {
+            name: orig_fn.name.clone(),
+            orig_size,
+            compare_size,
+            stats: diff_stats(
+                &ops,
+                &orig_insns[..orig_lines.len()],
+                &compare_insns[..compare_lines.len()],
+            ),
+            min_match: orig_fn
+                .min_match
+                .or(if orig_fn.done { Some(100.0) } else { None }),
+        });
+    }
+
+    if let Some(order) = info.sort {
+        sort_results(&mut results, order);
+    }
+    Ok(results)
+}
+
+fn bytes_at(bytes: &[u8], offset: usize, size: usize) -> Option<&[u8]> {
+    bytes.get(offset..offset.checked_add(size)?)
+}
+
+fn print_summary(results: &[FunctionResult]) {
+    let summary = Summary::from_results(results);
+    println!(
+        "\nCompared {} functions, {} matching, average match: {:.2}%",
+        summary.total_functions, summary.matching_functions, summary.average_match_percent
+    );
+}
+
+/// Prints the functions matching less than their `min_match`.
+fn check_min_match(
+    results: &[FunctionResult],
+    format: OutputFormat,
+) -> Result<(), CompareAllError> {
+    let mut failed = 0;
+    for res in results {
+        if let Some(min_match) = res.min_match {
+            let match_percent = res.stats.match_percent();
+            if match_percent < min_match {
+                format.print_message(&format!(
+                    "FAIL  {}: {:.2}% is below min_match {:.2}%",
+                    res.name, match_percent, min_match
+                ));
+                failed += 1;
+            }
+        }
+    }
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(BelowMinMatch(failed))
+    }
+}
+
+fn write_summary_json(
+    info: &CompareAllCommandInfo,
+    results: &[FunctionResult],
+) -> Result<(), CompareAllError> {
+    let path = match &info.summary_json {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let writer = BufWriter::new(File::create(path).map_err(IoError)?);
+    serde_json::to_writer_pretty(writer, &Summary::from_results(results)).map_err(SummaryJsonError)
+}
+
+fn print_tracker_json(results: &[FunctionResult]) -> Result<(), CompareAllError> {
+    let entries: Vec<TrackerEntry> = results
+        .iter()
+        .map(|res| TrackerEntry {
+            symbol: &res.name,
+            percent: (res.stats.match_percent() * 100.0).round() / 100.0,
+            size: res.orig_size,
+        })
+        .collect();
+
+    let stdout = std::io::stdout();
+    let mut stdout_lock = stdout.lock();
+    serde_json::to_writer_pretty(&mut stdout_lock, &entries).map_err(TrackerOutputError)?;
+    writeln!(stdout_lock).map_err(IoError)
+}
+
+/// `format` is the output format, errors go to stderr with the tracker format.
+pub fn print_error(e: &CompareAllError, format: OutputFormat) {
+    let msg = match e {
+        PdbError(e) => super::pdb::error_message(e),
+        IoError(e) => format!("IO error: {:#?}", e),
+        DisasmError(e) => super::disasm::error_message(e),
+        NormalizerError(e) => super::normalizer::error_message(e),
+        NotifyError(e) => format!("Watcher error: {:#?}", e),
+        SummaryJsonError(e) => format!("Error writing the summary file: {}", e),
+        TrackerOutputError(e) => format!("Error writing the tracker output: {}", e),
+        BelowMinMatch(n) => format!("{} function(s) are below their min_match.", n),
+    };
+    format.print_message(&msg);
+}
@@ -0,0 +1,133 @@
+use std::path::{Path, PathBuf};
+
+use self::CompareBuildsError::*;
+use super::comparer_config::CanonicalizeRule;
+use super::diff::*;
+use super::disasm::*;
+use super::pdb::*;
+use super::prepare::*;
+
+#[derive(Debug)]
+pub struct CompareBuildsCommandInfo {
+    pub first_file: PathBuf,
+    pub second_file: PathBuf,
+    pub debug_symbol: String,
+    pub disasm_opts: super::DisasmOpts,
+}
+
+#[derive(Debug)]
+pub enum CompareBuildsError {
+    PdbError(super::pdb::PdbError),
+    IoError(std::io::Error),
+    DisasmError(super::disasm::DisasmError),
+    SymbolNotFound(PathBuf),
+    FunctionOutOfBounds(PathBuf),
+    BuildsDiffer,
+}
+
+struct BuildFunction {
+    addr: u64,
+    bytes: Vec<u8>,
+    symbols: SymbolMap,
+}
+
+pub fn run(
+    mut info: CompareBuildsCommandInfo,
+    rules: &[CanonicalizeRule],
+) -> Result<(), CompareBuildsError> {
+    let first = read_function(&info.first_file, &info.debug_symbol)?;
+    let second = read_function(&info.second_file, &info.debug_symbol)?;
+
+    if first.bytes == second.bytes {
+        println!(
+            "{} is identical in both builds ({:#X} bytes).",
+            info.debug_symbol,
+            first.bytes.len()
+        );
+        return Ok(());
+    }
+
+    let mut first_insns = disasm(
+        &first.bytes,
+        &mut info.disasm_opts,
+        first.addr,
+        &[],
+        &first.symbols,
+    )
+    .map_err(DisasmError)?;
+    let mut second_insns = disasm(
+        &second.bytes,
+        &mut info.disasm_opts,
+        second.addr,
+        &[],
+        &second.symbols,
+    )
+    .map_err(DisasmError)?;
+    canonicalize_insns(rules, &mut first_insns);
+    canonicalize_insns(rules, &mut second_insns);
+
+    let first_lines: Vec<&str> = first_insns.iter().map(|insn| insn.text.as_ref()).collect();
+    let second_lines: Vec<&str> = second_insns.iter().map(|insn| insn.text.as_ref()).collect();
+    let ops = diff_lines(&first_lines, &second_lines);
+    let stats = DiffStats::from_ops(&ops, first_lines.len(), second_lines.len());
+
+    let first_diff = first
+        .bytes
+        .iter()
+        .zip(&second.bytes)
+        .position(|(a, b)| a != b)
+        .unwrap_or_else(|| first.bytes.len().min(second.bytes.len()));
+
+    println!(
+        "{} differs between the builds: sizes {:#X} and {:#X}, first differing byte at +{:#X}.",
+        info.debug_symbol,
+        first.bytes.len(),
+        second.bytes.len(),
+        first_diff
+    );
+    println!(
+        "Match: {:.2}% ({} matching, {} differing lines)",
+        stats.match_percent(),
+        stats.matching_lines,
+        stats.differing_lines(),
+    );
+
+    Err(BuildsDiffer)
+}
+
+fn read_function(file: &Path, symbol: &str) -> Result<BuildFunction, CompareBuildsError> {
+    let pdb = Pdb::new(file.with_extension("pdb")).map_err(PdbError)?;
+    let FunctionSymbol { offset, size, .. } = pdb
+        .parse_pdb()
+        .find(|func| func.name == symbol)
+        .ok_or_else(|| SymbolNotFound(file.to_path_buf()))?;
+
+    let bytes = std::fs::read(file).map_err(IoError)?;
+    let start = (offset + PDB_OFFSET_COMPARE_FILE) as usize;
+
+    Ok(BuildFunction {
+        addr: offset + PDB_SEGMENT_OFFSET,
+        bytes: bytes
+            .get(start..start + size)
+            .ok_or_else(|| FunctionOutOfBounds(file.to_path_buf()))?
+            .to_vec(),
+        symbols: pdb.symbol_map(),
+    })
+}
+
+pub fn print_error(e: &CompareBuildsError) {
+    match e {
+        PdbError(e) => super::pdb::print_error(e),
+        IoError(e) => println!("IO error: {:#?}", e),
+        DisasmError(e) => super::disasm::print_error(e),
+        SymbolNotFound(path) => println!(
+            "Could not find the symbol in the PDB of {}.",
+            path.to_string_lossy()
+        ),
+        FunctionOutOfBounds(path) => println!(
+            "Error: The function offset/size are outside of the bounds of {}.",
+            path.to_string_lossy()
+        ),
+        BuildsDiffer => println!("The builds are not identical."),
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use self::CompareGoldenError::*;
+use super::comparer_config::{canonicalize_line, CanonicalizeRule};
+use super::diff::*;
+use super::disasm::*;
+use super::mnemonics::canonical_mnemonic;
+use super::pdb::*;
+use super::prepare::*;
+use super::LineEndingWriter;
+
+const PATCH_FILE: &str = "compare.patch";
+const PATCH_CONTEXT_LINES: usize = 3;
+
+#[derive(Debug)]
+pub struct CompareGoldenCommandInfo {
+    pub compare_file_path: PathBuf,
+    pub debug_symbol: String,
+    pub golden_file: PathBuf,
+    pub disasm_opts: super::DisasmOpts,
+    /// Write a patch updating the golden file on differences.
+    pub write_patch: bool,
+}
+
+#[derive(Debug)]
+pub enum CompareGoldenError {
+    PdbError(super::pdb::PdbError),
+    IoError(std::io::Error),
+    DisasmError(super::disasm::DisasmError),
+    SymbolNotFound,
+    FunctionOutOfBounds,
+    GoldenMismatch,
+}
+
+pub fn run(
+    mut info: CompareGoldenCommandInfo,
+    rules: &[CanonicalizeRule],
+) -> Result<(), CompareGoldenError> {
+    let pdb = Pdb::new(info.compare_file_path.with_extension("pdb")).map_err(PdbError)?;
+    let FunctionSymbol { offset, size, .. } = pdb
+        .parse_pdb()
+        .find(|func| func.name == info.debug_symbol)
+        .ok_or(SymbolNotFound)?;
+
+    let bytes = std::fs::read(&info.compare_file_path).map_err(IoError)?;
+    let start = (offset + PDB_OFFSET_COMPARE_FILE) as usize;
+    let func_bytes = bytes.get(start..start + size).ok_or(FunctionOutOfBounds)?;
+
+    let mut insns = disasm(
+        func_bytes,
+        &mut info.disasm_opts,
+        offset + PDB_SEGMENT_OFFSET,
+        &[],
+        &pdb.symbol_map(),
+    )
+    .map_err(DisasmError)?;
+    let golden_content = std::fs::read_to_string(&info.golden_file).map_err(IoError)?;
+    let mut golden = parse_golden(&golden_content, info.disasm_opts.no_aliases);
+    canonicalize_insns(rules, &mut insns);
+    for (_, insn) in &mut golden {
+        insn.text = canonicalize_line(rules, &insn.text);
+    }
+
+    let golden_lines: Vec<&str> = golden.iter().map(|(_, insn)| insn.text.as_ref()).collect();
+    let lines: Vec<&str> = insns.iter().map(|insn| insn.text.as_ref()).collect();
+    let ops = diff_lines(&golden_lines, &lines);
+    let stats = DiffStats::from_ops(&ops, golden_lines.len(), lines.len());
+
+    if stats.differing_lines() == 0 {
+        println!(
+            "{} matches {}.",
+            info.debug_symbol,
+            info.golden_file.to_string_lossy()
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} differs from {} (golden on the left):",
+        info.debug_symbol,
+        info.golden_file.to_string_lossy()
+    );
+    let changed_rows: Vec<Row> = side_by_side_rows(&ops)
+        .into_iter()
+        .filter(|row| !matches!(row, Row::Equal(..)))
+        .collect();
+    let stdout = std::io::stdout();
+    write_side_by_side(&mut stdout.lock(), &changed_rows, &golden_lines, &lines)
+        .map_err(IoError)?;
+    println!(
+        "Match: {:.2}% ({} matching, {} differing lines)",
+        stats.match_percent(),
+        stats.matching_lines,
+        stats.differing_lines(),
+    );
+
+    if info.write_patch {
+        write_patch(&info, &golden_content, &golden, &insns, &ops)?;
+        println!("Wrote {}, apply it to update the golden file.", PATCH_FILE);
+    }
+
+    Err(GoldenMismatch)
+}
+
+/// Writes a unified diff turning the golden file into the current disassembly. Comments and
+/// empty lines of the golden file are kept.
+fn write_patch(
+    info: &CompareGoldenCommandInfo,
+    golden_content: &str,
+    golden: &[(usize, Instruction)],
+    insns: &[Instruction],
+    ops: &[DiffOp],
+) -> Result<(), CompareGoldenError> {
+    let old_lines: Vec<&str> = golden_content.lines().collect();
+    let inserted: Vec<String> = insns
+        .iter()
+        .map(|insn| {
+            if info.disasm_opts.print_adresses {
+                format!(
+                    "{}: {}",
+                    info.disasm_opts.addr_format.format(insn.ip),
+                    insn.text
+                )
+            } else {
+                insn.text.clone()
+            }
+        })
+        .collect();
+
+    let mut new_lines = Vec::with_capacity(old_lines.len());
+    let mut next_line = 0;
+    for op in ops {
+        match *op {
+            DiffOp::Equal(i, _) | DiffOp::Delete(i) => {
+                let line = golden[i].0;
+                new_lines.extend_from_slice(&old_lines[next_line..line]);
+                if let DiffOp::Equal(..) = op {
+                    new_lines.push(old_lines[line]);
+                }
+                next_line = line + 1;
+            }
+            DiffOp::Insert(j) => new_lines.push(&inserted[j]),
+        }
+    }
+    new_lines.extend_from_slice(&old_lines[next_line..]);
+
+    let name = info.golden_file.to_string_lossy();
+    File::create(PATCH_FILE)
+        .map(|f| LineEndingWriter::new(BufWriter::new(f), info.disasm_opts.line_ending))
+        .and_then(|mut writer| {
+            write_unified_diff(
+                &mut writer,
+                &name,
+                &name,
+                &old_lines,
+                &new_lines,
+                PATCH_CONTEXT_LINES,
+            )
+        })
+        .map_err(IoError)
+}
+
+/// Reads the instructions of a previously written disassembly together with their line
+/// indices. Empty lines and comments are skipped, as are leading addresses written with
+/// `--show-ip`. With `no_aliases` the mnemonics are brought into their canonical form.
+fn parse_golden(content: &str, no_aliases: bool) -> Vec<(usize, Instruction)> {
+    content
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with(';'))
+        .map(|(k, line)| {
+            let text = match line.find(": ") {
+                Some(pos) if is_address(&line[..pos]) => &line[pos + 2..],
+                _ => line,
+            };
+            (
+                k,
+                Instruction {
+                    start: 0,
+                    ip: 0,
+                    text: if no_aliases {
+                        canonical_mnemonic(text)
+                    } else {
+                        text.into()
+                    },
+                    branch_target: None,
+                    call_target: None,
+                    is_label: false,
+                    detail: InstructionDetail::default(),
+                },
+            )
+        })
+        .collect()
+}
+
+fn is_address(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() || c == 'x' || c == 'X' || c == 'h')
+}
+
+pub fn print_error(e: &CompareGoldenError) {
+    match e {
+        PdbError(e) => super::pdb::print_error(e),
+        IoError(e) => println!("IO error: {:#?}", e),
+        DisasmError(e) => super::disasm::print_error(e),
+        SymbolNotFound => println!("Could not find the symbol in the PDB."),
+        FunctionOutOfBounds => println!(
+            "Error: The function offset/size are outside of the bounds of the debug binary."
+        ),
+        GoldenMismatch => println!("The function does not match the golden file."),
+    }
+}
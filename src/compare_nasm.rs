@@ -0,0 +1,160 @@
+//! Comparing a function against hand written assembly in NASM syntax.
+//!
+//! The file is assembled with `nasm -f bin` at the address of the function, so relative jumps
+//! and calls to absolute addresses come out the same. Both sides are then disassembled and
+//! compared like with `compare-golden`.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+use self::CompareNasmError::*;
+use super::comparer_config::CanonicalizeRule;
+use super::diff::*;
+use super::disasm::*;
+use super::pdb::*;
+use super::prepare::*;
+
+#[derive(Debug)]
+pub struct CompareNasmCommandInfo {
+    pub compare_file_path: PathBuf,
+    pub debug_symbol: String,
+    pub nasm_file: PathBuf,
+    /// The nasm executable.
+    pub nasm: PathBuf,
+    pub disasm_opts: super::DisasmOpts,
+}
+
+#[derive(Debug)]
+pub enum CompareNasmError {
+    PdbError(super::pdb::PdbError),
+    IoError(std::io::Error),
+    /// Running nasm failed, with its stderr.
+    NasmFailed(ExitStatus, String),
+    DisasmError(super::disasm::DisasmError),
+    SymbolNotFound,
+    FunctionOutOfBounds,
+    NasmMismatch,
+}
+
+pub fn run(
+    mut info: CompareNasmCommandInfo,
+    rules: &[CanonicalizeRule],
+) -> Result<(), CompareNasmError> {
+    let pdb = Pdb::new(info.compare_file_path.with_extension("pdb")).map_err(PdbError)?;
+    let FunctionSymbol { offset, size, .. } = pdb
+        .parse_pdb()
+        .find(|func| func.name == info.debug_symbol)
+        .ok_or(SymbolNotFound)?;
+
+    let bytes = std::fs::read(&info.compare_file_path).map_err(IoError)?;
+    let start = (offset + PDB_OFFSET_COMPARE_FILE) as usize;
+    let func_bytes = bytes.get(start..start + size).ok_or(FunctionOutOfBounds)?;
+
+    let addr = offset + PDB_SEGMENT_OFFSET;
+    let nasm_bytes = assemble_file(&info, addr)?;
+    if nasm_bytes == func_bytes {
+        println!(
+            "{} matches {} ({:#X} identical bytes).",
+            info.debug_symbol,
+            info.nasm_file.to_string_lossy(),
+            size
+        );
+        return Ok(());
+    }
+
+    let symbols = pdb.symbol_map();
+    let mut nasm_insns =
+        disasm(&nasm_bytes, &mut info.disasm_opts, addr, &[], &symbols).map_err(DisasmError)?;
+    let mut insns =
+        disasm(func_bytes, &mut info.disasm_opts, addr, &[], &symbols).map_err(DisasmError)?;
+    canonicalize_insns(rules, &mut nasm_insns);
+    canonicalize_insns(rules, &mut insns);
+
+    let nasm_lines: Vec<&str> = nasm_insns.iter().map(|insn| insn.text.as_ref()).collect();
+    let lines: Vec<&str> = insns.iter().map(|insn| insn.text.as_ref()).collect();
+    let ops = diff_lines(&nasm_lines, &lines);
+    let stats = DiffStats::from_ops(&ops, nasm_lines.len(), lines.len());
+
+    println!(
+        "{} differs from {} ({:#X} bytes assembled, {:#X} in the binary; nasm on the left):",
+        info.debug_symbol,
+        info.nasm_file.to_string_lossy(),
+        nasm_bytes.len(),
+        size
+    );
+    let changed_rows: Vec<Row> = side_by_side_rows(&ops)
+        .into_iter()
+        .filter(|row| !matches!(row, Row::Equal(..)))
+        .collect();
+    let stdout = std::io::stdout();
+    write_side_by_side(&mut stdout.lock(), &changed_rows, &nasm_lines, &lines).map_err(IoError)?;
+    println!(
+        "Match: {:.2}% ({} matching, {} differing lines)",
+        stats.match_percent(),
+        stats.matching_lines,
+        stats.differing_lines(),
+    );
+
+    Err(NasmMismatch)
+}
+
+/// Assembles the file at `addr` through a wrapper file setting the bitness and origin.
+fn assemble_file(info: &CompareNasmCommandInfo, addr: u64) -> Result<Vec<u8>, CompareNasmError> {
+    // not `canonicalize`, nasm doesn't understand the \\?\ paths it returns on Windows
+    let nasm_file = std::env::current_dir()
+        .map_err(IoError)?
+        .join(&info.nasm_file);
+    assemble_source(
+        &info.nasm,
+        &format!(
+            "bits 32\norg {:#X}\n%include \"{}\"\n",
+            addr,
+            nasm_file.to_string_lossy()
+        ),
+    )
+}
+
+/// Assembles `source` with `nasm -f bin` from a temporary file.
+pub fn assemble_source(nasm: &Path, source: &str) -> Result<Vec<u8>, CompareNasmError> {
+    let tmp_dir = std::env::temp_dir();
+    let name = format!("devilution-comparer-{}", std::process::id());
+    let wrapper_path = tmp_dir.join(format!("{}.asm", name));
+    let output_path = tmp_dir.join(format!("{}.bin", name));
+
+    std::fs::write(&wrapper_path, source).map_err(IoError)?;
+    let output = Command::new(nasm)
+        .arg("-f")
+        .arg("bin")
+        .arg("-o")
+        .arg(&output_path)
+        .arg(&wrapper_path)
+        .output();
+    // a leftover temporary file is harmless, the result of nasm matters more
+    let _ = std::fs::remove_file(&wrapper_path);
+    let output = output.map_err(IoError)?;
+    if !output.status.success() {
+        let _ = std::fs::remove_file(&output_path);
+        return Err(NasmFailed(
+            output.status,
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+
+    let bytes = std::fs::read(&output_path).map_err(IoError);
+    let _ = std::fs::remove_file(&output_path);
+    bytes
+}
+
+pub fn print_error(e: &CompareNasmError) {
+    match e {
+        PdbError(e) => super::pdb::print_error(e),
+        IoError(e) => println!("IO error: {:#?}", e),
+        NasmFailed(status, stderr) => println!("nasm exited with {}:\n{}", status, stderr),
+        DisasmError(e) => super::disasm::print_error(e),
+        SymbolNotFound => println!("Could not find the symbol in the PDB."),
+        FunctionOutOfBounds => println!(
+            "Error: The function offset/size are outside of the bounds of the debug binary."
+        ),
+        NasmMismatch => println!("The function does not match the NASM file."),
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::{BufWriter, Read};
+
+use self::CompareStdinError::*;
+use super::disasm::*;
+use super::highlight::RegisterHighlight;
+use super::pager::Pager;
+use super::LineEndingWriter;
+
+#[derive(Debug)]
+pub struct CompareStdinCommandInfo {
+    /// Address of the first byte read from stdin.
+    pub base: u64,
+    pub disasm_opts: super::DisasmOpts,
+    /// Show the output in the pager if stdout is a terminal.
+    pub pager: bool,
+    pub highlight: RegisterHighlight,
+}
+
+#[derive(Debug)]
+pub enum CompareStdinError {
+    IoError(std::io::Error),
+    DisasmError(super::disasm::DisasmError),
+}
+
+pub fn run(mut info: CompareStdinCommandInfo) -> Result<(), CompareStdinError> {
+    let mut bytes = Vec::new();
+    std::io::stdin().read_to_end(&mut bytes).map_err(IoError)?;
+
+    let mut insns = disasm(
+        &bytes,
+        &mut info.disasm_opts,
+        info.base,
+        &[],
+        &SymbolMap::new(),
+    )
+    .map_err(DisasmError)?;
+    if info.highlight.is_active() {
+        for insn in &mut insns {
+            insn.text = info.highlight.apply(&insn.text).into_owned();
+        }
+    }
+
+    let mut writer = LineEndingWriter::new(
+        BufWriter::new(Pager::start(info.pager)),
+        info.disasm_opts.line_ending,
+    );
+    write_instructions(&mut writer, &insns, &info.disasm_opts).map_err(DisasmError)?;
+    writer
+        .into_inner()
+        .into_inner()
+        .map_err(|e| IoError(e.into()))?
+        .finish()
+        .map_err(IoError)
+}
+
+pub fn print_error(e: &CompareStdinError) {
+    match e {
+        IoError(e) => println!("IO error: {:#?}", e),
+        DisasmError(e) => super::disasm::print_error(e),
+    }
+}
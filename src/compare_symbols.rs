@@ -0,0 +1,125 @@
+//! Comparison of two functions of the devilution binary, e.g. to find duplicated code.
+
+use std::path::PathBuf;
+
+use self::CompareSymbolsError::*;
+use super::comparer_config::CanonicalizeRule;
+use super::diff::*;
+use super::disasm::*;
+use super::pdb::*;
+use super::prepare::*;
+
+#[derive(Debug)]
+pub struct CompareSymbolsCommandInfo {
+    pub compare_file_path: PathBuf,
+    pub first_symbol: String,
+    pub second_symbol: String,
+    pub disasm_opts: super::DisasmOpts,
+}
+
+#[derive(Debug)]
+pub enum CompareSymbolsError {
+    PdbError(super::pdb::PdbError),
+    IoError(std::io::Error),
+    DisasmError(super::disasm::DisasmError),
+    SymbolNotFound(String),
+    FunctionOutOfBounds(String),
+}
+
+pub fn run(
+    mut info: CompareSymbolsCommandInfo,
+    rules: &[CanonicalizeRule],
+) -> Result<(), CompareSymbolsError> {
+    let pdb = Pdb::new(info.compare_file_path.with_extension("pdb")).map_err(PdbError)?;
+    let find_symbol = |name: &str| {
+        pdb.parse_pdb()
+            .find(|func| func.name == name)
+            .ok_or_else(|| SymbolNotFound(name.to_owned()))
+    };
+    let first = find_symbol(&info.first_symbol)?;
+    let second = find_symbol(&info.second_symbol)?;
+
+    let bytes = std::fs::read(&info.compare_file_path).map_err(IoError)?;
+    let function_bytes = |func: &FunctionSymbol| {
+        let start = (func.offset + PDB_OFFSET_COMPARE_FILE) as usize;
+        bytes
+            .get(start..start + func.size)
+            .ok_or_else(|| FunctionOutOfBounds(func.name.to_owned()))
+    };
+    let first_bytes = function_bytes(&first)?;
+    let second_bytes = function_bytes(&second)?;
+
+    // Like the original in the normal comparison, the second function is disassembled at the
+    // address of the first one so their jumps line up. Its symbols are moved along, so calls
+    // still resolve to the right names.
+    let addr = first.offset + PDB_SEGMENT_OFFSET;
+    let shift = first.offset.wrapping_sub(second.offset);
+    let symbols = pdb.symbol_map();
+    let shifted_symbols: SymbolMap = symbols
+        .iter()
+        .map(|(&addr, name)| (addr.wrapping_add(shift), name.clone()))
+        .collect();
+    let mut first_insns =
+        disasm(first_bytes, &mut info.disasm_opts, addr, &[], &symbols).map_err(DisasmError)?;
+    let mut second_insns = disasm(
+        second_bytes,
+        &mut info.disasm_opts,
+        addr,
+        &[],
+        &shifted_symbols,
+    )
+    .map_err(DisasmError)?;
+    canonicalize_insns(rules, &mut first_insns);
+    canonicalize_insns(rules, &mut second_insns);
+
+    let first_lines: Vec<&str> = first_insns.iter().map(|insn| insn.text.as_ref()).collect();
+    let second_lines: Vec<&str> = second_insns.iter().map(|insn| insn.text.as_ref()).collect();
+    let ops = diff_lines(&first_lines, &second_lines);
+    let stats = DiffStats::from_ops(&ops, first_lines.len(), second_lines.len());
+
+    if stats.differing_lines() == 0 {
+        println!(
+            "{} and {} are identical ({:#X} and {:#X} bytes).",
+            info.first_symbol, info.second_symbol, first.size, second.size
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} ({:#X} bytes, on the left) differs from {} ({:#X} bytes):",
+        info.first_symbol, first.size, info.second_symbol, second.size
+    );
+    let changed_rows: Vec<Row> = side_by_side_rows(&ops)
+        .into_iter()
+        .filter(|row| !matches!(row, Row::Equal(..)))
+        .collect();
+    let stdout = std::io::stdout();
+    write_side_by_side(
+        &mut stdout.lock(),
+        &changed_rows,
+        &first_lines,
+        &second_lines,
+    )
+    .map_err(IoError)?;
+    println!(
+        "Match: {:.2}% ({} matching, {} differing lines)",
+        stats.match_percent(),
+        stats.matching_lines,
+        stats.differing_lines(),
+    );
+
+    Ok(())
+}
+
+pub fn print_error(e: &CompareSymbolsError) {
+    match e {
+        PdbError(e) => super::pdb::print_error(e),
+        IoError(e) => println!("IO error: {:#?}", e),
+        DisasmError(e) => super::disasm::print_error(e),
+        SymbolNotFound(name) => println!("Could not find the symbol {} in the PDB.", name),
+        FunctionOutOfBounds(name) => println!(
+            "Error: The offset/size of {} are outside of the bounds of the debug binary.",
+            name
+        ),
+    }
+}
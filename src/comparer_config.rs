@@ -1,38 +1,570 @@
-use std::path::Path;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
 
+use regex::Regex;
+use serde::{Deserialize as _, Deserializer};
 use serde_derive::Deserialize;
 
+use super::disasm::SymbolMap;
+
 const COMPARER_CONFIG_FILE: &str = "comparer-config.toml";
+/// Optional file next to the config whose values replace the ones of the config.
+const LOCAL_CONFIG_FILE: &str = "comparer-config.local.toml";
+
+/// Layout of comparer-config.toml as expected by `ComparerConfig`, keep in sync with the structs.
+pub const CONFIG_SCHEMA: &str = "\
+# comparer-config.toml, integers can be written as hex, e.g. 0x400000
+# values in an optional comparer-config.local.toml next to it replace the ones given here
+address_offset = <integer>               # required, virtual address minus file offset of the original binary
+default_symbol = <string>                # optional, compared if no DEBUG_SYMBOL is given
+build_dir = <string>                     # optional, searched for binary_name if DEVILUTION_FILE is auto
+binary_name = <string>                   # optional, file name of the debug binary, e.g. devilution.exe
+
+[profiles.<name>]                        # optional, flags applied with --profile <name>, repeatable
+<flag> = <boolean>                       # optional, show_ip, no_mem_disp, no_imms, resolve_symbols,
+                                         #   show_blocks, annotate_xrefs, skipdata, show_encoding_size,
+                                         #   no_aliases, show_padding, show_access,
+                                         #   annotate_immediates_as_chars, align_tabs or
+                                         #   resolve_globals
+<option> = <string>                      # optional, addr_format, line_ending or strip_symbols_regex
+
+[[canonicalize]]                         # optional, replacements applied to the disassembled lines before comparing, repeatable
+from = <string>                          # required, regular expression
+to = <string>                            # required, replacement, may refer to groups of from as $1
+
+[[func]]                                 # one table per function, repeatable
+name = <string>                          # required, the debug symbol in the devilution binary
+addr = <integer> | \"<name>+<integer>\"   # required, virtual address in the original binary, may be relative to another function
+size = <integer>                         # optional, defaults to the size in the pdb file
+mask = [[<integer>, <integer>], ...]     # optional, ignored byte ranges [start, end) relative to addr
+done = <boolean>                         # optional, matching functions are skipped by generate-full
+min_match = <float>                      # optional, lowest match percentage accepted by compare-all --check,
+                                         #   defaults to 100.0 if done = true and to no check otherwise
+";
 
 #[derive(Debug, Deserialize)]
 pub struct ComparerConfig {
     pub address_offset: u64,
+    /// Compared if no DEBUG_SYMBOL is given.
+    pub default_symbol: Option<String>,
+    /// Searched for `binary_name` if DEVILUTION_FILE is `auto`.
+    pub build_dir: Option<PathBuf>,
+    pub binary_name: Option<String>,
+    /// Named flag sets selected with `--profile`.
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    /// Replacements applied to every disassembled line before comparing.
+    #[serde(default)]
+    pub canonicalize: Vec<CanonicalizeRule>,
     pub func: Vec<FunctionDefinition>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct CanonicalizeRule {
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub from: Regex,
+    /// May refer to the groups of `from`, e.g. `$1`.
+    pub to: String,
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+/// Applies the matching rules to `line` in their order.
+pub fn canonicalize_line(rules: &[CanonicalizeRule], line: &str) -> String {
+    rules.iter().fold(line.to_owned(), |line, rule| {
+        rule.from.replace_all(&line, rule.to.as_str()).into_owned()
+    })
+}
+
+/// Disassembly flags stored in the config, named like the command line flags. Flags given on
+/// the command line are added to them, values given there replace them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub show_ip: bool,
+    pub no_mem_disp: bool,
+    pub no_imms: bool,
+    pub resolve_symbols: bool,
+    pub show_blocks: bool,
+    pub annotate_xrefs: bool,
+    pub skipdata: bool,
+    pub show_encoding_size: bool,
+    pub no_aliases: bool,
+    pub show_padding: bool,
+    pub show_access: bool,
+    pub annotate_immediates_as_chars: bool,
+    pub align_tabs: bool,
+    pub resolve_globals: bool,
+    pub addr_format: Option<String>,
+    pub line_ending: Option<String>,
+    pub strip_symbols_regex: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct FunctionDefinition {
     pub name: String,
     pub addr: u64,
     pub size: Option<usize>,
+    /// Byte ranges `[start, end)` relative to the function start that are ignored
+    /// when comparing, e.g. embedded pointers.
+    #[serde(default)]
+    pub mask: Vec<(usize, usize)>,
+    /// Marks functions that already match, `generate-full` skips them by default.
+    #[serde(default)]
+    pub done: bool,
+    /// Lowest match percentage accepted by `compare-all --check`, 100 for `done` functions.
+    pub min_match: Option<f64>,
 }
 
 #[derive(Debug)]
 pub enum ComparerConfigError {
     IoError(std::io::Error),
     ParseError(toml::de::Error),
+    /// Line number and description of an invalid CSV line.
+    InvalidCsv(usize, String),
+    /// A symbol relative `addr` that couldn't be resolved.
+    InvalidAddress(String),
 }
 
 impl ComparerConfig {
     fn read_from_file(path: impl AsRef<Path>) -> Result<Self, ComparerConfigError> {
-        Ok(toml::from_str::<Self>(
-            &std::fs::read_to_string(path).map_err(ComparerConfigError::IoError)?,
-        ).map_err(ComparerConfigError::ParseError)?)
+        let path = path.as_ref();
+        let mut value = read_toml(path)?;
+        let local_path = path.with_file_name(LOCAL_CONFIG_FILE);
+        if local_path.is_file() {
+            merge_values(&mut value, read_toml(&local_path)?);
+        }
+        resolve_symbol_addrs(&mut value)?;
+        value
+            .try_into::<Self>()
+            .map_err(ComparerConfigError::ParseError)
     }
 
     pub fn read_default() -> Result<Self, ComparerConfigError> {
-        let mut path = std::env::current_exe().map_err(ComparerConfigError::IoError)?;
-        path.set_file_name(COMPARER_CONFIG_FILE);
-        Self::read_from_file(path)
+        Self::read_from_file(default_path()?)
+    }
+
+    /// Like `read_default`, but `None` if there is no config file.
+    pub fn read_default_if_exists() -> Result<Option<Self>, ComparerConfigError> {
+        let path = default_path()?;
+        if path.is_file() {
+            Self::read_from_file(path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn default_path() -> Result<PathBuf, ComparerConfigError> {
+    let mut path = std::env::current_exe().map_err(ComparerConfigError::IoError)?;
+    path.set_file_name(COMPARER_CONFIG_FILE);
+    Ok(path)
+}
+
+fn read_toml(path: &Path) -> Result<toml::Value, ComparerConfigError> {
+    toml::from_str(&std::fs::read_to_string(path).map_err(ComparerConfigError::IoError)?)
+        .map_err(ComparerConfigError::ParseError)
+}
+
+/// Merges the tables of `overlay` into `base` recursively, everything else in `overlay`
+/// (including arrays like `func`) replaces the value in `base`.
+fn merge_values(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge_values(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Replaces `addr = "SymbolName+0x10"` with the address of the function named `SymbolName`
+/// plus the delta. The base function needs a numeric address.
+fn resolve_symbol_addrs(value: &mut toml::Value) -> Result<(), ComparerConfigError> {
+    let funcs = match value.get_mut("func").and_then(toml::Value::as_array_mut) {
+        Some(funcs) => funcs,
+        None => return Ok(()),
+    };
+
+    let addrs: HashMap<String, u64> = funcs
+        .iter()
+        .filter_map(|func| {
+            let name = func.get("name")?.as_str()?;
+            let addr = func.get("addr")?.as_integer()?;
+            Some((name.to_owned(), addr as u64))
+        })
+        .collect();
+
+    for func in funcs.iter_mut() {
+        let expr = match func.get("addr").and_then(toml::Value::as_str) {
+            Some(expr) => expr.to_owned(),
+            None => continue,
+        };
+        let addr = AddressExpr::parse(&expr)
+            .ok()
+            .and_then(|addr| addr.resolve(|name| addrs.get(name).cloned()))
+            .ok_or_else(|| ComparerConfigError::InvalidAddress(expr.clone()))?;
+        func["addr"] = toml::Value::Integer(addr as i64);
+    }
+
+    Ok(())
+}
+
+/// An address given as a number or relative to a symbol, e.g. `SymbolName+0x10`.
+#[derive(Debug, Clone)]
+pub enum AddressExpr {
+    Absolute(u64),
+    Relative(String, i64),
+}
+
+impl AddressExpr {
+    /// Parses a number or `SymbolName`, optionally followed by `+` or `-` and a number.
+    pub fn parse(v: &str) -> Result<Self, String> {
+        let v = v.trim();
+        if let Ok(addr) = parse_number(v) {
+            return Ok(AddressExpr::Absolute(addr));
+        }
+
+        let (name, delta) = match v.find(&['+', '-'][..]) {
+            Some(pos) => {
+                let delta = parse_number(v[pos + 1..].trim())
+                    .map_err(|_| format!("invalid delta in '{}'", v))?
+                    as i64;
+                let delta = if v[pos..].starts_with('-') {
+                    -delta
+                } else {
+                    delta
+                };
+                (v[..pos].trim(), delta)
+            }
+            None => (v, 0),
+        };
+        if name.is_empty() {
+            return Err(format!("missing symbol name in '{}'", v));
+        }
+        if !is_identifier(name) {
+            return Err(format!("'{}' is neither a number nor a symbol name", name));
+        }
+        Ok(AddressExpr::Relative(name.into(), delta))
+    }
+
+    /// Resolves the address, `lookup` returns the address of a symbol.
+    pub fn resolve(&self, lookup: impl Fn(&str) -> Option<u64>) -> Option<u64> {
+        match self {
+            AddressExpr::Absolute(addr) => Some(*addr),
+            AddressExpr::Relative(name, delta) => {
+                lookup(name).map(|addr| (addr as i64 + delta) as u64)
+            }
+        }
+    }
+}
+
+/// Symbol names may contain the characters of MSVC's decorated names, but can't start with a
+/// digit.
+fn is_identifier(name: &str) -> bool {
+    let is_symbol_char = |c: char| c.is_ascii_alphanumeric() || "_?@$".contains(c);
+    name.chars().all(is_symbol_char) && !name.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Maps the function addresses in the original binary to their names.
+pub fn function_symbols(funcs: &[FunctionDefinition]) -> SymbolMap {
+    funcs
+        .iter()
+        .map(|func| (func.addr, func.name.clone()))
+        .collect()
+}
+
+/// Reads function definitions from a CSV file. The header row names the columns, `name` and
+/// `addr` are required, `size` and `devilution symbol` are optional. The devilution symbol
+/// replaces the name when it's given, as the name has to be the debug symbol. Other columns
+/// are ignored.
+pub fn read_functions_csv(
+    path: impl AsRef<Path>,
+) -> Result<Vec<FunctionDefinition>, ComparerConfigError> {
+    let content = std::fs::read_to_string(path).map_err(ComparerConfigError::IoError)?;
+    parse_functions_csv(&content)
+}
+
+fn parse_functions_csv(content: &str) -> Result<Vec<FunctionDefinition>, ComparerConfigError> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            split_csv_line(line)
+                .map(|fields| (i + 1, fields))
+                .map_err(|e| ComparerConfigError::InvalidCsv(i + 1, e))
+        });
+
+    let header: Vec<String> = match lines.next() {
+        Some(header) => header?.1.iter().map(|col| col.to_lowercase()).collect(),
+        None => Vec::new(),
+    };
+    let column = |name: &str| header.iter().position(|col| col == name);
+    let (name_col, addr_col, size_col, symbol_col) = match (column("name"), column("addr")) {
+        (Some(name), Some(addr)) => (name, addr, column("size"), column("devilution symbol")),
+        _ => {
+            return Err(ComparerConfigError::InvalidCsv(
+                1,
+                "The header needs a name and an addr column.".into(),
+            ))
+        }
+    };
+
+    let funcs = lines
+        .map(|line| {
+            let (line_no, fields) = line?;
+            let field = |col: usize| {
+                fields
+                    .get(col)
+                    .map(String::as_str)
+                    .filter(|f| !f.is_empty())
+            };
+            let invalid = |msg: String| ComparerConfigError::InvalidCsv(line_no, msg);
+
+            let addr = field(addr_col)
+                .ok_or_else(|| invalid("missing addr".into()))
+                .and_then(|f| AddressExpr::parse(f).map_err(invalid))?;
+            let size = match size_col.and_then(field) {
+                Some(f) => Some(
+                    parse_number(f).map_err(|_| invalid(format!("invalid number '{}'", f)))?
+                        as usize,
+                ),
+                None => None,
+            };
+            let func = FunctionDefinition {
+                name: symbol_col
+                    .and_then(field)
+                    .or_else(|| field(name_col))
+                    .ok_or_else(|| invalid("missing name".into()))?
+                    .into(),
+                addr: 0,
+                size,
+                mask: Vec::new(),
+                done: false,
+                min_match: None,
+            };
+            Ok((line_no, addr, func))
+        })
+        .collect::<Result<Vec<_>, ComparerConfigError>>()?;
+
+    // symbol relative addresses refer to other functions of the file, like in the config
+    let addrs: HashMap<&str, u64> = funcs
+        .iter()
+        .filter_map(|(_, addr, func)| match addr {
+            AddressExpr::Absolute(addr) => Some((func.name.as_ref(), *addr)),
+            AddressExpr::Relative(..) => None,
+        })
+        .collect();
+    funcs
+        .iter()
+        .map(|(line_no, addr, func)| {
+            let addr = addr
+                .resolve(|name| addrs.get(name).cloned())
+                .ok_or_else(|| {
+                    ComparerConfigError::InvalidCsv(*line_no, "unknown symbol in addr".into())
+                })?;
+            Ok(FunctionDefinition {
+                addr,
+                ..func.clone()
+            })
+        })
+        .collect()
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while let Some(c) = chars.peek() {
+        if !c.is_whitespace() {
+            break;
+        }
+        chars.next();
+    }
+}
+
+/// Splits a CSV line into its trimmed fields. Quoted fields may contain commas, and `""`
+/// inside them stands for a quote.
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        skip_whitespace(&mut chars);
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err("unterminated quoted field".into()),
+                }
+            }
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => fields.push(field),
+                None => {
+                    fields.push(field);
+                    return Ok(fields);
+                }
+                Some(c) => return Err(format!("unexpected '{}' after a quoted field", c)),
+            }
+        } else {
+            loop {
+                match chars.next() {
+                    Some(',') => break,
+                    Some(c) => field.push(c),
+                    None => {
+                        fields.push(field.trim().to_owned());
+                        return Ok(fields);
+                    }
+                }
+            }
+            fields.push(field.trim().to_owned());
+        }
+    }
+}
+
+pub fn print_error(e: &ComparerConfigError) {
+    match e {
+        ComparerConfigError::IoError(e) => println!("IO error: {:#?}", e),
+        ComparerConfigError::ParseError(e) => println!("Error parsing the config file: {:#?}", e),
+        ComparerConfigError::InvalidCsv(line, msg) => {
+            println!("Error in line {} of the CSV file: {}", line, msg)
+        }
+        ComparerConfigError::InvalidAddress(addr) => {
+            println!("Error: Invalid function address '{}'", addr)
+        }
+    }
+}
+
+/// Parses a `0x` prefixed hexadecimal or a decimal number.
+pub fn parse_number(v: &str) -> Result<u64, std::num::ParseIntError> {
+    if v.starts_with("0x") || v.starts_with("0X") {
+        u64::from_str_radix(&v[2..], 16)
+    } else {
+        v.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(expr: &str) -> Option<u64> {
+        AddressExpr::parse(expr)
+            .unwrap()
+            .resolve(|name| match name {
+                "InitDiabloMsg" => Some(0x41_0000),
+                "?Foo@@YAXXZ" => Some(0x40_1000),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn parses_absolute_addresses() {
+        match AddressExpr::parse(" 0x401A2C ") {
+            Ok(AddressExpr::Absolute(0x40_1A2C)) => {}
+            other => panic!("{:?}", other),
+        }
+        match AddressExpr::parse("4198400") {
+            Ok(AddressExpr::Absolute(4_198_400)) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_symbol_relative_addresses() {
+        assert_eq!(resolve("InitDiabloMsg"), Some(0x41_0000));
+        assert_eq!(resolve("InitDiabloMsg+0x10"), Some(0x41_0010));
+        assert_eq!(resolve("InitDiabloMsg - 16"), Some(0x40_FFF0));
+        assert_eq!(resolve("?Foo@@YAXXZ+4"), Some(0x40_1004));
+        assert_eq!(resolve("UnknownFunc+4"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        assert!(AddressExpr::parse("+0x10").is_err());
+        assert!(AddressExpr::parse("InitDiabloMsg+foo").is_err());
+        assert!(AddressExpr::parse("1stFunc").is_err());
+        assert!(AddressExpr::parse("Init Diablo").is_err());
+    }
+
+    #[test]
+    fn splits_csv_lines() {
+        assert_eq!(split_csv_line("a, b ,c").unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(split_csv_line("a,,").unwrap(), vec!["a", "", ""]);
+        assert_eq!(
+            split_csv_line(r#""a, b", "say ""hi""" ,c"#).unwrap(),
+            vec!["a, b", r#"say "hi""#, "c"]
+        );
+        assert!(split_csv_line(r#""open"#).is_err());
+        assert!(split_csv_line(r#""a"b,c"#).is_err());
+    }
+
+    #[test]
+    fn parses_functions_csv() {
+        let funcs = parse_functions_csv(
+            "Name,Addr,Size,Devilution Symbol,Notes\n\
+             InitDiabloMsg,0x410000,0x20,,\n\
+             \n\
+             ?Foo@@YAXXZ,InitDiabloMsg+0x20,,Foo,\"a, b\"\n",
+        )
+        .unwrap();
+        assert_eq!(funcs.len(), 2);
+        assert_eq!(funcs[0].name, "InitDiabloMsg");
+        assert_eq!(funcs[0].addr, 0x41_0000);
+        assert_eq!(funcs[0].size, Some(0x20));
+        assert_eq!(funcs[1].name, "Foo");
+        assert_eq!(funcs[1].addr, 0x41_0020);
+        assert_eq!(funcs[1].size, None);
+    }
+
+    #[test]
+    fn rejects_invalid_functions_csv() {
+        let line_of_error = |content: &str| match parse_functions_csv(content) {
+            Err(ComparerConfigError::InvalidCsv(line, _)) => line,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(line_of_error("name,size\nFoo,4\n"), 1);
+        assert_eq!(line_of_error("name,addr\nFoo,0x10\nBar,\n"), 3);
+        assert_eq!(line_of_error("name,addr,size\nFoo,0x10,big\n"), 2);
+        assert_eq!(line_of_error("name,addr\nFoo,Bar+4\n"), 2);
+    }
+
+    #[test]
+    fn merges_tables_recursively() {
+        let mut base: toml::Value = toml::from_str(
+            "[profile.strict]\nno_imms = true\nno_aliases = true\n\n\
+             [[func]]\nname = \"A\"\naddr = 1\n",
+        )
+        .unwrap();
+        let overlay: toml::Value = toml::from_str(
+            "[profile.strict]\nno_imms = false\n\n\
+             [[func]]\nname = \"B\"\naddr = 2\n",
+        )
+        .unwrap();
+        merge_values(&mut base, overlay);
+
+        let strict = &base["profile"]["strict"];
+        assert_eq!(strict["no_imms"].as_bool(), Some(false));
+        assert_eq!(strict["no_aliases"].as_bool(), Some(true));
+        // arrays are replaced, not appended to
+        let funcs = base["func"].as_array().unwrap();
+        assert_eq!(funcs.len(), 1);
+        assert_eq!(funcs[0]["name"].as_str(), Some("B"));
     }
 }
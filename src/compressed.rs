@@ -0,0 +1,111 @@
+//! Transparent decompression of `.gz` files and zip archives containing a single file, and
+//! gzip compressed output files.
+
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use zip::ZipArchive;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// Whether `read_binary` has to decompress the file.
+pub fn is_compressed(path: &Path) -> Result<bool, Error> {
+    if is_gz(path) {
+        return Ok(true);
+    }
+
+    let mut magic = [0u8; 4];
+    let mut f = File::open(path)?;
+    match f.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == ZIP_MAGIC),
+        Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the whole file, decompressing it first if it ends in `.gz` or is a zip archive.
+pub fn read_binary(path: impl AsRef<Path>) -> Result<Vec<u8>, Error> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+
+    if is_gz(path) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&bytes[..]).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    } else if bytes.starts_with(ZIP_MAGIC) {
+        let mut archive = ZipArchive::new(Cursor::new(bytes))
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        if archive.len() != 1 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} has to contain exactly one file, found {}",
+                    path.to_string_lossy(),
+                    archive.len()
+                ),
+            ));
+        }
+
+        let mut decompressed = Vec::new();
+        archive
+            .by_index(0)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+            .read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    } else {
+        Ok(bytes)
+    }
+}
+
+fn is_gz(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => ext.to_string_lossy().eq_ignore_ascii_case("gz"),
+        None => false,
+    }
+}
+
+/// An output file, written gzip compressed if created with `compress`.
+pub enum OutputFile {
+    Plain(BufWriter<File>),
+    Gz(GzEncoder<BufWriter<File>>),
+}
+
+impl OutputFile {
+    pub fn create(path: impl AsRef<Path>, compress: bool) -> Result<Self, Error> {
+        let writer = BufWriter::new(File::create(path)?);
+        Ok(if compress {
+            OutputFile::Gz(GzEncoder::new(writer, Compression::default()))
+        } else {
+            OutputFile::Plain(writer)
+        })
+    }
+
+    /// Writes the end of the gzip stream and flushes the file, dropping the file instead would
+    /// ignore any errors.
+    pub fn finish(self) -> Result<(), Error> {
+        match self {
+            OutputFile::Plain(mut writer) => writer.flush(),
+            OutputFile::Gz(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match self {
+            OutputFile::Plain(writer) => writer.write(buf),
+            OutputFile::Gz(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            OutputFile::Plain(writer) => writer.flush(),
+            OutputFile::Gz(encoder) => encoder.flush(),
+        }
+    }
+}
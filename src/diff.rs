@@ -0,0 +1,472 @@
+//! Line based comparison of two disassemblies.
+
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffOp {
+    /// Line `.0` of the original matches line `.1` of the compared function.
+    Equal(usize, usize),
+    /// Line `.0` only exists in the original function.
+    Delete(usize),
+    /// Line `.0` only exists in the compared function.
+    Insert(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiffStats {
+    pub orig_lines: usize,
+    pub compare_lines: usize,
+    pub matching_lines: usize,
+}
+
+impl DiffStats {
+    pub fn from_ops(ops: &[DiffOp], orig_lines: usize, compare_lines: usize) -> Self {
+        DiffStats {
+            orig_lines,
+            compare_lines,
+            matching_lines: ops
+                .iter()
+                .filter(|op| matches!(op, DiffOp::Equal(..)))
+                .count(),
+        }
+    }
+
+    pub fn differing_lines(&self) -> usize {
+        self.orig_lines + self.compare_lines - 2 * self.matching_lines
+    }
+
+    /// Percentage of lines shared by both sides, 100.0 for two empty functions.
+    pub fn match_percent(&self) -> f64 {
+        let total = self.orig_lines + self.compare_lines;
+        if total == 0 {
+            100.0
+        } else {
+            (2 * self.matching_lines) as f64 * 100.0 / total as f64
+        }
+    }
+}
+
+/// Aligns both sides using their longest common subsequence.
+pub fn diff_lines<T: PartialEq>(orig: &[T], compare: &[T]) -> Vec<DiffOp> {
+    // strip the common prefix and suffix first, this keeps the table small for
+    // almost matching functions
+    let prefix = orig.iter().zip(compare).take_while(|(a, b)| a == b).count();
+    let suffix = orig[prefix..]
+        .iter()
+        .rev()
+        .zip(compare[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let a = &orig[prefix..orig.len() - suffix];
+    let b = &compare[prefix..compare.len() - suffix];
+
+    // lcs[i][j] = length of the LCS of a[i..] and b[j..]
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops: Vec<DiffOp> = (0..prefix).map(|i| DiffOp::Equal(i, i)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push(DiffOp::Equal(prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if j == b.len()
+            || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+        {
+            ops.push(DiffOp::Delete(prefix + i));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert(prefix + j));
+            j += 1;
+        }
+    }
+    ops.extend((0..suffix).map(|k| DiffOp::Equal(prefix + a.len() + k, prefix + b.len() + k)));
+
+    ops
+}
+
+/// Same as `diff_lines`, but returns `None` if more than `max` lines differ. The length
+/// difference and then the edit distance up to `max` are checked first, so clearly unrelated
+/// functions are never aligned.
+pub fn diff_lines_limited<T: PartialEq>(
+    orig: &[T],
+    compare: &[T],
+    max: usize,
+) -> Option<Vec<DiffOp>> {
+    let len_diff = orig.len().max(compare.len()) - orig.len().min(compare.len());
+    if len_diff > max || !within_edit_distance(orig, compare, max) {
+        return None;
+    }
+
+    Some(diff_lines(orig, compare))
+}
+
+/// Whether at most `max` lines have to be deleted or inserted to turn `a` into `b`, the
+/// number of differing lines of their diff. Follows the furthest reaching paths of Myers'
+/// algorithm for each number of edits, so it takes O((n + m) * max) time.
+fn within_edit_distance<T: PartialEq>(a: &[T], b: &[T], max: usize) -> bool {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = max as isize;
+    // furthest x on diagonal k = x - y, at index k + max + 1
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + max + 1) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            if x >= n && y >= m {
+                return true;
+            }
+            v[idx] = x;
+        }
+    }
+    false
+}
+
+/// A row of a side-by-side view.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Row {
+    Equal(usize, usize),
+    Changed(usize, usize),
+    LeftOnly(usize),
+    RightOnly(usize),
+}
+
+impl Row {
+    /// Swaps the left and the right side.
+    pub fn mirrored(self) -> Self {
+        match self {
+            Row::Equal(l, r) => Row::Equal(r, l),
+            Row::Changed(l, r) => Row::Changed(r, l),
+            Row::LeftOnly(l) => Row::RightOnly(l),
+            Row::RightOnly(r) => Row::LeftOnly(r),
+        }
+    }
+}
+
+/// Converts the diff into side-by-side rows with the original on the left. The deleted and
+/// inserted lines of each changed block are paired up in order.
+pub fn side_by_side_rows(ops: &[DiffOp]) -> Vec<Row> {
+    fn flush(rows: &mut Vec<Row>, deleted: &mut Vec<usize>, inserted: &mut Vec<usize>) {
+        let paired = deleted.len().min(inserted.len());
+        rows.extend((0..paired).map(|k| Row::Changed(deleted[k], inserted[k])));
+        rows.extend(deleted.drain(..).skip(paired).map(Row::LeftOnly));
+        rows.extend(inserted.drain(..).skip(paired).map(Row::RightOnly));
+    }
+
+    let mut rows = Vec::new();
+    let mut deleted = Vec::new();
+    let mut inserted = Vec::new();
+
+    for op in ops {
+        match *op {
+            DiffOp::Equal(i, j) => {
+                flush(&mut rows, &mut deleted, &mut inserted);
+                rows.push(Row::Equal(i, j));
+            }
+            DiffOp::Delete(i) => deleted.push(i),
+            DiffOp::Insert(j) => inserted.push(j),
+        }
+    }
+    flush(&mut rows, &mut deleted, &mut inserted);
+
+    rows
+}
+
+/// Pairs of original and compared lines that were changed.
+pub fn changed_pairs(ops: &[DiffOp]) -> Vec<(usize, usize)> {
+    side_by_side_rows(ops)
+        .into_iter()
+        .filter_map(|row| match row {
+            Row::Changed(i, j) => Some((i, j)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Per line markers of both sides: `=` for matching lines, `~` for changed lines that only
+/// differ in their operands and `!` for all other differences.
+pub fn line_markers(ops: &[DiffOp], orig: &[&str], compare: &[&str]) -> (Vec<char>, Vec<char>) {
+    let mut orig_markers = vec!['!'; orig.len()];
+    let mut compare_markers = vec!['!'; compare.len()];
+
+    for row in side_by_side_rows(ops) {
+        match row {
+            Row::Equal(i, j) => {
+                orig_markers[i] = '=';
+                compare_markers[j] = '=';
+            }
+            Row::Changed(i, j) if split_mnemonic(orig[i]).0 == split_mnemonic(compare[j]).0 => {
+                orig_markers[i] = '~';
+                compare_markers[j] = '~';
+            }
+            _ => {}
+        }
+    }
+
+    (orig_markers, compare_markers)
+}
+
+/// Writes both sides next to each other, marking the rows like `sdiff` does.
+pub fn write_side_by_side(
+    writer: &mut impl Write,
+    rows: &[Row],
+    left: &[&str],
+    right: &[&str],
+) -> std::io::Result<()> {
+    let width = left.iter().map(|line| line.len()).max().unwrap_or(0);
+
+    for row in rows {
+        let (l, marker, r) = match *row {
+            Row::Equal(l, r) => (left[l], ' ', right[r]),
+            Row::Changed(l, r) => (left[l], '|', right[r]),
+            Row::LeftOnly(l) => (left[l], '<', ""),
+            Row::RightOnly(r) => ("", '>', right[r]),
+        };
+        writeln!(writer, "{:width$} {} {}", l, marker, r, width = width)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the diff in the unified format with `context` unchanged lines around each change.
+/// Nothing is written if both sides are equal.
+pub fn write_unified_diff(
+    writer: &mut impl Write,
+    old_name: &str,
+    new_name: &str,
+    old: &[&str],
+    new: &[&str],
+    context: usize,
+) -> std::io::Result<()> {
+    let ops = diff_lines(old, new);
+    let changes: Vec<usize> = (0..ops.len())
+        .filter(|&k| !matches!(ops[k], DiffOp::Equal(..)))
+        .collect();
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    // ranges of ops, merged if their context overlaps
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &k in &changes {
+        let start = k.saturating_sub(context);
+        let end = (k + context + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    writeln!(writer, "--- {}", old_name)?;
+    writeln!(writer, "+++ {}", new_name)?;
+    for (start, end) in hunks {
+        // the lines of both sides preceding the hunk
+        let old_start = ops[..start]
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Insert(..)))
+            .count();
+        let new_start = ops[..start]
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Delete(..)))
+            .count();
+        let hunk = &ops[start..end];
+        let old_count = hunk
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Insert(..)))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Delete(..)))
+            .count();
+
+        writeln!(
+            writer,
+            "@@ -{} +{} @@",
+            hunk_range(old_start, old_count),
+            hunk_range(new_start, new_count)
+        )?;
+        for op in hunk {
+            match *op {
+                DiffOp::Equal(i, _) => writeln!(writer, " {}", old[i])?,
+                DiffOp::Delete(i) => writeln!(writer, "-{}", old[i])?,
+                DiffOp::Insert(j) => writeln!(writer, "+{}", new[j])?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Line range of a hunk header, empty ranges start at the line before them.
+fn hunk_range(preceding: usize, count: usize) -> String {
+    if count == 0 {
+        format!("{},0", preceding)
+    } else {
+        format!("{},{}", preceding + 1, count)
+    }
+}
+
+/// Splits a formatted instruction into its mnemonic and operands.
+pub fn split_mnemonic(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    match line.find(' ') {
+        Some(pos) => (&line[..pos], line[pos..].trim_start()),
+        None => (line, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DiffOp::*;
+
+    #[test]
+    fn diff_lines_of_equal_sides() {
+        assert_eq!(
+            diff_lines(&["a", "b", "c"], &["a", "b", "c"]),
+            vec![Equal(0, 0), Equal(1, 1), Equal(2, 2)]
+        );
+        assert_eq!(diff_lines::<&str>(&[], &[]), vec![]);
+    }
+
+    #[test]
+    fn diff_lines_of_one_empty_side() {
+        assert_eq!(diff_lines(&["a", "b"], &[]), vec![Delete(0), Delete(1)]);
+        assert_eq!(diff_lines(&[], &["a", "b"]), vec![Insert(0), Insert(1)]);
+    }
+
+    #[test]
+    fn diff_lines_aligns_the_longest_common_subsequence() {
+        let ops = diff_lines(&["a", "b", "c", "d", "e"], &["a", "c", "x", "d", "e"]);
+        assert_eq!(
+            ops,
+            vec![
+                Equal(0, 0),
+                Delete(1),
+                Equal(2, 1),
+                Insert(2),
+                Equal(3, 3),
+                Equal(4, 4)
+            ]
+        );
+        let stats = DiffStats::from_ops(&ops, 5, 5);
+        assert_eq!(stats.matching_lines, 4);
+        assert_eq!(stats.differing_lines(), 2);
+        assert!((stats.match_percent() - 80.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn diff_lines_deletes_before_inserting() {
+        assert_eq!(
+            diff_lines(&["a", "b", "d"], &["a", "c", "d"]),
+            vec![Equal(0, 0), Delete(1), Insert(1), Equal(2, 2)]
+        );
+    }
+
+    #[test]
+    fn match_percent_of_empty_functions() {
+        assert_eq!(DiffStats::from_ops(&[], 0, 0).match_percent(), 100.0);
+    }
+
+    #[test]
+    fn diff_lines_limited_by_the_differing_lines() {
+        let orig = ["a", "b", "c", "d"];
+        let compare = ["a", "x", "c", "y"];
+        // the diff deletes and inserts both changed lines
+        assert!(diff_lines_limited(&orig, &compare, 4).is_some());
+        assert!(diff_lines_limited(&orig, &compare, 3).is_none());
+        assert!(diff_lines_limited(&orig, &orig, 0).is_some());
+        assert!(diff_lines_limited(&orig, &orig[..1], 2).is_none());
+    }
+
+    #[test]
+    fn edit_distance_matches_the_diff() {
+        let sides: &[&[&str]] = &[
+            &[],
+            &["a"],
+            &["a", "b", "c"],
+            &["c", "b", "a"],
+            &["a", "c", "b", "a", "c"],
+            &["b", "b", "c", "a"],
+        ];
+        for a in sides {
+            for b in sides {
+                let ops = diff_lines(a, b);
+                let differing = DiffStats::from_ops(&ops, a.len(), b.len()).differing_lines();
+                assert!(within_edit_distance(a, b, differing), "{:?} {:?}", a, b);
+                if differing > 0 {
+                    assert!(
+                        !within_edit_distance(a, b, differing - 1),
+                        "{:?} {:?}",
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    fn unified_diff(old: &[&str], new: &[&str], context: usize) -> String {
+        let mut out = Vec::new();
+        write_unified_diff(&mut out, "old", "new", old, new, context).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn unified_diff_of_equal_sides_is_empty() {
+        assert_eq!(unified_diff(&["a", "b"], &["a", "b"], 3), "");
+    }
+
+    #[test]
+    fn unified_diff_with_context() {
+        let old = ["a", "b", "c", "d", "e", "f", "g"];
+        let new = ["a", "b", "c", "x", "e", "f", "g"];
+        assert_eq!(
+            unified_diff(&old, &new, 1),
+            "--- old\n+++ new\n@@ -3,3 +3,3 @@\n c\n-d\n+x\n e\n"
+        );
+    }
+
+    #[test]
+    fn unified_diff_merges_overlapping_hunks() {
+        let old = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let new = ["x", "b", "c", "d", "e", "f", "g", "y"];
+        assert_eq!(
+            unified_diff(&old, &new, 1),
+            "--- old\n+++ new\n@@ -1,2 +1,2 @@\n-a\n+x\n b\n@@ -7,2 +7,2 @@\n g\n-h\n+y\n"
+        );
+        assert_eq!(unified_diff(&old, &new, 3).matches("@@ -").count(), 1);
+    }
+
+    #[test]
+    fn unified_diff_of_empty_ranges() {
+        assert_eq!(
+            unified_diff(&[], &["a"], 3),
+            "--- old\n+++ new\n@@ -0,0 +1,1 @@\n+a\n"
+        );
+        assert_eq!(
+            unified_diff(&["a", "b"], &["a"], 0),
+            "--- old\n+++ new\n@@ -2,1 +1,0 @@\n-b\n"
+        );
+    }
+}
@@ -1,32 +1,245 @@
 use std::any::Any;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CStr;
 use std::io::{Error as IoError, Write};
 use std::os::raw::c_char;
 
+use regex::Regex;
+use serde_derive::Serialize;
 use zydis::gen::*;
-use zydis::{Buffer, Decoder, Formatter, ZydisResult};
+use zydis::{Buffer, Decoder, Formatter, ZydisRegisterMethods, ZydisResult};
 
 use super::hexformat::*;
+use super::mnemonics::canonical_mnemonic;
+use super::LineEnding;
 
 #[derive(Debug, Clone)]
 pub struct DisasmOpts {
     pub print_adresses: bool,
     pub show_mem_disp: bool,
     pub show_imms: bool,
+    pub addr_format: AddrFormat,
+    pub line_ending: LineEnding,
+    pub resolve_symbols: bool,
+    /// Matches removed from the resolved symbol names.
+    pub strip_symbols: Option<Regex>,
+    pub show_blocks: bool,
+    pub annotate_xrefs: bool,
+    /// Write undecodable bytes as `db` and continue after them instead of stopping.
+    pub skip_data: bool,
+    /// Write the byte length of every instruction before it.
+    pub show_encoding_size: bool,
+    /// Print aliased mnemonics in one canonical form, see `mnemonics`.
+    pub no_aliases: bool,
+    /// Write a header before the `int3`/`nop` padding following the final `ret`.
+    pub show_padding: bool,
+    /// Append whether the explicit operands are read or written.
+    pub show_access: bool,
+    /// Append immediates made of printable ASCII characters as a string.
+    pub annotate_chars: bool,
+    /// Separate the mnemonic and the operands with a tab, so the operands line up.
+    pub align_tabs: bool,
+    /// Replace absolute memory addresses that are known variables with their names.
+    pub resolve_globals: bool,
+}
+
+impl DisasmOpts {
+    /// Shows all immediates and displacements, without symbols, annotations or other changes.
+    pub fn raw() -> Self {
+        DisasmOpts {
+            print_adresses: false,
+            show_mem_disp: true,
+            show_imms: true,
+            addr_format: AddrFormat::default(),
+            line_ending: LineEnding::Lf,
+            resolve_symbols: false,
+            strip_symbols: None,
+            show_blocks: false,
+            annotate_xrefs: false,
+            skip_data: false,
+            show_encoding_size: false,
+            no_aliases: false,
+            show_padding: false,
+            show_access: false,
+            annotate_chars: false,
+            align_tabs: false,
+            resolve_globals: false,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum DisasmError {
     IoError(IoError),
     ZydisError(ZydisStatusCode),
+    /// Formatting the instruction at `address` failed, `bytes` starts a few bytes before it.
+    FormatError {
+        status: ZydisStatusCode,
+        address: u64,
+        bytes: Vec<u8>,
+    },
+    /// Decoding failed `offset` bytes into the function, `bytes` are the ones following it.
+    DecodeError {
+        status: ZydisStatusCode,
+        offset: usize,
+        bytes: Vec<u8>,
+    },
 }
 
-pub fn write_disasm(
-    writer: &mut impl Write,
+/// Bytes shown before and after an instruction that couldn't be formatted or decoded.
+const ERROR_CONTEXT_BYTES: usize = 4;
+
+/// Words Zydis prints in front of the mnemonic.
+const PREFIXES: &[&str] = &[
+    "lock", "rep", "repe", "repne", "repz", "repnz", "xacquire", "xrelease", "bnd",
+];
+
+/// Human readable description of a zydis status code.
+pub fn zydis_status_description(status: ZydisStatusCode) -> &'static str {
+    match status {
+        ZYDIS_STATUS_INVALID_PARAMETER => "invalid parameter passed to zydis",
+        ZYDIS_STATUS_INVALID_OPERATION => "invalid operation, e.g. an unsupported machine mode",
+        ZYDIS_STATUS_INSUFFICIENT_BUFFER_SIZE => "the formatted instruction is too long",
+        ZYDIS_STATUS_NO_MORE_DATA => "the function ends in the middle of an instruction",
+        ZYDIS_STATUS_DECODING_ERROR => "invalid instruction bytes",
+        ZYDIS_STATUS_INSTRUCTION_TOO_LONG => "instruction longer than 15 bytes",
+        ZYDIS_STATUS_BAD_REGISTER => "invalid register operand",
+        ZYDIS_STATUS_ILLEGAL_LOCK => "lock prefix on an instruction that doesn't allow it",
+        ZYDIS_STATUS_ILLEGAL_LEGACY_PFX | ZYDIS_STATUS_ILLEGAL_REX => "illegal prefix",
+        _ => "unknown error",
+    }
+}
+
+pub fn print_error(e: &DisasmError) {
+    println!("{}", error_message(e));
+}
+
+pub fn error_message(e: &DisasmError) -> String {
+    match e {
+        DisasmError::IoError(e) => format!("IO error while writing the disassembly: {:#?}", e),
+        DisasmError::ZydisError(status) => format!(
+            "Zydis disassembly engine error: {} (status {:#X})",
+            zydis_status_description(*status),
+            status
+        ),
+        DisasmError::FormatError {
+            status,
+            address,
+            bytes,
+        } => format!(
+            "Zydis disassembly engine error at {:#X}: {} (status {:#X}), bytes around it: {}",
+            address,
+            zydis_status_description(*status),
+            status,
+            hex_bytes(bytes)
+        ),
+        DisasmError::DecodeError {
+            status,
+            offset,
+            bytes,
+        } => format!(
+            "Zydis disassembly engine error at offset {:#X} of the function: {} \
+             (status {:#X}), next bytes: {}\n\
+             The address may be wrong, or --skipdata decodes such bytes as data.",
+            offset,
+            zydis_status_description(*status),
+            status,
+            hex_bytes(bytes)
+        ),
+    }
+}
+
+/// The bytes as hex separated by spaces, `8B EC`.
+pub fn hex_bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    bytes.join(" ")
+}
+
+/// Zeroes out the masked byte ranges, so both sides disassemble to the same text there.
+pub fn mask_bytes(bytes: &mut [u8], masks: &[(usize, usize)]) {
+    for &(start, end) in masks {
+        let end = end.min(bytes.len());
+        if start < end {
+            for b in &mut bytes[start..end] {
+                *b = 0;
+            }
+        }
+    }
+}
+
+/// Function names keyed by their virtual address.
+pub type SymbolMap = HashMap<u64, String>;
+
+/// A single formatted instruction.
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    /// The address of the instruction.
+    pub start: u64,
+    /// The address after the instruction, as reported by the zydis instruction iterator.
+    pub ip: u64,
+    pub text: String,
+    /// Target of a relative jump, calls are left out.
+    pub branch_target: Option<u64>,
+    /// Target of a relative call.
+    pub call_target: Option<u64>,
+    /// A label inserted by `jumps_as_labels`, no instruction of the function.
+    pub is_label: bool,
+    pub detail: InstructionDetail,
+}
+
+/// The registers an instruction accesses, including the implicit ones, and what kind of
+/// control flow it is.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InstructionDetail {
+    pub regs_read: Vec<&'static str>,
+    pub regs_written: Vec<&'static str>,
+    /// `jump`, `call`, `ret` or `interrupt`.
+    pub groups: Vec<&'static str>,
+}
+
+/// The instructions decoded from the bytes of a function, up to the first bytes that aren't
+/// valid instructions.
+#[derive(Debug)]
+pub struct Disassembly {
+    pub insns: Vec<Instruction>,
+    /// The `DecodeError` that stopped the disassembly before the end of the bytes.
+    pub decode_error: Option<DisasmError>,
+}
+
+impl Disassembly {
+    /// The decoded instructions, printing a warning about `what` if they stop early.
+    pub fn into_insns(self, what: &str) -> Vec<Instruction> {
+        if let Some(e) = &self.decode_error {
+            println!(
+                "WARN: Stopped disassembling {} early. {}",
+                what,
+                error_message(e)
+            );
+        }
+        self.insns
+    }
+}
+
+/// Like `disasm_until_error`, printing a warning if the disassembly stops early.
+pub fn disasm(
     bytes: &[u8],
     disasm_opts: &mut DisasmOpts,
     offset: u64,
-) -> Result<(), DisasmError> {
+    masks: &[(usize, usize)],
+    symbols: &SymbolMap,
+) -> Result<Vec<Instruction>, DisasmError> {
+    disasm_until_error(bytes, disasm_opts, offset, masks, symbols)
+        .map(|disassembly| disassembly.into_insns("the function"))
+}
+
+pub fn disasm_until_error(
+    bytes: &[u8],
+    disasm_opts: &mut DisasmOpts,
+    offset: u64,
+    masks: &[(usize, usize)],
+    symbols: &SymbolMap,
+) -> Result<Disassembly, DisasmError> {
     let mut buf = [0u8; 255];
 
     let mut formatter =
@@ -50,23 +263,533 @@ pub fn write_disasm(
     let decoder = Decoder::new(ZYDIS_MACHINE_MODE_LEGACY_32, ZYDIS_ADDRESS_WIDTH_32)
         .map_err(DisasmError::ZydisError)?;
 
-    for (insn, ip) in decoder.instruction_iterator(bytes, offset) {
+    let mut insns = Vec::new();
+    let mut decode_error = None;
+    let mut insn_start = 0;
+    while insn_start < bytes.len() {
+        let insn = match decoder.decode(&bytes[insn_start..], offset + insn_start as u64) {
+            Ok(Some(insn)) => insn,
+            Err(_) if disasm_opts.skip_data => {
+                insns.push(Instruction {
+                    start: offset + insn_start as u64,
+                    ip: offset + insn_start as u64 + 1,
+                    text: format!("db {:#04X}", bytes[insn_start]),
+                    branch_target: None,
+                    call_target: None,
+                    is_label: false,
+                    detail: InstructionDetail::default(),
+                });
+                insn_start += 1;
+                continue;
+            }
+            // only the last instruction can be cut off by the end of the function
+            Ok(None) | Err(ZYDIS_STATUS_NO_MORE_DATA) => break,
+            Err(status) => {
+                let context_end = (insn_start + ERROR_CONTEXT_BYTES).min(bytes.len());
+                decode_error = Some(DisasmError::DecodeError {
+                    status,
+                    offset: insn_start,
+                    bytes: bytes[insn_start..context_end].to_vec(),
+                });
+                break;
+            }
+        };
+        let length = insn.length as usize;
+        let ip = offset + (insn_start + length) as u64;
         formatter
             .format_instruction_raw(&insn, &mut buf, Some(disasm_opts))
-            .map_err(DisasmError::ZydisError)?;
+            .map_err(|status| {
+                let context_start = insn_start.saturating_sub(ERROR_CONTEXT_BYTES);
+                let context_end = (insn_start + length + ERROR_CONTEXT_BYTES).min(bytes.len());
+                DisasmError::FormatError {
+                    status,
+                    address: ip - length as u64,
+                    bytes: bytes[context_start..context_end].to_vec(),
+                }
+            })?;
+
+        let mut insn_str = unsafe { CStr::from_ptr(buf.as_ptr() as *const c_char) }
+            .to_string_lossy()
+            .into_owned();
+        if disasm_opts.resolve_symbols {
+            insn_str = resolve_symbols(&insn, ip, insn_str, disasm_opts, symbols);
+        }
+        if disasm_opts.resolve_globals {
+            insn_str = resolve_globals(&insn, insn_str, disasm_opts, symbols);
+        }
+        if disasm_opts.no_aliases {
+            insn_str = canonical_mnemonic(&insn_str);
+        }
+        if disasm_opts.annotate_chars {
+            if let Some(chars) = ascii_immediate(&insn) {
+                insn_str = format!("{} ; \"{}\"", insn_str, chars);
+            }
+        }
+        if disasm_opts.show_access {
+            if let Some(access) = operand_access(&insn, &insn_str) {
+                insn_str = format!("{} ; {}", insn_str, access);
+            }
+        }
+
+        let masked = masks
+            .iter()
+            .any(|&(start, end)| start < insn_start + length && insn_start < end);
+        let text = if masked {
+            format!("{} ; ??", insn_str)
+        } else {
+            insn_str
+        };
+
+        let relative_target = insn.operands[..insn.operandCount as usize]
+            .iter()
+            .find(|op| {
+                ZydisOperandTypes::from(op.type_) == ZYDIS_OPERAND_TYPE_IMMEDIATE
+                    && op.imm.isRelative != 0
+            })
+            .map(|op| (ip as i64 + *unsafe { op.imm.value.s.as_ref() }) as u64);
+        // 0xE8 = CALL rel32
+        let (branch_target, call_target) = if insn.opcode == 0xE8 {
+            (None, relative_target)
+        } else {
+            (relative_target, None)
+        };
+
+        insns.push(Instruction {
+            start: ip - length as u64,
+            ip,
+            text,
+            branch_target,
+            call_target,
+            is_label: false,
+            detail: instruction_detail(&insn),
+        });
+        insn_start += length;
+    }
+
+    Ok(Disassembly {
+        insns,
+        decode_error,
+    })
+}
+
+/// The characters of an immediate of at least two bytes that only consists of printable ASCII
+/// characters, followed by zero bytes at most.
+fn ascii_immediate(insn: &ZydisDecodedInstruction) -> Option<String> {
+    let op = insn.operands[..insn.operandCount as usize]
+        .iter()
+        .find(|op| {
+            ZydisOperandTypes::from(op.type_) == ZYDIS_OPERAND_TYPE_IMMEDIATE
+                && op.imm.isRelative == 0
+        })?;
+    let value = *unsafe { op.imm.value.u.as_ref() };
+    let bytes = &value.to_le_bytes()[..(op.size as usize / 8).min(8)];
+
+    let len = bytes
+        .iter()
+        .rposition(|&b| b != 0)
+        .map_or(0, |last| last + 1);
+    let chars = &bytes[..len];
+    if chars.len() < 2 || !chars.iter().all(|&b| b.is_ascii_graphic() || b == b' ') {
+        return None;
+    }
+    Some(
+        chars
+            .iter()
+            .map(|&b| b as char)
+            .collect::<String>()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\""),
+    )
+}
+
+/// Describes the access to the explicit operands like `eax=W, dword ptr [ebp-0x04]=R`,
+/// conditional accesses are put in parentheses. Immediates are left out.
+fn operand_access(insn: &ZydisDecodedInstruction, text: &str) -> Option<String> {
+    let explicit: Vec<&ZydisDecodedOperand> = insn.operands[..insn.operandCount as usize]
+        .iter()
+        .filter(|op| {
+            ZydisOperandVisibilities::from(op.visibility) == ZYDIS_OPERAND_VISIBILITY_EXPLICIT
+        })
+        .collect();
+    if explicit.is_empty() {
+        return None;
+    }
+
+    let mut rest = text;
+    while let Some(pos) = rest.find(' ') {
+        let word = &rest[..pos];
+        rest = &rest[pos + 1..];
+        if !PREFIXES.contains(&word) {
+            break;
+        }
+    }
+    // resolved names can contain commas, those can't be matched to the operands
+    let operand_texts: Vec<&str> = rest.split(", ").collect();
+    if operand_texts.len() != explicit.len() {
+        return None;
+    }
+
+    let access: Vec<String> = explicit
+        .iter()
+        .zip(operand_texts)
+        .filter(|(op, _)| ZydisOperandTypes::from(op.type_) != ZYDIS_OPERAND_TYPE_IMMEDIATE)
+        .map(|(op, text)| {
+            let action = match ZydisOperandActions::from(op.action) {
+                ZYDIS_OPERAND_ACTION_READ => "R",
+                ZYDIS_OPERAND_ACTION_WRITE => "W",
+                ZYDIS_OPERAND_ACTION_READWRITE => "RW",
+                ZYDIS_OPERAND_ACTION_CONDREAD => "(R)",
+                ZYDIS_OPERAND_ACTION_CONDWRITE => "(W)",
+                ZYDIS_OPERAND_ACTION_READ_CONDWRITE => "R(W)",
+                ZYDIS_OPERAND_ACTION_CONDREAD_WRITE => "(R)W",
+                _ => "?",
+            };
+            format!("{}={}", text, action)
+        })
+        .collect();
+    if access.is_empty() {
+        None
+    } else {
+        Some(access.join(", "))
+    }
+}
+
+fn instruction_detail(insn: &ZydisDecodedInstruction) -> InstructionDetail {
+    let mut detail = InstructionDetail::default();
+    let add = |regs: &mut Vec<&'static str>, reg: ZydisRegister| {
+        let reg = ZydisRegisters::from(reg);
+        if reg == ZYDIS_REGISTER_NONE {
+            return;
+        }
+        if let Some(name) = reg.get_string() {
+            if !regs.contains(&name) {
+                regs.push(name);
+            }
+        }
+    };
+    for op in &insn.operands[..insn.operandCount as usize] {
+        match ZydisOperandTypes::from(op.type_) {
+            ZYDIS_OPERAND_TYPE_REGISTER => {
+                let action = ZydisOperandActions::from(op.action);
+                if action != ZYDIS_OPERAND_ACTION_WRITE && action != ZYDIS_OPERAND_ACTION_CONDWRITE
+                {
+                    add(&mut detail.regs_read, op.reg.value);
+                }
+                if action != ZYDIS_OPERAND_ACTION_READ && action != ZYDIS_OPERAND_ACTION_CONDREAD {
+                    add(&mut detail.regs_written, op.reg.value);
+                }
+            }
+            // the address registers are read, whatever happens to the memory
+            ZYDIS_OPERAND_TYPE_MEMORY => {
+                add(&mut detail.regs_read, op.mem.base);
+                add(&mut detail.regs_read, op.mem.index);
+            }
+            _ => {}
+        }
+    }
+
+    detail.groups = match ZydisInstructionCategories::from(insn.meta.category) {
+        ZYDIS_CATEGORY_COND_BR | ZYDIS_CATEGORY_UNCOND_BR => vec!["jump"],
+        ZYDIS_CATEGORY_CALL => vec!["call"],
+        ZYDIS_CATEGORY_RET => vec!["ret"],
+        ZYDIS_CATEGORY_INTERRUPT => vec!["interrupt"],
+        _ => Vec::new(),
+    };
+    detail
+}
+
+/// Replaces call targets and immediate operands that are known function addresses with the
+/// function names.
+fn resolve_symbols(
+    insn: &ZydisDecodedInstruction,
+    ip: u64,
+    mut text: String,
+    disasm_opts: &DisasmOpts,
+    symbols: &SymbolMap,
+) -> String {
+    let operands = &insn.operands[..insn.operandCount as usize];
+    for op in operands
+        .iter()
+        .filter(|op| ZydisOperandTypes::from(op.type_) == ZYDIS_OPERAND_TYPE_IMMEDIATE)
+    {
+        if op.imm.isRelative != 0 {
+            // 0xE8 = CALL rel32, relative to the address after the instruction
+            if insn.opcode == 0xE8 {
+                let target = (ip as i64 + *unsafe { op.imm.value.s.as_ref() }) as u64;
+                if let Some(name) = symbols.get(&target) {
+                    text = text.replacen("<imm_fn>", &symbol_name(name, disasm_opts), 1);
+                }
+            }
+        } else {
+            let value = *unsafe { op.imm.value.u.as_ref() };
+            if let Some(name) = symbols.get(&value) {
+                let imm = if disasm_opts.show_imms {
+                    format!("{:#X}", value)
+                } else {
+                    format!("<imm{}>", op.size)
+                };
+                let name = symbol_name(name, disasm_opts);
+                text = text.replacen(&imm, &format!("offset {}", name), 1);
+            }
+        }
+    }
+    text
+}
+
+/// Replaces absolute memory addresses that are known variables with the variable names, e.g.
+/// `mov eax, dword ptr [0x48A3C0]` with `mov eax, dword ptr [gbMaxPlayers]`.
+fn resolve_globals(
+    insn: &ZydisDecodedInstruction,
+    mut text: String,
+    disasm_opts: &DisasmOpts,
+    symbols: &SymbolMap,
+) -> String {
+    let operands = &insn.operands[..insn.operandCount as usize];
+    for op in operands.iter().filter(|op| {
+        ZydisOperandTypes::from(op.type_) == ZYDIS_OPERAND_TYPE_MEMORY
+            && ZydisRegisters::from(op.mem.base) == ZYDIS_REGISTER_NONE
+            && ZydisRegisters::from(op.mem.index) == ZYDIS_REGISTER_NONE
+    }) {
+        // formatted by `format_addrs`
+        let addr = format!("[{:#X}]", op.mem.disp.value);
+        if let Some(name) = symbols.get(&(op.mem.disp.value as u64)) {
+            let name = format!("[{}]", symbol_name(name, disasm_opts));
+            text = text.replacen(&addr, &name, 1);
+        }
+    }
+    text
+}
+
+fn symbol_name<'a>(name: &'a str, disasm_opts: &DisasmOpts) -> Cow<'a, str> {
+    match &disasm_opts.strip_symbols {
+        Some(regex) => regex.replace_all(name, ""),
+        None => Cow::Borrowed(name),
+    }
+}
+
+/// Replaces the relative address of jumps within the function with the number of instructions
+/// to their target, e.g. `jnz $-0x1A` with `jnz @-5`. `offset` is the address of the first
+/// instruction, jumps leaving the function or into the middle of an instruction are kept.
+pub fn jumps_by_index(insns: &mut [Instruction], offset: u64) {
+    let starts: HashMap<u64, usize> = insns
+        .iter()
+        .enumerate()
+        .map(|(k, _)| (if k == 0 { offset } else { insns[k - 1].ip }, k))
+        .collect();
+
+    for (k, insn) in insns.iter_mut().enumerate() {
+        let target = match insn.branch_target.and_then(|target| starts.get(&target)) {
+            Some(&target) => target,
+            None => continue,
+        };
+        replace_jump_target(&mut insn.text, &format!("@{:+}", target as i64 - k as i64));
+    }
+}
 
-        let insn_str = unsafe { CStr::from_ptr(buf.as_ptr() as *const c_char) }.to_string_lossy();
+/// Replaces the relative address of jumps within the function with generated labels, e.g.
+/// `jnz $-0x1A` with `jnz loc_2`, and inserts a `loc_2:` line before each target. The labels are
+/// numbered in address order, so they match if the control flow does.
+pub fn jumps_as_labels(insns: &mut Vec<Instruction>) {
+    let starts: HashSet<u64> = insns.iter().map(|insn| insn.start).collect();
+    let mut targets: Vec<u64> = insns
+        .iter()
+        .filter_map(|insn| insn.branch_target)
+        .filter(|target| starts.contains(target))
+        .collect();
+    targets.sort();
+    targets.dedup();
+    let labels: HashMap<u64, String> = targets
+        .into_iter()
+        .enumerate()
+        .map(|(k, target)| (target, format!("loc_{}", k + 1)))
+        .collect();
 
+    let mut labeled = Vec::with_capacity(insns.len() + labels.len());
+    for mut insn in insns.drain(..) {
+        if let Some(label) = labels.get(&insn.start) {
+            // an empty instruction, starting and ending at the target
+            labeled.push(Instruction {
+                start: insn.start,
+                ip: insn.start,
+                text: format!("{}:", label),
+                branch_target: None,
+                call_target: None,
+                is_label: true,
+                detail: InstructionDetail::default(),
+            });
+        }
+        if let Some(label) = insn.branch_target.and_then(|target| labels.get(&target)) {
+            replace_jump_target(&mut insn.text, label);
+        }
+        labeled.push(insn);
+    }
+    *insns = labeled;
+}
+
+/// Replaces the `$+0x12` operand of a formatted relative jump.
+pub fn replace_jump_target(text: &mut String, replacement: &str) {
+    if let Some(start) = text.find('$') {
+        let end = text[start..]
+            .find(' ')
+            .map_or(text.len(), |len| start + len);
+        text.replace_range(start..end, replacement);
+    }
+}
+
+/// Number of trailing `int3`/`nop` instructions following the final `ret`.
+pub fn trailing_padding_len(insns: &[Instruction]) -> usize {
+    let padding = insns
+        .iter()
+        .rev()
+        .take_while(|insn| {
+            let mnemonic = insn.text.split(' ').next().unwrap_or("");
+            mnemonic == "int3" || mnemonic == "nop"
+        })
+        .count();
+
+    match insns.len().checked_sub(padding + 1) {
+        Some(ret) if insns[ret].text.starts_with("ret") => padding,
+        _ => 0,
+    }
+}
+
+/// Indices of the instructions starting a new basic block, i.e. following a jump or `ret`, or
+/// being the target of a jump within the function. The first instruction is left out.
+pub fn block_starts(insns: &[Instruction]) -> Vec<usize> {
+    let targets: HashSet<u64> = insns.iter().filter_map(|insn| insn.branch_target).collect();
+    (1..insns.len())
+        .filter(|&k| {
+            let prev = &insns[k - 1];
+            // a label already starts the block
+            if prev.is_label {
+                return false;
+            }
+            let mnemonic = prev.text.split(' ').next().unwrap_or("");
+            // instructions start where the previous one ends
+            mnemonic.starts_with('j') || mnemonic.starts_with("ret") || targets.contains(&prev.ip)
+        })
+        .collect()
+}
+
+pub fn write_instructions(
+    writer: &mut impl Write,
+    insns: &[Instruction],
+    disasm_opts: &DisasmOpts,
+) -> Result<(), DisasmError> {
+    write_marked_instructions(writer, insns, &[], disasm_opts)
+}
+
+/// Like `write_instructions`, starting each line with the marker of its instruction. Lines
+/// without a marker get a space, no markers leave out the column.
+pub fn write_marked_instructions(
+    writer: &mut impl Write,
+    insns: &[Instruction],
+    markers: &[char],
+    disasm_opts: &DisasmOpts,
+) -> Result<(), DisasmError> {
+    let block_starts = if disasm_opts.show_blocks {
+        block_starts(insns)
+    } else {
+        Vec::new()
+    };
+    let padding_start = if disasm_opts.show_padding {
+        Some(insns.len() - trailing_padding_len(insns)).filter(|&start| start < insns.len())
+    } else {
+        None
+    };
+
+    let mut xrefs: HashMap<u64, Vec<u64>> = HashMap::new();
+    if disasm_opts.annotate_xrefs {
+        for insn in insns {
+            if let Some(target) = insn.branch_target {
+                xrefs.entry(target).or_default().push(insn.start);
+            }
+        }
+    }
+
+    for (k, insn) in insns.iter().enumerate() {
+        if padding_start == Some(k) {
+            writeln!(writer, "; --- padding ---").map_err(DisasmError::IoError)?;
+        } else if block_starts.contains(&k) {
+            writeln!(writer, "; ----").map_err(DisasmError::IoError)?;
+        }
+        if let Some(sources) = xrefs.remove(&insn.start) {
+            let sources: Vec<String> = sources
+                .iter()
+                .map(|&source| disasm_opts.addr_format.format(source))
+                .collect();
+            writeln!(writer, "; xref from {}", sources.join(", ")).map_err(DisasmError::IoError)?;
+        }
+        let size = if disasm_opts.show_encoding_size {
+            format!("{:>2}  ", insn.ip - insn.start)
+        } else {
+            String::new()
+        };
+        let text = if disasm_opts.align_tabs {
+            tab_after_mnemonic(&insn.text)
+        } else {
+            Cow::Borrowed(insn.text.as_ref())
+        };
+        let marker = if markers.is_empty() {
+            String::new()
+        } else {
+            format!("{} ", markers.get(k).cloned().unwrap_or(' '))
+        };
         if disasm_opts.print_adresses {
-            writeln!(writer, "{:X}: {}", ip, insn_str).map_err(DisasmError::IoError)?;
+            writeln!(
+                writer,
+                "{}{}: {}{}",
+                marker,
+                disasm_opts.addr_format.format(insn.ip),
+                size,
+                text
+            )
+            .map_err(DisasmError::IoError)?;
         } else {
-            writeln!(writer, "{}", insn_str).map_err(DisasmError::IoError)?;
+            writeln!(writer, "{}{}{}", marker, size, text).map_err(DisasmError::IoError)?;
         }
     }
 
     Ok(())
 }
 
+/// Replaces the space between the mnemonic, including its prefixes, and the operands with a tab.
+/// A tab instead of padding to the longest mnemonic keeps the lines of both output files the
+/// same, so they still diff line by line.
+fn tab_after_mnemonic<'a>(text: &'a str) -> Cow<'a, str> {
+    let mut mnemonic_end = 0;
+    while let Some(len) = text[mnemonic_end..].find(' ') {
+        let word = &text[mnemonic_end..mnemonic_end + len];
+        mnemonic_end += len;
+        if !PREFIXES.contains(&word) {
+            let mut aligned = text.to_owned();
+            aligned.replace_range(mnemonic_end..=mnemonic_end, "\t");
+            return Cow::Owned(aligned);
+        }
+        mnemonic_end += 1;
+    }
+    Cow::Borrowed(text)
+}
+
+/// Writes the instructions, only the first `max_lines` of them if given.
+pub fn write_disasm(
+    writer: &mut impl Write,
+    insns: &[Instruction],
+    disasm_opts: &DisasmOpts,
+    max_lines: Option<usize>,
+) -> Result<(), DisasmError> {
+    let (insns, truncated) = match max_lines {
+        Some(max) if insns.len() > max => (&insns[..max], true),
+        _ => (insns, false),
+    };
+
+    write_instructions(writer, insns, disasm_opts)?;
+    if truncated {
+        writeln!(writer, "; ... truncated ...").map_err(DisasmError::IoError)?;
+    }
+    Ok(())
+}
+
 fn format_addrs(
     _: &Formatter,
     buf: &mut Buffer,
@@ -141,3 +864,86 @@ fn void_format_imms(
     buf.append(&format!("<imm{}>", op.size))?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insns(lines: &[&str]) -> Vec<Instruction> {
+        lines
+            .iter()
+            .map(|text| Instruction {
+                start: 0,
+                ip: 0,
+                text: text.to_string(),
+                branch_target: None,
+                call_target: None,
+                is_label: false,
+                detail: InstructionDetail::default(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trailing_padding_after_ret() {
+        assert_eq!(
+            trailing_padding_len(&insns(&["push ebp", "ret", "int3", "int3", "nop"])),
+            3
+        );
+        assert_eq!(
+            trailing_padding_len(&insns(&["pop ebp", "ret 8", "int3"])),
+            1
+        );
+        assert_eq!(trailing_padding_len(&insns(&["pop ebp", "ret"])), 0);
+    }
+
+    #[test]
+    fn no_trailing_padding_without_ret() {
+        assert_eq!(trailing_padding_len(&insns(&["call 0x401000", "int3"])), 0);
+        assert_eq!(trailing_padding_len(&insns(&["int3", "int3"])), 0);
+        assert_eq!(trailing_padding_len(&[]), 0);
+    }
+
+    #[test]
+    fn tab_after_the_mnemonic() {
+        assert_eq!(tab_after_mnemonic("mov eax, ecx"), "mov\teax, ecx");
+        assert_eq!(
+            tab_after_mnemonic("rep movsd dword ptr es:[edi], dword ptr [esi]"),
+            "rep movsd\tdword ptr es:[edi], dword ptr [esi]"
+        );
+        assert_eq!(
+            tab_after_mnemonic("lock xadd [ecx], eax"),
+            "lock xadd\t[ecx], eax"
+        );
+    }
+
+    #[test]
+    fn no_tab_without_operands() {
+        assert!(matches!(tab_after_mnemonic("ret"), Cow::Borrowed("ret")));
+        assert!(matches!(
+            tab_after_mnemonic("rep stosb"),
+            Cow::Borrowed("rep stosb")
+        ));
+    }
+
+    #[test]
+    fn markers_in_their_own_column() {
+        let opts = DisasmOpts {
+            show_padding: true,
+            align_tabs: true,
+            ..DisasmOpts::raw()
+        };
+        let mut out = Vec::new();
+        write_marked_instructions(
+            &mut out,
+            &insns(&["push ebp", "ret", "int3"]),
+            &['=', '!'],
+            &opts,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "= push\tebp\n! ret\n; --- padding ---\n  int3\n"
+        );
+    }
+}
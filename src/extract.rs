@@ -0,0 +1,84 @@
+//! Extracting the bytes of a function of the debug binary, e.g. for bug reports.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use serde_derive::Serialize;
+
+use self::ExtractError::*;
+use super::pdb::*;
+
+#[derive(Debug)]
+pub struct ExtractCommandInfo {
+    pub compare_file_path: PathBuf,
+    pub debug_symbol: String,
+    /// The bytes are written here, the metadata next to it with a `.json` extension.
+    pub out: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum ExtractError {
+    PdbError(super::pdb::PdbError),
+    IoError(std::io::Error),
+    JsonError(serde_json::Error),
+    SymbolNotFound,
+    FunctionOutOfBounds,
+}
+
+/// What's needed to disassemble the extracted bytes like the comparer does.
+#[derive(Debug, Serialize)]
+struct Metadata<'a> {
+    symbol: &'a str,
+    /// Offset of the function in the debug binary.
+    offset: usize,
+    size: usize,
+    /// Address of the first byte.
+    base: u64,
+}
+
+pub fn run(info: ExtractCommandInfo) -> Result<(), ExtractError> {
+    let pdb = Pdb::new(info.compare_file_path.with_extension("pdb")).map_err(PdbError)?;
+    let FunctionSymbol { offset, size, .. } = pdb
+        .parse_pdb()
+        .find(|func| func.name == info.debug_symbol)
+        .ok_or(SymbolNotFound)?;
+
+    let bytes = std::fs::read(&info.compare_file_path).map_err(IoError)?;
+    let start = (offset + PDB_OFFSET_COMPARE_FILE) as usize;
+    let func_bytes = bytes.get(start..start + size).ok_or(FunctionOutOfBounds)?;
+    std::fs::write(&info.out, func_bytes).map_err(IoError)?;
+
+    let metadata = Metadata {
+        symbol: &info.debug_symbol,
+        offset: start,
+        size,
+        base: offset + PDB_SEGMENT_OFFSET,
+    };
+    let metadata_path = info.out.with_extension("json");
+    File::create(&metadata_path)
+        .map(BufWriter::new)
+        .map_err(IoError)
+        .and_then(|writer| serde_json::to_writer_pretty(writer, &metadata).map_err(JsonError))?;
+
+    println!(
+        "Wrote {:#X} bytes of {} to {}, the metadata to {}.",
+        size,
+        info.debug_symbol,
+        info.out.to_string_lossy(),
+        metadata_path.to_string_lossy()
+    );
+    Ok(())
+}
+
+pub fn print_error(e: &ExtractError) {
+    match e {
+        PdbError(e) => super::pdb::print_error(e),
+        IoError(e) => println!("IO error: {:#?}", e),
+        JsonError(e) => println!("Error writing the metadata: {}", e),
+        SymbolNotFound => println!("Could not find the symbol in the PDB."),
+        FunctionOutOfBounds => println!(
+            "Error: The function offset/size are outside of the bounds of the debug binary."
+        ),
+    }
+}
@@ -1,13 +1,17 @@
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::Write;
 use std::iter::FromIterator;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use self::GenerateFullCommandError::*;
 use super::comparer_config::*;
+use super::compressed::{read_binary, OutputFile};
 use super::disasm::*;
+use super::output_lock::OutputLock;
 use super::pdb::*;
+use super::pe::section_addresses;
+use super::thunks::is_thunk;
+use super::LineEndingWriter;
 
 #[derive(Debug)]
 pub struct GenerateFullCommandInfo {
@@ -15,6 +19,17 @@ pub struct GenerateFullCommandInfo {
     pub orig_file: bool,
     pub disasm_opts: super::DisasmOpts,
     pub truncate_to_original: bool,
+    pub functions_csv: Option<PathBuf>,
+    pub max_lines_per_function: Option<usize>,
+    pub include_done: bool,
+    /// Add the original and devilution addresses and sizes to the function headers.
+    pub metadata: bool,
+    /// Leave out thunks and adjustors.
+    pub skip_thunks: bool,
+    /// Write the output gzip compressed, as `.asm.gz`.
+    pub compress: bool,
+    /// The other binary, functions with the same bytes in both are written as a single line.
+    pub binary_identical: Option<PathBuf>,
 }
 
 #[derive(Debug)]
@@ -23,36 +38,114 @@ pub enum GenerateFullCommandError {
     IoError(std::io::Error),
     DisasmError(super::disasm::DisasmError),
     FunctionDefSizeWrong(String),
+    ConfigError(ComparerConfigError),
+    OutputLockError(super::output_lock::OutputLockError),
 }
 
 pub fn run(
     info: GenerateFullCommandInfo,
     cfg: &ComparerConfig,
 ) -> Result<(), GenerateFullCommandError> {
+    let mut funcs = match &info.functions_csv {
+        Some(path) => read_functions_csv(path).map_err(ConfigError)?,
+        None => cfg.func.clone(),
+    };
+    if !info.include_done {
+        funcs.retain(|func| !func.done);
+    }
+
+    let _lock =
+        OutputLock::acquire(std::env::current_dir().map_err(IoError)?).map_err(OutputLockError)?;
+
+    let identical_bytes = match &info.binary_identical {
+        Some(path) => other_function_bytes(path, &info, cfg, &funcs)?,
+        None => HashMap::new(),
+    };
+
+    if info.orig_file {
+        generate_full_orig(info, cfg, &funcs, &identical_bytes)
+    } else {
+        generate_full_pdb(info, &funcs, &identical_bytes)
+    }
+}
+
+/// Reads the bytes of the functions from the other binary, the debug binary when generating
+/// the file for the original one and vice versa.
+fn other_function_bytes(
+    path: &Path,
+    info: &GenerateFullCommandInfo,
+    cfg: &ComparerConfig,
+    funcs: &[FunctionDefinition],
+) -> Result<HashMap<String, Vec<u8>>, GenerateFullCommandError> {
+    let mut func_bytes = HashMap::new();
     if info.orig_file {
-        generate_full_orig(info, cfg)
+        let pdb = Pdb::new(path.with_extension("pdb")).map_err(PdbError)?;
+        let pdb_funcs: HashMap<&str, FunctionSymbol> =
+            HashMap::from_iter(pdb.parse_pdb().map(|func| (func.name, func)));
+        let bytes = std::fs::read(path).map_err(IoError)?;
+        for func in funcs {
+            if let Some(pdb_func) = pdb_funcs.get::<str>(func.name.as_ref()) {
+                let size = match func.size {
+                    Some(size) if info.truncate_to_original => size,
+                    _ => pdb_func.size,
+                };
+                let offset = (pdb_func.offset + PDB_OFFSET_COMPARE_FILE) as usize;
+                if let Some(bytes) = bytes.get(offset..offset + size) {
+                    func_bytes.insert(func.name.clone(), bytes.to_vec());
+                }
+            }
+        }
     } else {
-        generate_full_pdb(info, cfg)
+        let bytes = read_binary(path).map_err(IoError)?;
+        for func in funcs {
+            if let (Some(size), Some(offset)) =
+                (func.size, func.addr.checked_sub(cfg.address_offset))
+            {
+                let offset = offset as usize;
+                if let Some(bytes) = bytes.get(offset..offset + size) {
+                    func_bytes.insert(func.name.clone(), bytes.to_vec());
+                }
+            }
+        }
+    }
+
+    for func in funcs {
+        if let Some(bytes) = func_bytes.get_mut(&func.name) {
+            mask_bytes(bytes, &func.mask);
+        }
+    }
+    Ok(func_bytes)
+}
+
+fn output_file_name(base: &str, compress: bool) -> String {
+    if compress {
+        format!("{}.asm.gz", base)
+    } else {
+        format!("{}.asm", base)
     }
 }
 
 fn generate_full_orig(
     mut info: GenerateFullCommandInfo,
     cfg: &ComparerConfig,
+    funcs: &[FunctionDefinition],
+    identical_bytes: &HashMap<String, Vec<u8>>,
 ) -> Result<(), GenerateFullCommandError> {
     let mut path = std::env::current_dir().map_err(IoError)?;
-    path.push("orig_full.asm");
+    path.push(output_file_name("orig_full", info.compress));
 
-    let bytes = std::fs::read(&info.file_path).map_err(IoError)?;
+    let bytes = read_binary(&info.file_path).map_err(IoError)?;
+    let symbols = function_symbols(funcs);
 
     let stdout = std::io::stdout();
     let mut stdout_lock = stdout.lock();
 
-    File::create(path)
+    OutputFile::create(path, info.compress)
         .map_err(IoError)
-        .map(BufWriter::new)
+        .map(|f| LineEndingWriter::new(f, info.disasm_opts.line_ending))
         .and_then(|mut writer| {
-            for func in &cfg.func {
+            let mut skipped_thunks = 0;
+            for func in funcs {
                 let size = match func.size {
                     None => {
                         writeln!(
@@ -66,19 +159,64 @@ fn generate_full_orig(
                     Some(size) => size,
                 };
 
-                write_function_head(&mut writer, size, func.name.as_ref())?;
-
                 let offset = (func.addr - cfg.address_offset) as usize;
                 let offset_end = offset + size;
 
-                let func_bytes = bytes
+                let mut func_bytes = bytes
                     .get(offset..offset_end)
-                    .ok_or_else(|| FunctionDefSizeWrong(func.name.clone()))?;
+                    .ok_or_else(|| FunctionDefSizeWrong(func.name.clone()))?
+                    .to_vec();
+                mask_bytes(&mut func_bytes, &func.mask);
 
-                write_disasm(&mut writer, func_bytes, &mut info.disasm_opts, func.addr)
-                    .map_err(DisasmError)?;
+                let metadata = if info.metadata {
+                    vec![original_metadata(func)]
+                } else {
+                    Vec::new()
+                };
+                // identical functions aren't disassembled at all
+                if identical_bytes.get(&func.name) == Some(&func_bytes) {
+                    write_function_head(&mut writer, size, func.name.as_ref(), &metadata)?;
+                    write_identical(&mut writer, func_bytes.len())?;
+                    continue;
+                }
+
+                let insns = match disasm_until_error(
+                    &func_bytes,
+                    &mut info.disasm_opts,
+                    func.addr,
+                    &func.mask,
+                    &symbols,
+                )
+                .map_err(DisasmError)?
+                {
+                    Disassembly {
+                        insns,
+                        decode_error: None,
+                    } => insns,
+                    Disassembly {
+                        decode_error: Some(e),
+                        ..
+                    } => {
+                        print_skipped(&mut stdout_lock, &func.name, &e)?;
+                        continue;
+                    }
+                };
+                if info.skip_thunks && is_thunk(&func.name, &insns) {
+                    skipped_thunks += 1;
+                    continue;
+                }
+
+                write_function_head(&mut writer, size, func.name.as_ref(), &metadata)?;
+                write_disasm(
+                    &mut writer,
+                    &insns,
+                    &info.disasm_opts,
+                    info.max_lines_per_function,
+                )
+                .map_err(DisasmError)?;
             }
-            Ok(())
+            print_skipped_thunks(&mut stdout_lock, skipped_thunks)?;
+            writer.into_inner().finish().map_err(IoError)
         })?;
 
     Ok(())
@@ -86,33 +224,40 @@ fn generate_full_orig(
 
 fn generate_full_pdb(
     mut info: GenerateFullCommandInfo,
-    cfg: &ComparerConfig,
+    funcs: &[FunctionDefinition],
+    identical_bytes: &HashMap<String, Vec<u8>>,
 ) -> Result<(), GenerateFullCommandError> {
     let mut pdb_path = info.file_path.clone();
     pdb_path.set_extension("pdb");
 
     let pdb = Pdb::new(pdb_path).map_err(PdbError)?;
+    let mut symbols = pdb.symbol_map();
     let mut pdb_funcs: HashMap<&str, FunctionSymbol> =
         HashMap::from_iter(pdb.parse_pdb().map(|func| (func.name, func)));
 
     let mut path = std::env::current_dir().map_err(IoError)?;
-    path.push("compare_full.asm");
+    path.push(output_file_name("compare_full", info.compress));
 
     // println!("{}", path.to_str().unwrap());
 
     let bytes = std::fs::read(&info.file_path).map_err(IoError)?;
+    if info.disasm_opts.resolve_globals {
+        match section_addresses(&bytes) {
+            Some(addresses) => symbols.extend(pdb.data_symbol_map(&addresses)),
+            None => println!("WARN: Could not read the sections of the devilution binary."),
+        }
+    }
 
     let stdout = std::io::stdout();
     let mut stdout_lock = stdout.lock();
 
-    File::create(path)
+    OutputFile::create(path, info.compress)
         .map_err(IoError)
-        .map(BufWriter::new)
+        .map(|f| LineEndingWriter::new(f, info.disasm_opts.line_ending))
         .and_then(|mut writer| {
-            for func in &cfg.func {
+            let mut skipped_thunks = 0;
+            for func in funcs {
                 if let Some(pdb_func) = pdb_funcs.remove::<str>(func.name.as_ref()) {
-                    write_function_head(&mut writer, pdb_func.size, func.name.as_ref())?;
-
                     let offset = (pdb_func.offset + PDB_OFFSET_COMPARE_FILE) as usize;
                     let size = if info.truncate_to_original {
                         if let Some(size) = func.size {
@@ -130,15 +275,65 @@ fn generate_full_pdb(
                     };
                     let offset_end = offset + size;
 
-                    let func_bytes = bytes
+                    let mut func_bytes = bytes
                         .get(offset..offset_end)
-                        .ok_or_else(|| FunctionDefSizeWrong(func.name.clone()))?;
+                        .ok_or_else(|| FunctionDefSizeWrong(func.name.clone()))?
+                        .to_vec();
+                    mask_bytes(&mut func_bytes, &func.mask);
+
+                    let addr = pdb_func.offset + PDB_SEGMENT_OFFSET;
+                    let metadata = if info.metadata {
+                        vec![
+                            original_metadata(func),
+                            format!("devilution: {:#X}, size: {:#X}", addr, pdb_func.size),
+                        ]
+                    } else {
+                        Vec::new()
+                    };
+                    // identical functions aren't disassembled at all
+                    if identical_bytes.get(&func.name) == Some(&func_bytes) {
+                        write_function_head(
+                            &mut writer,
+                            pdb_func.size,
+                            func.name.as_ref(),
+                            &metadata,
+                        )?;
+                        write_identical(&mut writer, func_bytes.len())?;
+                        continue;
+                    }
 
+                    let insns = match disasm_until_error(
+                        &func_bytes,
+                        &mut info.disasm_opts,
+                        addr,
+                        &func.mask,
+                        &symbols,
+                    )
+                    .map_err(DisasmError)?
+                    {
+                        Disassembly {
+                            insns,
+                            decode_error: None,
+                        } => insns,
+                        Disassembly {
+                            decode_error: Some(e),
+                            ..
+                        } => {
+                            print_skipped(&mut stdout_lock, &func.name, &e)?;
+                            continue;
+                        }
+                    };
+                    if info.skip_thunks && is_thunk(&func.name, &insns) {
+                        skipped_thunks += 1;
+                        continue;
+                    }
+
+                    write_function_head(&mut writer, pdb_func.size, func.name.as_ref(), &metadata)?;
                     write_disasm(
                         &mut writer,
-                        func_bytes,
-                        &mut info.disasm_opts,
-                        pdb_func.offset + PDB_SEGMENT_OFFSET,
+                        &insns,
+                        &info.disasm_opts,
+                        info.max_lines_per_function,
                     )
                     .map_err(DisasmError)?;
                 } else {
@@ -150,6 +345,7 @@ fn generate_full_pdb(
                     .map_err(IoError)?;
                 }
             }
+            print_skipped_thunks(&mut stdout_lock, skipped_thunks)?;
             for func in pdb_funcs {
                 writeln!(
                     stdout_lock,
@@ -158,37 +354,78 @@ fn generate_full_pdb(
                 )
                 .map_err(IoError)?;
             }
-            writer.flush().map_err(IoError)?;
-
-            Ok(())
+            writer.into_inner().finish().map_err(IoError)
         })?;
 
     Ok(())
 }
 
+fn print_skipped(
+    writer: &mut impl Write,
+    name: &str,
+    e: &super::disasm::DisasmError,
+) -> Result<(), GenerateFullCommandError> {
+    writeln!(
+        writer,
+        "WARN: Skipping {}: {}",
+        name,
+        super::disasm::error_message(e)
+    )
+    .map_err(IoError)
+}
+
+fn print_skipped_thunks(
+    writer: &mut impl Write,
+    skipped_thunks: usize,
+) -> Result<(), GenerateFullCommandError> {
+    if skipped_thunks > 0 {
+        writeln!(writer, "Note: Skipped {} thunk(s).", skipped_thunks).map_err(IoError)?;
+    }
+    Ok(())
+}
+
+fn original_metadata(func: &FunctionDefinition) -> String {
+    match func.size {
+        Some(size) => format!("original: {:#X}, size: {:#X}", func.addr, size),
+        None => format!("original: {:#X}, size: undefined", func.addr),
+    }
+}
+
 fn write_function_head(
     writer: &mut impl Write,
     size: usize,
     name: &str,
+    metadata: &[String],
 ) -> Result<(), GenerateFullCommandError> {
     // (blank line)
     // ;
     // ; <function>
     // ; size: 0xDEADBEEF
+    // ; <metadata lines>
     // ;
     // (blank line)
-    writeln!(writer, "\n;\n; {}\n; size: {:#X}\n;\n", name, size).map_err(IoError)
+    write!(writer, "\n;\n; {}\n; size: {:#X}\n", name, size).map_err(IoError)?;
+    for line in metadata {
+        writeln!(writer, "; {}", line).map_err(IoError)?;
+    }
+    writeln!(writer, ";\n").map_err(IoError)
+}
+
+fn write_identical(writer: &mut impl Write, size: usize) -> Result<(), GenerateFullCommandError> {
+    writeln!(writer, "; identical ({:#X} bytes)", size).map_err(IoError)
 }
 
 pub fn print_error(e: &GenerateFullCommandError) {
     match e {
-        PdbError(e) => println!("PDB file error: {:#?}", e),
+        PdbError(e) => super::pdb::print_error(e),
         IoError(e) => println!("IO error: {:#?}", e),
-        DisasmError(e) => println!("Zydis disassembly engine error: {:#?}", e),
+        DisasmError(e) => super::disasm::print_error(e),
         FunctionDefSizeWrong(s) => println!(
             "Error: The function offset/size of {} are outside of \
              the bounds of the input file.",
             s
         ),
+        ConfigError(e) => super::comparer_config::print_error(e),
+        OutputLockError(e) => super::output_lock::print_error(e),
     }
 }
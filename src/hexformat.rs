@@ -35,3 +35,48 @@ impl<T: UpperHex + Signed + Copy> From<T> for CustomUpperHexFormat<T> {
         CustomUpperHexFormat(v)
     }
 }
+
+/// A printf style format for addresses, like `0x%08x` or `%X`. The default is `%X`.
+#[derive(Debug, Clone, Default)]
+pub struct AddrFormat {
+    prefix: String,
+    suffix: String,
+    width: usize,
+    zero_pad: bool,
+    lowercase: bool,
+}
+
+impl AddrFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        let percent = format
+            .find('%')
+            .ok_or_else(|| String::from("The address format needs a %x or %X conversion."))?;
+        let spec = &format[percent + 1..];
+        let digits = spec.chars().take_while(char::is_ascii_digit).count();
+        let lowercase = match spec[digits..].chars().next() {
+            Some('x') => true,
+            Some('X') => false,
+            _ => return Err("Only %x and %X conversions are supported.".into()),
+        };
+
+        Ok(AddrFormat {
+            prefix: format[..percent].into(),
+            suffix: spec[digits + 1..].into(),
+            width: spec[..digits].parse().unwrap_or(0),
+            zero_pad: spec.starts_with('0'),
+            lowercase,
+        })
+    }
+
+    pub fn format(&self, addr: u64) -> String {
+        let hex = if self.lowercase {
+            format!("{:x}", addr)
+        } else {
+            format!("{:X}", addr)
+        };
+        let padding =
+            if self.zero_pad { "0" } else { " " }.repeat(self.width.saturating_sub(hex.len()));
+
+        format!("{}{}{}{}", self.prefix, padding, hex, self.suffix)
+    }
+}
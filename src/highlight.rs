@@ -0,0 +1,104 @@
+//! Coloring the register names given with `--highlight-registers` in the terminal output.
+
+use std::borrow::Cow;
+
+use atty::Stream;
+
+const HIGHLIGHT_START: &str = "\x1b[1;33m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Default)]
+pub struct RegisterHighlight {
+    /// Lowercase register names, empty if stdout isn't a terminal.
+    registers: Vec<String>,
+}
+
+impl RegisterHighlight {
+    pub fn new(registers: &[&str]) -> Self {
+        let registers = if atty::is(Stream::Stdout) {
+            registers
+                .iter()
+                .map(|reg| reg.trim().to_ascii_lowercase())
+                .filter(|reg| !reg.is_empty())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        RegisterHighlight { registers }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.registers.is_empty()
+    }
+
+    /// Wraps every token of `text` that is one of the registers in color codes.
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if !self.is_active() {
+            return Cow::Borrowed(text);
+        }
+
+        let mut highlighted = String::with_capacity(text.len());
+        let mut token_start = None;
+        for (pos, c) in text.char_indices().chain(Some((text.len(), ' '))) {
+            match (token_start, c.is_ascii_alphanumeric()) {
+                (None, true) => token_start = Some(pos),
+                (Some(start), false) => {
+                    self.push_token(&mut highlighted, &text[start..pos]);
+                    token_start = None;
+                }
+                _ => {}
+            }
+            if token_start.is_none() && pos < text.len() {
+                highlighted.push(c);
+            }
+        }
+        Cow::Owned(highlighted)
+    }
+
+    fn push_token(&self, highlighted: &mut String, token: &str) {
+        if self
+            .registers
+            .iter()
+            .any(|reg| reg.eq_ignore_ascii_case(token))
+        {
+            highlighted.push_str(HIGHLIGHT_START);
+            highlighted.push_str(token);
+            highlighted.push_str(HIGHLIGHT_END);
+        } else {
+            highlighted.push_str(token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlight(registers: &[&str]) -> RegisterHighlight {
+        RegisterHighlight {
+            registers: registers.iter().map(|reg| reg.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn highlights_whole_register_tokens() {
+        assert_eq!(
+            highlight(&["esi"]).apply("mov esi, dword ptr [esi+4]"),
+            "mov \x1b[1;33mesi\x1b[0m, dword ptr [\x1b[1;33mesi\x1b[0m+4]"
+        );
+        assert_eq!(highlight(&["si"]).apply("push esi"), "push esi");
+        assert_eq!(
+            highlight(&["eax"]).apply("xor EAX, ecx"),
+            "xor \x1b[1;33mEAX\x1b[0m, ecx"
+        );
+    }
+
+    #[test]
+    fn inactive_without_registers() {
+        assert!(!highlight(&[]).is_active());
+        assert!(matches!(
+            highlight(&[]).apply("mov esi, edi"),
+            Cow::Borrowed("mov esi, edi")
+        ));
+    }
+}
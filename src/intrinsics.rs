@@ -0,0 +1,135 @@
+//! Detection of `memcpy`/`memset` calls that VC6 expanded into `rep movs`/`rep stos`.
+
+use super::diff::DiffOp;
+use super::disasm::Instruction;
+
+/// Name of the function a `rep movs`/`rep stos` instruction was most likely inlined from.
+pub fn inlined_intrinsic(text: &str) -> Option<&'static str> {
+    if text.starts_with("rep movs") {
+        Some("memcpy")
+    } else if text.starts_with("rep stos") {
+        Some("memset")
+    } else {
+        None
+    }
+}
+
+/// Treats changed blocks as matching if one side contains an inlined intrinsic and the other
+/// side calls the same function instead. The intrinsic is paired with the call, and the lines
+/// around them with the ones at the same distance before and after it, so the argument setup of
+/// both sides lines up. Returns the new diff and the orig and compare line of each collapsed
+/// intrinsic and call.
+///
+/// The calls are only recognizable with resolved symbols, otherwise the call target is hidden.
+pub fn collapse_intrinsics(
+    ops: &[DiffOp],
+    orig_lines: &[&str],
+    compare_lines: &[&str],
+) -> (Vec<DiffOp>, Vec<(usize, usize)>) {
+    let mut result = Vec::with_capacity(ops.len());
+    let mut collapsed = Vec::new();
+
+    let mut k = 0;
+    while k < ops.len() {
+        if let DiffOp::Equal(..) = ops[k] {
+            result.push(ops[k]);
+            k += 1;
+            continue;
+        }
+
+        let end = ops[k..]
+            .iter()
+            .position(|op| matches!(op, DiffOp::Equal(..)))
+            .map_or(ops.len(), |len| k + len);
+        let block = &ops[k..end];
+        k = end;
+
+        let deleted: Vec<usize> = block
+            .iter()
+            .filter_map(|op| match *op {
+                DiffOp::Delete(i) => Some(i),
+                _ => None,
+            })
+            .collect();
+        let inserted: Vec<usize> = block
+            .iter()
+            .filter_map(|op| match *op {
+                DiffOp::Insert(j) => Some(j),
+                _ => None,
+            })
+            .collect();
+        let deleted_lines: Vec<&str> = deleted.iter().map(|&i| orig_lines[i]).collect();
+        let inserted_lines: Vec<&str> = inserted.iter().map(|&j| compare_lines[j]).collect();
+
+        let anchor = replaced_call(&deleted_lines, &inserted_lines).or_else(|| {
+            replaced_call(&inserted_lines, &deleted_lines).map(|(call, inlined)| (inlined, call))
+        });
+        match anchor {
+            Some((d, i)) => {
+                align_around(&deleted, &inserted, d, i, &mut result);
+                collapsed.push((deleted[d], inserted[i]));
+            }
+            None => result.extend_from_slice(block),
+        }
+    }
+
+    (result, collapsed)
+}
+
+/// Appends ops pairing `deleted[d]` with `inserted[i]` and the lines before and after them
+/// outwards from there, keeping the unpaired ones as deleted and inserted lines.
+fn align_around(
+    deleted: &[usize],
+    inserted: &[usize],
+    d: usize,
+    i: usize,
+    result: &mut Vec<DiffOp>,
+) {
+    let before = d.min(i);
+    result.extend(deleted[..d - before].iter().map(|&a| DiffOp::Delete(a)));
+    result.extend(inserted[..i - before].iter().map(|&b| DiffOp::Insert(b)));
+    result.extend(
+        deleted[d - before..=d]
+            .iter()
+            .zip(&inserted[i - before..=i])
+            .map(|(&a, &b)| DiffOp::Equal(a, b)),
+    );
+
+    let after = (deleted.len() - d - 1).min(inserted.len() - i - 1);
+    result.extend(
+        deleted[d + 1..=d + after]
+            .iter()
+            .zip(&inserted[i + 1..=i + after])
+            .map(|(&a, &b)| DiffOp::Equal(a, b)),
+    );
+    result.extend(deleted[d + after + 1..].iter().map(|&a| DiffOp::Delete(a)));
+    result.extend(inserted[i + after + 1..].iter().map(|&b| DiffOp::Insert(b)));
+}
+
+/// The position of an inlined intrinsic in `inlined` and of the call in `calls` it replaces.
+fn replaced_call(inlined: &[&str], calls: &[&str]) -> Option<(usize, usize)> {
+    inlined.iter().enumerate().find_map(|(d, line)| {
+        let name = inlined_intrinsic(line)?;
+        calls
+            .iter()
+            .position(|line| line.starts_with("call ") && line.contains(name))
+            .map(|i| (d, i))
+    })
+}
+
+/// Marks the inlined intrinsics of the collapsed blocks with the function they replace.
+pub fn annotate_inlined(
+    orig_insns: &mut [Instruction],
+    compare_insns: &mut [Instruction],
+    collapsed: &[(usize, usize)],
+) {
+    let annotate = |insn: &mut Instruction| {
+        if let Some(name) = inlined_intrinsic(&insn.text) {
+            insn.text = format!("{} ; inlined {}", insn.text, name);
+        }
+    };
+    for &(i, j) in collapsed {
+        annotate(&mut orig_insns[i]);
+        annotate(&mut compare_insns[j]);
+    }
+}
@@ -0,0 +1,100 @@
+use std::io::{Result as IoResult, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Native,
+}
+
+impl LineEnding {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::Crlf),
+            "native" => Some(LineEnding::Native),
+            _ => None,
+        }
+    }
+
+    fn is_crlf(self) -> bool {
+        match self {
+            LineEnding::Lf => false,
+            LineEnding::Crlf => true,
+            LineEnding::Native => cfg!(target_os = "windows"),
+        }
+    }
+}
+
+/// Wraps a writer, translating every `\n` into the chosen line ending.
+pub struct LineEndingWriter<W: Write> {
+    inner: W,
+    crlf: bool,
+}
+
+impl<W: Write> LineEndingWriter<W> {
+    pub fn new(inner: W, line_ending: LineEnding) -> Self {
+        LineEndingWriter {
+            inner,
+            crlf: line_ending.is_crlf(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for LineEndingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        if !self.crlf {
+            return self.inner.write(buf);
+        }
+
+        for (i, line) in buf.split(|&b| b == b'\n').enumerate() {
+            if i > 0 {
+                self.inner.write_all(b"\r\n")?;
+            }
+            self.inner.write_all(line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_with(line_ending: LineEnding, chunks: &[&str]) -> String {
+        let mut writer = LineEndingWriter::new(Vec::new(), line_ending);
+        for chunk in chunks {
+            writer.write_all(chunk.as_bytes()).unwrap();
+        }
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn parses_line_endings() {
+        assert_eq!(LineEnding::parse("lf"), Some(LineEnding::Lf));
+        assert_eq!(LineEnding::parse("crlf"), Some(LineEnding::Crlf));
+        assert_eq!(LineEnding::parse("native"), Some(LineEnding::Native));
+        assert_eq!(LineEnding::parse("CRLF"), None);
+    }
+
+    #[test]
+    fn translates_newlines() {
+        let chunks = ["push ebp\nmov ebp, esp", "\n", "\nret\n"];
+        assert_eq!(
+            write_with(LineEnding::Lf, &chunks),
+            "push ebp\nmov ebp, esp\n\nret\n"
+        );
+        assert_eq!(
+            write_with(LineEnding::Crlf, &chunks),
+            "push ebp\r\nmov ebp, esp\r\n\r\nret\r\n"
+        );
+    }
+}
@@ -1,44 +1,158 @@
+mod alignment;
+mod build_flags;
+mod call_graph;
+mod callconv;
+mod cfg_dot;
 mod cmdline;
 mod compare;
+mod compare_all;
+mod compare_builds;
+mod compare_golden;
+mod compare_nasm;
+mod compare_stdin;
+mod compare_symbols;
 mod comparer_config;
+mod compressed;
+mod diff;
 mod disasm;
+mod extract;
 mod generate_full;
 mod hexformat;
+mod highlight;
+mod intrinsics;
+mod line_ending;
+mod metrics;
+mod mnemonics;
+mod normalizer;
+mod output_lock;
+mod pager;
 mod pdb;
+mod pe;
+mod prepare;
+mod reorder;
+mod roundtrip;
+mod segments;
+mod selftest;
+mod size_hint;
+mod structure;
+mod thunks;
+mod x87;
 
 pub use self::compare::{CompareCommandInfo, CompareOpts};
-use self::comparer_config::ComparerConfig;
+pub use self::compare_all::CompareAllCommandInfo;
+pub use self::compare_builds::CompareBuildsCommandInfo;
+pub use self::compare_golden::CompareGoldenCommandInfo;
+pub use self::compare_nasm::CompareNasmCommandInfo;
+pub use self::compare_stdin::CompareStdinCommandInfo;
+pub use self::compare_symbols::CompareSymbolsCommandInfo;
+use self::comparer_config::{CanonicalizeRule, ComparerConfig};
 pub use self::disasm::{DisasmError, DisasmOpts};
+pub use self::extract::ExtractCommandInfo;
 pub use self::generate_full::GenerateFullCommandInfo;
-pub use self::hexformat::CustomUpperHexFormat;
+pub use self::hexformat::{AddrFormat, CustomUpperHexFormat};
+pub use self::line_ending::{LineEnding, LineEndingWriter};
 
+#[allow(clippy::large_enum_variant)] // only one is created, when parsing the command line
 pub enum Command {
     Compare(CompareCommandInfo),
     GenerateFull(GenerateFullCommandInfo),
+    CompareBuilds(CompareBuildsCommandInfo),
+    CompareAll(CompareAllCommandInfo),
+    CompareGolden(CompareGoldenCommandInfo),
+    CompareNasm(CompareNasmCommandInfo),
+    CompareStdin(CompareStdinCommandInfo),
+    CompareSymbols(CompareSymbolsCommandInfo),
+    Extract(ExtractCommandInfo),
+    PrintConfigSchema,
+    Selftest,
 }
 
 fn main() {
-    let command = cmdline::parse_cmdline();
-    let comparer_config = match ComparerConfig::read_default() {
-        Ok(cfg) => cfg,
-        Err(e) => {
-            println!("Error reading the config file: {:#?}", e);
-            std::process::exit(1);
-        }
-    };
-
-    match command {
+    match cmdline::parse_cmdline() {
         Command::Compare(info) => {
-            if let Err(e) = compare::run(info, &comparer_config) {
+            if let Err(e) = compare::run(info, &read_config()) {
                 compare::print_error(&e);
                 std::process::exit(1);
             }
         }
         Command::GenerateFull(info) => {
-            if let Err(e) = generate_full::run(info, &comparer_config) {
+            if let Err(e) = generate_full::run(info, &read_config()) {
                 generate_full::print_error(&e);
                 std::process::exit(1);
             }
         }
+        Command::CompareBuilds(info) => {
+            if let Err(e) = compare_builds::run(info, &read_canonicalize_rules()) {
+                compare_builds::print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        Command::CompareAll(info) => {
+            let format = info.format;
+            if let Err(e) = compare_all::run(info, &read_config()) {
+                compare_all::print_error(&e, format);
+                std::process::exit(1);
+            }
+        }
+        Command::CompareGolden(info) => {
+            if let Err(e) = compare_golden::run(info, &read_canonicalize_rules()) {
+                compare_golden::print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        Command::CompareNasm(info) => {
+            if let Err(e) = compare_nasm::run(info, &read_canonicalize_rules()) {
+                compare_nasm::print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        Command::CompareStdin(info) => {
+            if let Err(e) = compare_stdin::run(info) {
+                compare_stdin::print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        Command::CompareSymbols(info) => {
+            if let Err(e) = compare_symbols::run(info, &read_canonicalize_rules()) {
+                compare_symbols::print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        Command::Extract(info) => {
+            if let Err(e) = extract::run(info) {
+                extract::print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        Command::PrintConfigSchema => print!("{}", comparer_config::CONFIG_SCHEMA),
+        Command::Selftest => {
+            if let Err(e) = selftest::run() {
+                selftest::print_error(&e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// The `[[canonicalize]]` rules for the commands working without a config, none if there is
+/// no config file.
+fn read_canonicalize_rules() -> Vec<CanonicalizeRule> {
+    match ComparerConfig::read_default_if_exists() {
+        Ok(cfg) => cfg.map(|cfg| cfg.canonicalize).unwrap_or_default(),
+        Err(e) => {
+            comparer_config::print_error(&e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Only the commands using the config read it, so the others work without one.
+fn read_config() -> ComparerConfig {
+    match ComparerConfig::read_default() {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            comparer_config::print_error(&e);
+            std::process::exit(1);
+        }
     }
 }
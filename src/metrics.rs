@@ -0,0 +1,81 @@
+//! Writing the match results in the text format read by the node exporter's textfile collector.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, Error, Write};
+use std::path::{Path, PathBuf};
+
+use super::diff::DiffStats;
+
+/// Replaces `path` with the metrics of one compared function. The file is written next to it
+/// first and then renamed, so the collector never reads a partially written file.
+pub fn write_metrics_file(
+    path: &Path,
+    symbol: &str,
+    size: usize,
+    stats: Option<&DiffStats>,
+) -> Result<(), Error> {
+    let mut tmp_path = OsString::from(path);
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        let labels = format!("{{symbol=\"{}\"}}", escape_label_value(symbol));
+        let match_percent = stats.map_or(100.0, DiffStats::match_percent);
+
+        write_gauge(
+            &mut writer,
+            "devilution_match_percent",
+            "Percentage of matching instructions.",
+            &labels,
+            &format!("{:.2}", match_percent),
+        )?;
+        write_gauge(
+            &mut writer,
+            "devilution_function_size_bytes",
+            "Size of the devilution function.",
+            &labels,
+            &size.to_string(),
+        )?;
+        // the line counts are unknown if --binary-identical skipped the disassembly
+        if let Some(stats) = stats {
+            write_gauge(
+                &mut writer,
+                "devilution_matching_lines",
+                "Number of matching instructions.",
+                &labels,
+                &stats.matching_lines.to_string(),
+            )?;
+            write_gauge(
+                &mut writer,
+                "devilution_differing_lines",
+                "Number of instructions only found on one side.",
+                &labels,
+                &stats.differing_lines().to_string(),
+            )?;
+        }
+        writer.flush()?;
+    }
+
+    std::fs::rename(&tmp_path, path)
+}
+
+fn write_gauge(
+    writer: &mut impl Write,
+    name: &str,
+    help: &str,
+    labels: &str,
+    value: &str,
+) -> Result<(), Error> {
+    writeln!(writer, "# HELP {} {}", name, help)?;
+    writeln!(writer, "# TYPE {} gauge", name)?;
+    writeln!(writer, "{}{} {}", name, labels, value)
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
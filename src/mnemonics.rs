@@ -0,0 +1,96 @@
+//! Canonical spellings of mnemonics that have several aliases.
+//!
+//! Zydis always prints the same alias for an instruction, but golden files or normalized
+//! output coming from other disassemblers may use another one (`je` instead of `jz`, `sal`
+//! instead of `shl`). Mapping every alias to the form Zydis uses keeps such lines equal.
+
+/// Condition code aliases used by `jcc`, `setcc` and `cmovcc`, mapped to the Zydis spelling.
+const CONDITION_ALIASES: &[(&str, &str)] = &[
+    ("e", "z"),
+    ("ne", "nz"),
+    ("c", "b"),
+    ("nae", "b"),
+    ("nc", "nb"),
+    ("ae", "nb"),
+    ("na", "be"),
+    ("a", "nbe"),
+    ("nge", "l"),
+    ("ge", "nl"),
+    ("ng", "le"),
+    ("g", "nle"),
+    ("pe", "p"),
+    ("po", "np"),
+];
+
+const CONDITIONAL_PREFIXES: &[&str] = &["j", "set", "cmov"];
+
+const MNEMONIC_ALIASES: &[(&str, &str)] = &[("sal", "shl"), ("retn", "ret")];
+
+/// Replaces an aliased mnemonic at the start of `text` with its canonical form.
+pub fn canonical_mnemonic(text: &str) -> String {
+    let (mnemonic, rest) = match text.find(' ') {
+        Some(pos) => text.split_at(pos),
+        None => (text, ""),
+    };
+
+    match canonical_form(mnemonic) {
+        Some(canonical) => format!("{}{}", canonical, rest),
+        None => text.to_owned(),
+    }
+}
+
+fn canonical_form(mnemonic: &str) -> Option<String> {
+    if let Some(&(_, canonical)) = MNEMONIC_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == mnemonic)
+    {
+        return Some(canonical.to_owned());
+    }
+
+    // `jecxz` and friends start with `j` too, but aren't followed by a condition code alias
+    CONDITIONAL_PREFIXES.iter().find_map(|prefix| {
+        if !mnemonic.starts_with(prefix) {
+            return None;
+        }
+        let condition = &mnemonic[prefix.len()..];
+        CONDITION_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == condition)
+            .map(|(_, canonical)| format!("{}{}", prefix, canonical))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_condition_code_aliases() {
+        assert_eq!(canonical_mnemonic("je 0x401000"), "jz 0x401000");
+        assert_eq!(canonical_mnemonic("jnae short 0x10"), "jb short 0x10");
+        assert_eq!(canonical_mnemonic("seta al"), "setnbe al");
+        assert_eq!(canonical_mnemonic("cmovge eax, ecx"), "cmovnl eax, ecx");
+    }
+
+    #[test]
+    fn replaces_mnemonic_aliases() {
+        assert_eq!(canonical_mnemonic("sal eax, 2"), "shl eax, 2");
+        assert_eq!(canonical_mnemonic("retn"), "ret");
+    }
+
+    #[test]
+    fn keeps_canonical_and_unrelated_mnemonics() {
+        for text in &[
+            "jz 0x10",
+            "jmp eax",
+            "jecxz 0x10",
+            "call 0x401000",
+            "ret 4",
+            "setnz al",
+        ] {
+            assert_eq!(canonical_mnemonic(text), *text);
+        }
+        // only the mnemonic is replaced, never the operands
+        assert_eq!(canonical_mnemonic("push sal"), "push sal");
+    }
+}
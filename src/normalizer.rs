@@ -0,0 +1,74 @@
+//! Piping the disassembled lines through a user supplied command.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+use self::NormalizerError::*;
+
+#[derive(Debug)]
+pub enum NormalizerError {
+    IoError(std::io::Error),
+    Unsuccessful(ExitStatus),
+    /// Number of lines returned and expected.
+    LineCountMismatch(usize, usize),
+}
+
+/// Runs `command` in the shell once, writing one line per instruction to its stdin and reading
+/// the same number of lines back from its stdout.
+pub fn normalize_lines(command: &str, lines: &[&str]) -> Result<Vec<String>, NormalizerError> {
+    let mut child = (if cfg!(target_os = "windows") {
+        let mut c = Command::new("cmd");
+        c.arg("/C");
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.arg("-c");
+        c
+    })
+    .arg(command)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .spawn()
+    .map_err(IoError)?;
+
+    // write from another thread, the command may block on a full stdout pipe otherwise
+    let mut stdin = child.stdin.take().unwrap();
+    let input: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = BufReader::new(child.stdout.take().unwrap())
+        .lines()
+        .map(|line| line.map(|line| line.trim_end_matches('\r').to_owned()))
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(IoError)?;
+
+    // a command exiting early breaks the pipe, report that as the actual problem instead
+    let written = writer.join().unwrap();
+    let status = child.wait().map_err(IoError)?;
+    if !status.success() {
+        return Err(Unsuccessful(status));
+    }
+    if output.len() != lines.len() {
+        return Err(LineCountMismatch(output.len(), lines.len()));
+    }
+    written.map_err(IoError)?;
+
+    Ok(output)
+}
+
+pub fn error_message(e: &NormalizerError) -> String {
+    match e {
+        IoError(e) => format!("Error running the normalizer: {:#?}", e),
+        Unsuccessful(status) => format!("The normalizer exited with {}.", status),
+        LineCountMismatch(got, expected) => format!(
+            "The normalizer returned {} lines instead of {}, it has to print one line per \
+             instruction.",
+            got, expected
+        ),
+    }
+}
+
+pub fn print_error(e: &NormalizerError) {
+    println!("{}", error_message(e));
+}
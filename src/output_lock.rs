@@ -0,0 +1,118 @@
+//! A lock file keeping concurrent runs from writing the same output files.
+//!
+//! An editor triggering a compare while a watching instance writes its output would otherwise
+//! interleave both writes. The lock file is removed again when the `OutputLock` is dropped, one
+//! left behind by a killed run is taken over once its process is gone.
+
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+const LOCK_FILE: &str = ".devilution-comparer.lock";
+const LOCK_ATTEMPTS: u32 = 25;
+const LOCK_RETRY_DELAY_MS: u64 = 200;
+
+#[derive(Debug)]
+pub enum OutputLockError {
+    IoError(std::io::Error),
+    /// Another run still holds the lock file at this path.
+    Locked(PathBuf),
+}
+
+#[derive(Debug)]
+pub struct OutputLock {
+    path: PathBuf,
+}
+
+impl OutputLock {
+    /// Creates the lock file in `dir`, waiting a few seconds for another run to finish.
+    pub fn acquire(dir: impl AsRef<Path>) -> Result<Self, OutputLockError> {
+        let path = dir.as_ref().join(LOCK_FILE);
+        for attempt in 1..=LOCK_ATTEMPTS {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(f) => {
+                    write_pid(f).map_err(OutputLockError::IoError)?;
+                    return Ok(OutputLock { path });
+                }
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if is_stale(&path) {
+                        println!("Note: Removing the lock file of a run that is gone.");
+                        match std::fs::remove_file(&path) {
+                            Ok(()) => continue,
+                            // the other run may have cleaned up itself meanwhile
+                            Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                            Err(e) => return Err(OutputLockError::IoError(e)),
+                        }
+                    }
+                    if attempt == 1 {
+                        println!("Waiting for another run writing to the same directory...");
+                    }
+                    std::thread::sleep(Duration::from_millis(LOCK_RETRY_DELAY_MS));
+                }
+                Err(e) => return Err(OutputLockError::IoError(e)),
+            }
+        }
+        Err(OutputLockError::Locked(path))
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn write_pid(mut f: File) -> std::io::Result<()> {
+    writeln!(f, "{}", std::process::id())
+}
+
+/// Whether the process that wrote the lock file has exited. A lock file without a PID yet is
+/// never stale, its run may not have written it so far.
+fn is_stale(path: &Path) -> bool {
+    let pid = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| content.trim().parse::<u32>().ok());
+    match pid {
+        Some(pid) => !is_running(pid),
+        None => false,
+    }
+}
+
+/// Asks `tasklist` on Windows and `kill -0` elsewhere, assuming the process runs if that fails.
+fn is_running(pid: u32) -> bool {
+    if cfg!(target_os = "windows") {
+        Command::new("tasklist")
+            .arg("/FI")
+            .arg(format!("PID eq {}", pid))
+            .arg("/NH")
+            .stderr(Stdio::null())
+            .output()
+            .map(|output| {
+                String::from_utf8_lossy(&output.stdout)
+                    .split_whitespace()
+                    .any(|word| word == pid.to_string())
+            })
+            .unwrap_or(true)
+    } else {
+        Command::new("kill")
+            .arg("-0")
+            .arg(pid.to_string())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(true)
+    }
+}
+
+pub fn print_error(e: &OutputLockError) {
+    match e {
+        OutputLockError::IoError(e) => println!("Error creating the lock file: {:#?}", e),
+        OutputLockError::Locked(path) => println!(
+            "Another run is still writing to the output directory. If none is running, delete \
+             {} and try again.",
+            path.to_string_lossy()
+        ),
+    }
+}
@@ -0,0 +1,89 @@
+//! Paging long terminal output through `$PAGER`, or `less` if it isn't set.
+
+use std::io::{ErrorKind, Result as IoResult, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use atty::Stream;
+
+const DEFAULT_PAGER: &str = "less";
+
+/// Writes to the stdin of the pager, or to stdout if paging is off, stdout isn't a terminal or
+/// the pager can't be started. Dropping it waits until the pager is closed.
+pub struct Pager {
+    child: Option<Child>,
+}
+
+impl Pager {
+    pub fn start(enabled: bool) -> Self {
+        let child = if enabled && atty::is(Stream::Stdout) {
+            spawn_pager()
+        } else {
+            None
+        };
+        Pager { child }
+    }
+
+    /// Closes the input of the pager and waits until it is quit.
+    pub fn finish(mut self) -> IoResult<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> IoResult<()> {
+        match self.child.take() {
+            Some(mut child) => {
+                drop(child.stdin.take());
+                child.wait().map(|_| ())
+            }
+            None => std::io::stdout().flush(),
+        }
+    }
+
+    fn pager_stdin(&mut self) -> Option<&mut ChildStdin> {
+        self.child.as_mut().and_then(|child| child.stdin.as_mut())
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        match self.pager_stdin() {
+            Some(stdin) => match stdin.write(buf) {
+                // the pager was quit before showing everything
+                Err(ref e) if e.kind() == ErrorKind::BrokenPipe => Ok(buf.len()),
+                result => result,
+            },
+            None => std::io::stdout().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match self.pager_stdin() {
+            Some(stdin) => match stdin.flush() {
+                Err(ref e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+                result => result,
+            },
+            None => std::io::stdout().flush(),
+        }
+    }
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+/// Starts `$PAGER`, which may contain arguments like `less -S`.
+fn spawn_pager() -> Option<Child> {
+    let command = std::env::var("PAGER")
+        .ok()
+        .filter(|command| !command.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_owned());
+    let mut words = command.split_whitespace();
+    let mut pager = Command::new(words.next()?);
+    pager.args(words).stdin(Stdio::piped());
+    // let less show the colors of --highlight-registers, like git does
+    if std::env::var_os("LESS").is_none() {
+        pager.env("LESS", "R");
+    }
+    pager.spawn().ok()
+}
@@ -1,14 +1,26 @@
 use std::env::current_exe;
-use std::path::Path;
-use std::process::{Command, ExitStatus};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::RwLock;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use lazy_static::lazy_static;
 use regex::Regex;
 
+use super::disasm::SymbolMap;
+
 lazy_static! {
     static ref REGEX: Regex =
         Regex::new(r"(?m)^.*?PROC.*?\[.*?:(?P<offset>[0-9a-fA-F]*?)\], Cb: (?P<length>[0-9a-fA-F]*?),.*?, (?P<name>.*?)\r?$")
             .unwrap();
+    static ref DATA_REGEX: Regex =
+        Regex::new(r"(?m)^S_[GL]DATA32: \[(?P<segment>[0-9a-fA-F]+):(?P<offset>[0-9a-fA-F]+)\], Type:.*?, (?P<name>.*?)\r?$")
+            .unwrap();
+    static ref WINE_PATH: RwLock<PathBuf> = RwLock::new(PathBuf::from("wine"));
+    static ref CVDUMP_TIMEOUT: RwLock<Option<Duration>> = RwLock::new(None);
 }
 
 // pdb symbol offset + offset_compare = file offset
@@ -16,11 +28,28 @@ lazy_static! {
 pub const PDB_OFFSET_COMPARE_FILE: u64 = 0x1000;
 pub const PDB_SEGMENT_OFFSET: u64 = 0x0040_1000;
 
+const CVDUMP_ATTEMPTS: u64 = 3;
+const CVDUMP_RETRY_DELAY_MS: u64 = 200;
+const CVDUMP_POLL_INTERVAL_MS: u64 = 50;
+
 #[derive(Debug)]
 pub enum PdbError {
     IoError(std::io::Error),
     CvDumpUnsuccessful(CvDumpError),
     Utf8Error(std::string::FromUtf8Error),
+    /// CvDump was killed after running longer than the timeout.
+    CvDumpTimeout(Duration),
+}
+
+pub fn error_message(e: &PdbError) -> String {
+    match e {
+        PdbError::CvDumpTimeout(timeout) => format!("CvDump didn't finish within {:?}.", timeout),
+        e => format!("PDB file error: {:#?}", e),
+    }
+}
+
+pub fn print_error(e: &PdbError) {
+    println!("{}", error_message(e));
 }
 
 #[derive(Debug)]
@@ -45,19 +74,17 @@ pub struct Pdb {
 
 impl Pdb {
     pub fn new(file: impl AsRef<Path>) -> Result<Self, PdbError> {
-        let mut cvdump_exe_path = current_exe().map_err(PdbError::IoError)?;
-        cvdump_exe_path.set_file_name("cvdump.exe");
-
-        let cvdump = (if cfg!(target_os = "windows") {
-            Command::new(cvdump_exe_path)
-        } else {
-            let mut c = Command::new("wine");
-            c.arg(cvdump_exe_path);
-            c
-        }).arg("-s")
-        .arg(file.as_ref())
-        .output()
-        .map_err(PdbError::IoError)?;
+        // cvdump only reports a generic failure for a locked file, opening it first gives the
+        // actual error
+        File::open(file.as_ref()).map_err(PdbError::IoError)?;
+
+        let mut command = cvdump_command().map_err(PdbError::IoError)?;
+        // -g for the global variables, only static ones are among the module symbols
+        command.arg("-s").arg("-g").arg(file.as_ref());
+        let cvdump = match *CVDUMP_TIMEOUT.read().unwrap() {
+            Some(timeout) => output_with_timeout(command, timeout)?,
+            None => command.output().map_err(PdbError::IoError)?,
+        };
 
         let stdout = String::from_utf8(cvdump.stdout).map_err(PdbError::Utf8Error)?;
 
@@ -65,7 +92,7 @@ impl Pdb {
             return Err(PdbError::CvDumpUnsuccessful(CvDumpError {
                 error_code: cvdump.status.code().unwrap_or(0),
                 stdout,
-                stderr: String::from_utf8(cvdump.stderr).map_err(PdbError::Utf8Error)?
+                stderr: String::from_utf8(cvdump.stderr).map_err(PdbError::Utf8Error)?,
             }));
         }
 
@@ -75,13 +102,140 @@ impl Pdb {
         })
     }
 
+    /// Same as `new`, but retries with a growing delay if CvDump fails or the PDB is locked.
+    /// The PDB may still be written by the compiler when the watcher fires.
+    pub fn new_with_retry(file: impl AsRef<Path>) -> Result<Self, PdbError> {
+        let mut attempt = 1;
+        loop {
+            match Self::new(file.as_ref()) {
+                Err(ref e) if is_transient(e) && attempt < CVDUMP_ATTEMPTS => {
+                    std::thread::sleep(Duration::from_millis(CVDUMP_RETRY_DELAY_MS * attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
     pub fn parse_pdb(&self) -> impl Iterator<Item = FunctionSymbol> {
-        REGEX
+        parse_cvdump_output(&self.stdout)
+    }
+
+    /// Maps the function addresses in the compare binary to their names.
+    pub fn symbol_map(&self) -> SymbolMap {
+        self.parse_pdb()
+            .map(|func| (func.offset + PDB_SEGMENT_OFFSET, func.name.to_owned()))
+            .collect()
+    }
+
+    /// Maps the addresses of the global and static variables to their names, `section_addresses`
+    /// are the addresses of the segments of the compare binary.
+    pub fn data_symbol_map(&self, section_addresses: &[u64]) -> SymbolMap {
+        DATA_REGEX
             .captures_iter(&self.stdout)
-            .map(|caps| FunctionSymbol {
-                name: caps.name("name").unwrap().into(),
-                offset: u64::from_str_radix(&caps["offset"], 16).unwrap(),
-                size: usize::from_str_radix(&caps["length"], 16).unwrap(),
+            .filter_map(|caps| {
+                let segment = usize::from_str_radix(&caps["segment"], 16).unwrap();
+                let offset = u64::from_str_radix(&caps["offset"], 16).unwrap();
+                let section_address = section_addresses.get(segment.checked_sub(1)?)?;
+                Some((section_address + offset, caps["name"].to_owned()))
             })
+            .collect()
     }
 }
+
+fn is_transient(e: &PdbError) -> bool {
+    match e {
+        PdbError::CvDumpUnsuccessful(_) => true,
+        PdbError::IoError(e) => is_sharing_violation(e),
+        _ => false,
+    }
+}
+
+/// Whether another process opened the file without allowing to read it, e.g. the linker
+/// while it's still writing it.
+pub fn is_sharing_violation(e: &std::io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    cfg!(target_os = "windows") && [Some(32), Some(33)].contains(&e.raw_os_error())
+}
+
+/// cvdump.exe is expected next to this executable.
+pub fn cvdump_exe_path() -> Result<PathBuf, std::io::Error> {
+    let mut path = current_exe()?;
+    path.set_file_name("cvdump.exe");
+    Ok(path)
+}
+
+/// Sets the wine executable used to run cvdump.exe on other systems than Windows, `wine` from
+/// the `PATH` by default.
+pub fn set_wine_path(path: PathBuf) {
+    *WINE_PATH.write().unwrap() = path;
+}
+
+/// Kills CvDump if it runs longer than `timeout`, by default it isn't limited.
+pub fn set_cvdump_timeout(timeout: Duration) {
+    *CVDUMP_TIMEOUT.write().unwrap() = Some(timeout);
+}
+
+/// Same as `Command::output`, but kills the process after `timeout`.
+fn output_with_timeout(mut command: Command, timeout: Duration) -> Result<Output, PdbError> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(PdbError::IoError)?;
+    // read from other threads, cvdump blocks on a full pipe otherwise
+    let stdout = read_to_end_in_thread(child.stdout.take().unwrap());
+    let stderr = read_to_end_in_thread(child.stderr.take().unwrap());
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(PdbError::IoError)? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            // the process may have exited in the meantime, the timeout is reported either way
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(PdbError::CvDumpTimeout(timeout));
+        }
+        thread::sleep(Duration::from_millis(CVDUMP_POLL_INTERVAL_MS));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap().map_err(PdbError::IoError)?,
+        stderr: stderr.join().unwrap().map_err(PdbError::IoError)?,
+    })
+}
+
+fn read_to_end_in_thread(
+    mut pipe: impl Read + Send + 'static,
+) -> JoinHandle<Result<Vec<u8>, std::io::Error>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        pipe.read_to_end(&mut buf).map(|_| buf)
+    })
+}
+
+/// The command running cvdump.exe from the directory of this executable, using wine on
+/// other systems than Windows.
+pub fn cvdump_command() -> Result<Command, std::io::Error> {
+    let cvdump_exe_path = cvdump_exe_path()?;
+
+    Ok(if cfg!(target_os = "windows") {
+        Command::new(cvdump_exe_path)
+    } else {
+        let mut c = Command::new(&*WINE_PATH.read().unwrap());
+        c.arg(cvdump_exe_path);
+        c
+    })
+}
+
+/// Extracts the function symbols from the output of `cvdump -s`.
+pub fn parse_cvdump_output(stdout: &str) -> impl Iterator<Item = FunctionSymbol<'_>> {
+    REGEX.captures_iter(stdout).map(|caps| FunctionSymbol {
+        name: caps.name("name").unwrap().into(),
+        offset: u64::from_str_radix(&caps["offset"], 16).unwrap(),
+        size: usize::from_str_radix(&caps["length"], 16).unwrap(),
+    })
+}
@@ -0,0 +1,171 @@
+//! Reading the section table and base relocations of a PE file.
+
+/// The headers of the debug binary, the code starts right after them.
+pub const HEADERS_SIZE: usize = 0x1000;
+
+const PE_SIGNATURE: &[u8] = b"PE\0\0";
+const SECTION_HEADER_SIZE: usize = 40;
+const BASE_RELOCATION_DIRECTORY: usize = 5;
+/// Type of the relocations only padding a block to a multiple of 4 bytes.
+const IMAGE_REL_BASED_ABSOLUTE: u16 = 0;
+const IMAGE_REL_BASED_HIGHLOW: u16 = 3;
+
+struct Section {
+    virtual_address: u32,
+    raw_size: u32,
+    raw_offset: u32,
+}
+
+struct Headers {
+    image_base: u64,
+    sections: Vec<Section>,
+    /// RVA and size of the base relocations, if there are any.
+    base_relocations: Option<(u32, u32)>,
+}
+
+/// A base relocation, `target` is the address it currently points to for 32 bit relocations.
+#[derive(Debug)]
+pub struct Relocation {
+    pub address: u64,
+    pub kind: u16,
+    pub target: Option<u64>,
+}
+
+/// The virtual address of every section, in the order the PDB numbers the segments starting
+/// from 1. `None` if `headers` doesn't start with valid PE headers.
+pub fn section_addresses(headers: &[u8]) -> Option<Vec<u64>> {
+    let headers = parse_headers(headers)?;
+    Some(
+        headers
+            .sections
+            .iter()
+            .map(|section| headers.image_base + u64::from(section.virtual_address))
+            .collect(),
+    )
+}
+
+/// All base relocations of the PE file `bytes`, `None` if it can't be parsed.
+pub fn base_relocations(bytes: &[u8]) -> Option<Vec<Relocation>> {
+    let headers = parse_headers(bytes)?;
+    let (rva, size) = match headers.base_relocations {
+        Some(directory) => directory,
+        None => return Some(Vec::new()),
+    };
+    let start = file_offset(&headers, rva)?;
+    let table = bytes.get(start..start.checked_add(size as usize)?)?;
+
+    let mut relocations = Vec::new();
+    let mut block_start = 0;
+    while block_start + 8 <= table.len() {
+        let page_rva = read_u32(table, block_start)?;
+        let block_size = read_u32(table, block_start + 4)? as usize;
+        if block_size < 8 {
+            return None;
+        }
+        let block_end = block_start.checked_add(block_size)?;
+
+        for entry_offset in (block_start + 8..block_end).step_by(2) {
+            let entry = read_u16(table, entry_offset)?;
+            let kind = entry >> 12;
+            if kind == IMAGE_REL_BASED_ABSOLUTE {
+                continue;
+            }
+            let rva = page_rva.checked_add(u32::from(entry & 0xFFF))?;
+            let target = if kind == IMAGE_REL_BASED_HIGHLOW {
+                file_offset(&headers, rva)
+                    .and_then(|offset| read_u32(bytes, offset))
+                    .map(u64::from)
+            } else {
+                None
+            };
+            relocations.push(Relocation {
+                address: headers.image_base + u64::from(rva),
+                kind,
+                target,
+            });
+        }
+        block_start = block_end;
+    }
+
+    Some(relocations)
+}
+
+/// Name of an `IMAGE_REL_BASED_*` relocation type.
+pub fn relocation_kind_name(kind: u16) -> &'static str {
+    match kind {
+        1 => "HIGH",
+        2 => "LOW",
+        IMAGE_REL_BASED_HIGHLOW => "HIGHLOW",
+        4 => "HIGHADJ",
+        10 => "DIR64",
+        _ => "unknown",
+    }
+}
+
+fn parse_headers(bytes: &[u8]) -> Option<Headers> {
+    let pe_offset = read_u32(bytes, 0x3C)? as usize;
+    if bytes.get(pe_offset..pe_offset + PE_SIGNATURE.len())? != PE_SIGNATURE {
+        return None;
+    }
+
+    let coff_header = pe_offset + PE_SIGNATURE.len();
+    let section_count = read_u16(bytes, coff_header + 2)? as usize;
+    let optional_header_size = read_u16(bytes, coff_header + 16)? as usize;
+    let optional_header = coff_header + 20;
+    let image_base = u64::from(read_u32(bytes, optional_header + 28)?);
+
+    let directory_count = read_u32(bytes, optional_header + 92)? as usize;
+    let base_relocations = if directory_count > BASE_RELOCATION_DIRECTORY {
+        let directory = optional_header + 96 + BASE_RELOCATION_DIRECTORY * 8;
+        Some((read_u32(bytes, directory)?, read_u32(bytes, directory + 4)?))
+            .filter(|&(_, size)| size > 0)
+    } else {
+        None
+    };
+
+    let section_table = optional_header + optional_header_size;
+    let sections = (0..section_count)
+        .map(|k| {
+            let header = section_table + k * SECTION_HEADER_SIZE;
+            Some(Section {
+                virtual_address: read_u32(bytes, header + 12)?,
+                raw_size: read_u32(bytes, header + 16)?,
+                raw_offset: read_u32(bytes, header + 20)?,
+            })
+        })
+        .collect::<Option<Vec<Section>>>()?;
+
+    Some(Headers {
+        image_base,
+        sections,
+        base_relocations,
+    })
+}
+
+/// Offset in the file of the byte at `rva`, if it's stored in the file.
+fn file_offset(headers: &Headers, rva: u32) -> Option<usize> {
+    headers
+        .sections
+        .iter()
+        .find_map(|section| {
+            let offset = rva.checked_sub(section.virtual_address)?;
+            if offset < section.raw_size {
+                section.raw_offset.checked_add(offset)
+            } else {
+                None
+            }
+        })
+        .map(|offset| offset as usize)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let mut buf = [0; 2];
+    buf.copy_from_slice(bytes.get(offset..offset + 2)?);
+    Some(u16::from_le_bytes(buf))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(bytes.get(offset..offset + 4)?);
+    Some(u32::from_le_bytes(buf))
+}
@@ -0,0 +1,166 @@
+//! The steps between disassembling two functions and diffing their lines, shared by all
+//! commands comparing functions so they report the same matches.
+
+use super::comparer_config::{canonicalize_line, CanonicalizeRule};
+use super::diff::{DiffOp, DiffStats};
+use super::disasm::*;
+use super::normalizer::{normalize_lines, NormalizerError};
+use super::reorder::{pair_reordered_runs, REORDER_WINDOW};
+
+#[derive(Debug, Clone, Default)]
+pub struct PrepareOpts {
+    pub jumps_by_index: bool,
+    pub jumps_as_labels: bool,
+    /// Shell command every disassembled line is piped through before comparing.
+    pub normalizer: Option<String>,
+    pub allow_reorder: bool,
+    /// Only compare the first N instructions.
+    pub prologue: Option<usize>,
+    /// Only compare the last N instructions.
+    pub epilogue: Option<usize>,
+    pub ignore_trailing_padding: bool,
+}
+
+impl PrepareOpts {
+    /// Whether all instructions are compared, none left out by `prologue` or `epilogue`.
+    pub fn untrimmed(&self) -> bool {
+        self.prologue.is_none() && self.epilogue.is_none()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Prepared {
+    /// Number of trailing padding instructions left out of the diff.
+    pub orig_padding: usize,
+    pub compare_padding: usize,
+}
+
+/// Rewrites the instructions of both functions for diffing: the jump targets, the
+/// `[[canonicalize]]` rules of the config, the normalizer and the prologue and epilogue
+/// trimming. `orig_addr` and `compare_addr` are the function addresses.
+pub fn prepare_insns(
+    opts: &PrepareOpts,
+    rules: &[CanonicalizeRule],
+    orig_insns: &mut Vec<Instruction>,
+    orig_addr: u64,
+    compare_insns: &mut Vec<Instruction>,
+    compare_addr: u64,
+) -> Result<Prepared, NormalizerError> {
+    if opts.jumps_by_index {
+        jumps_by_index(orig_insns, orig_addr);
+        jumps_by_index(compare_insns, compare_addr);
+    }
+    if opts.jumps_as_labels {
+        jumps_as_labels(orig_insns);
+        jumps_as_labels(compare_insns);
+    }
+
+    canonicalize_insns(rules, orig_insns);
+    canonicalize_insns(rules, compare_insns);
+
+    if let Some(command) = &opts.normalizer {
+        let lines: Vec<&str> = orig_insns
+            .iter()
+            .chain(compare_insns.iter())
+            .map(|insn| insn.text.as_ref())
+            .collect();
+        let normalized = normalize_lines(command, &lines)?;
+        for (insn, text) in orig_insns
+            .iter_mut()
+            .chain(compare_insns.iter_mut())
+            .zip(normalized)
+        {
+            insn.text = text;
+        }
+    }
+
+    if !opts.untrimmed() {
+        prologue_epilogue(orig_insns, opts.prologue, opts.epilogue);
+        prologue_epilogue(compare_insns, opts.prologue, opts.epilogue);
+    }
+
+    let (orig_padding, compare_padding) = if opts.ignore_trailing_padding {
+        (
+            trailing_padding_len(orig_insns),
+            trailing_padding_len(compare_insns),
+        )
+    } else {
+        (0, 0)
+    };
+
+    Ok(Prepared {
+        orig_padding,
+        compare_padding,
+    })
+}
+
+/// Pairs the runs of reordered independent instructions in the diff of `diff_input` if
+/// `allow_reorder` is set. Returns the ops and the number of paired runs.
+pub fn pair_reordered(
+    opts: &PrepareOpts,
+    ops: Vec<DiffOp>,
+    orig_lines: &[&str],
+    compare_lines: &[&str],
+) -> (Vec<DiffOp>, usize) {
+    if opts.allow_reorder {
+        pair_reordered_runs(&ops, orig_lines, compare_lines, REORDER_WINDOW)
+    } else {
+        (ops, 0)
+    }
+}
+
+/// Applies the `[[canonicalize]]` rules of the config to every instruction.
+pub fn canonicalize_insns(rules: &[CanonicalizeRule], insns: &mut [Instruction]) {
+    if rules.is_empty() {
+        return;
+    }
+    for insn in insns {
+        insn.text = canonicalize_line(rules, &insn.text);
+    }
+}
+
+/// The lines to diff, leaving out `padding` trailing instructions.
+pub fn diff_input(insns: &[Instruction], padding: usize) -> Vec<&str> {
+    insns[..insns.len() - padding]
+        .iter()
+        .map(|insn| insn.text.as_ref())
+        .collect()
+}
+
+/// The statistics of the diff of `diff_input`, leaving out the labels of `jumps_as_labels`
+/// so they don't count as matching lines.
+pub fn diff_stats(
+    ops: &[DiffOp],
+    orig_lines: &[Instruction],
+    compare_lines: &[Instruction],
+) -> DiffStats {
+    let instructions = |insns: &[Instruction]| insns.iter().filter(|insn| !insn.is_label).count();
+    DiffStats {
+        orig_lines: instructions(orig_lines),
+        compare_lines: instructions(compare_lines),
+        matching_lines: ops
+            .iter()
+            .filter(|op| match **op {
+                DiffOp::Equal(i, _) => !orig_lines[i].is_label,
+                _ => false,
+            })
+            .count(),
+    }
+}
+
+/// Keeps the first `prologue` and last `epilogue` instructions, or all of them if those
+/// overlap.
+fn prologue_epilogue(
+    insns: &mut Vec<Instruction>,
+    prologue: Option<usize>,
+    epilogue: Option<usize>,
+) {
+    let head = prologue.unwrap_or(0);
+    let tail = epilogue.unwrap_or(0);
+    if head + tail >= insns.len() {
+        return;
+    }
+
+    let tail_start = insns.len() - tail;
+    insns.drain(head..tail_start);
+}
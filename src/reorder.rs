@@ -0,0 +1,229 @@
+//! Heuristic matching of reordered independent instructions.
+//!
+//! The compiler is free to swap instructions that don't depend on each other, e.g. two `mov`s
+//! to different registers. `pair_reordered_runs` pairs such runs in the diff with the
+//! instructions of the original function they are a permutation of, so they show up as
+//! matching while the compared function keeps its true order. The dependency check only
+//! understands a handful of common mnemonics and treats everything else as dependent, so this
+//! can both miss reorderings and, in rare cases, accept ones that change the semantics.
+
+use super::diff::*;
+
+/// Maximum number of adjacent instructions considered as a single reordered run.
+pub const REORDER_WINDOW: usize = 4;
+/// Maximum number of runs paired in a function, each one diffs the lines after it again.
+const MAX_REORDERED_RUNS: usize = 32;
+
+/// Registers and memory accessed by a single instruction.
+struct Effects {
+    reads: Vec<&'static str>,
+    writes: Vec<&'static str>,
+    mem_read: bool,
+    mem_write: bool,
+}
+
+/// Replaces the ops of runs of up to `window` lines in `compare` that are a permutation of
+/// the independent lines of `orig` at the aligned position by `Equal` ops pairing each
+/// original line with its moved counterpart. Returns the new ops and the number of paired
+/// runs.
+pub fn pair_reordered_runs(
+    ops: &[DiffOp],
+    orig: &[&str],
+    compare: &[&str],
+    window: usize,
+) -> (Vec<DiffOp>, usize) {
+    let mut paired = Vec::with_capacity(ops.len());
+    let mut runs = 0;
+    // the not yet copied ops, replaced by the diff of the lines after each paired run
+    let mut rest = ops.to_vec();
+    let mut k = 0;
+    // runs can only start at the beginning of the function or right after a matching line
+    let mut sync = Some((0, 0));
+
+    while k < rest.len() {
+        let run = match sync {
+            Some((i, j)) if runs < MAX_REORDERED_RUNS => (2..=window)
+                .rev()
+                .find(|&len| is_reordered_run(orig, compare, i, j, len))
+                .map(|len| (i, j, len)),
+            _ => None,
+        };
+
+        match run {
+            Some((i, j, len)) => {
+                let mut moved: Vec<usize> = (j..j + len).collect();
+                for (offset, line) in orig[i..i + len].iter().enumerate() {
+                    let pos = moved.iter().position(|&j| compare[j] == *line).unwrap();
+                    paired.push(DiffOp::Equal(i + offset, moved.remove(pos)));
+                }
+                runs += 1;
+
+                let (i, j) = (i + len, j + len);
+                rest = diff_lines(&orig[i..], &compare[j..])
+                    .into_iter()
+                    .map(|op| match op {
+                        DiffOp::Equal(a, b) => DiffOp::Equal(i + a, j + b),
+                        DiffOp::Delete(a) => DiffOp::Delete(i + a),
+                        DiffOp::Insert(b) => DiffOp::Insert(j + b),
+                    })
+                    .collect();
+                k = 0;
+                sync = Some((i, j));
+            }
+            None => {
+                let op = rest[k];
+                paired.push(op);
+                sync = match op {
+                    DiffOp::Equal(i, j) => Some((i + 1, j + 1)),
+                    _ => None,
+                };
+                k += 1;
+            }
+        }
+    }
+
+    (paired, runs)
+}
+
+fn is_reordered_run(orig: &[&str], compare: &[&str], i: usize, j: usize, len: usize) -> bool {
+    if i + len > orig.len() || j + len > compare.len() {
+        return false;
+    }
+
+    let orig_run = &orig[i..i + len];
+    let compare_run = &compare[j..j + len];
+    if orig_run[0] == compare_run[0] {
+        return false;
+    }
+
+    let mut orig_texts = orig_run.to_vec();
+    let mut compare_texts = compare_run.to_vec();
+    orig_texts.sort_unstable();
+    compare_texts.sort_unstable();
+    if orig_texts != compare_texts {
+        return false;
+    }
+
+    let effects: Option<Vec<Effects>> = orig_texts.iter().map(|text| effects(text)).collect();
+    match effects {
+        Some(effects) => effects.iter().enumerate().all(|(k, a)| {
+            effects[k + 1..]
+                .iter()
+                .all(|b| !conflicts(a, b) && !conflicts(b, a))
+        }),
+        None => false,
+    }
+}
+
+/// Whether `a` writes something `b` reads or writes.
+fn conflicts(a: &Effects, b: &Effects) -> bool {
+    a.writes
+        .iter()
+        .any(|reg| b.reads.contains(reg) || b.writes.contains(reg))
+        || (a.mem_write && (b.mem_read || b.mem_write))
+}
+
+/// Effects of the instruction, `None` if the mnemonic is unknown or the line is masked.
+fn effects(text: &str) -> Option<Effects> {
+    if text.contains(';') {
+        return None;
+    }
+
+    let (mnemonic, operands) = split_mnemonic(text);
+    // (destination written, destination read, flags written)
+    let (dest_written, dest_read, writes_flags) = match mnemonic {
+        "mov" | "movzx" | "movsx" | "lea" => (true, false, false),
+        "add" | "sub" | "and" | "or" | "xor" | "shl" | "shr" | "sar" | "inc" | "dec" | "neg" => {
+            (true, true, true)
+        }
+        "not" => (true, true, false),
+        "cmp" | "test" => (false, true, true),
+        _ => return None,
+    };
+
+    let mut effects = Effects {
+        reads: Vec::new(),
+        writes: Vec::new(),
+        mem_read: false,
+        mem_write: false,
+    };
+    if writes_flags {
+        effects.writes.push("flags");
+    }
+
+    for (k, operand) in operands
+        .split(',')
+        .map(str::trim)
+        .filter(|operand| !operand.is_empty())
+        .enumerate()
+    {
+        let registers = operand
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter_map(full_register);
+        // lea only computes the address, it doesn't access the memory
+        if operand.contains('[') && mnemonic != "lea" {
+            effects.reads.extend(registers);
+            if k == 0 && dest_written {
+                effects.mem_write = true;
+            }
+            if k > 0 || dest_read {
+                effects.mem_read = true;
+            }
+        } else if k == 0 {
+            let registers: Vec<_> = registers.collect();
+            if dest_written {
+                effects.writes.extend(&registers);
+            }
+            if dest_read {
+                effects.reads.extend(&registers);
+            }
+        } else {
+            effects.reads.extend(registers);
+        }
+    }
+
+    Some(effects)
+}
+
+/// The 32 bit register containing `name`, partial registers are treated as the whole register.
+fn full_register(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "eax" | "ax" | "ah" | "al" => "eax",
+        "ebx" | "bx" | "bh" | "bl" => "ebx",
+        "ecx" | "cx" | "ch" | "cl" => "ecx",
+        "edx" | "dx" | "dh" | "dl" => "edx",
+        "esi" | "si" => "esi",
+        "edi" | "di" => "edi",
+        "ebp" | "bp" => "ebp",
+        "esp" | "sp" => "esp",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DiffOp::*;
+
+    #[test]
+    fn pairs_swapped_independent_instructions() {
+        let orig = ["push ebp", "mov eax, 1", "mov ecx, 2", "ret"];
+        let compare = ["push ebp", "mov ecx, 2", "mov eax, 1", "ret"];
+        let ops = diff_lines(&orig, &compare);
+        assert_eq!(
+            pair_reordered_runs(&ops, &orig, &compare, REORDER_WINDOW),
+            (vec![Equal(0, 0), Equal(1, 2), Equal(2, 1), Equal(3, 3)], 1)
+        );
+    }
+
+    #[test]
+    fn keeps_dependent_instructions_apart() {
+        let orig = ["mov eax, 1", "add ecx, eax"];
+        let compare = ["add ecx, eax", "mov eax, 1"];
+        let ops = diff_lines(&orig, &compare);
+        assert_eq!(
+            pair_reordered_runs(&ops, &orig, &compare, REORDER_WINDOW),
+            (ops, 0)
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Reassembling the disassembled instructions with nasm, to catch text that doesn't describe
+//! the instruction exactly.
+//!
+//! Every instruction is assembled in its own section starting at its address, so relative
+//! jumps and calls get the original targets even if an earlier instruction comes out at a
+//! different length. A last section stores the length of each instruction, the output ends
+//! with one `dd` per instruction.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use self::RoundtripError::*;
+use super::compare_nasm::{assemble_source, CompareNasmError};
+use super::disasm::*;
+
+lazy_static! {
+    /// A line number in the error messages of nasm, `file.asm:12: error: ...`.
+    static ref NASM_ERROR_LINE: Regex = Regex::new(r":(\d+): error:").unwrap();
+}
+
+/// Operands hidden by the disassembly even with the raw options.
+const PLACEHOLDERS: &[&str] = &["<imm_fn>", "<indir_fn>", "<imm_addr>", "<indir_addr>"];
+/// String instructions, whose explicit operands nasm doesn't accept.
+const STRING_MNEMONICS: &[&str] = &[
+    "movsb", "movsw", "movsd", "stosb", "stosw", "stosd", "lodsb", "lodsw", "lodsd", "scasb",
+    "scasw", "scasd", "cmpsb", "cmpsw", "cmpsd", "insb", "insw", "insd", "outsb", "outsw", "outsd",
+];
+
+#[derive(Debug)]
+pub enum RoundtripError {
+    NasmError(CompareNasmError),
+    DisasmError(super::disasm::DisasmError),
+    /// The lengths at the end of the nasm output don't add up to its size.
+    InvalidOutput,
+}
+
+#[derive(Debug)]
+pub struct Roundtrip {
+    /// Number of reassembled instructions, without the ones nasm rejected.
+    pub checked: usize,
+    /// Instructions with operands hidden by placeholders, which can't be reassembled.
+    pub skipped: Vec<Instruction>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+#[derive(Debug)]
+pub struct Mismatch {
+    /// The instruction as passed to nasm.
+    pub insn: Instruction,
+    pub bytes: Vec<u8>,
+    /// `None` if nasm rejected the text.
+    pub assembled: Option<Vec<u8>>,
+}
+
+/// Disassembles `bytes` at `addr` with immediates and displacements shown and without symbols,
+/// then reassembles each instruction with `nasm` and compares the bytes.
+pub fn verify_roundtrip(nasm: &Path, bytes: &[u8], addr: u64) -> Result<Roundtrip, RoundtripError> {
+    let insns =
+        disasm(bytes, &mut DisasmOpts::raw(), addr, &[], &SymbolMap::new()).map_err(DisasmError)?;
+
+    let mut skipped = Vec::new();
+    let mut converted = Vec::new();
+    for insn in insns {
+        match nasm_syntax(&insn) {
+            Some(text) => converted.push(Instruction { text, ..insn }),
+            None => skipped.push(insn),
+        }
+    }
+
+    let mut rejected: HashSet<usize> = HashSet::new();
+    let assembled = loop {
+        let accepted: Vec<&Instruction> = converted
+            .iter()
+            .enumerate()
+            .filter(|(k, _)| !rejected.contains(k))
+            .map(|(_, insn)| insn)
+            .collect();
+        match assemble_each(nasm, &accepted) {
+            Ok(assembled) => break assembled,
+            Err(NasmError(CompareNasmError::NasmFailed(status, stderr))) => {
+                // leave out the rejected instructions and try again with the others
+                let lines = rejected_lines(&stderr);
+                let newly_rejected: Vec<usize> = (0..converted.len())
+                    .filter(|k| !rejected.contains(k))
+                    .enumerate()
+                    .filter(|&(index, _)| lines.contains(&instruction_line(index)))
+                    .map(|(_, k)| k)
+                    .collect();
+                rejected.extend(&newly_rejected);
+                if newly_rejected.is_empty() {
+                    return Err(NasmError(CompareNasmError::NasmFailed(status, stderr)));
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    let checked = converted.len() - rejected.len();
+    let mut assembled = assembled.into_iter();
+    let mut mismatches = Vec::new();
+    for (k, insn) in converted.into_iter().enumerate() {
+        let start = (insn.start - addr) as usize;
+        let original = bytes[start..start + (insn.ip - insn.start) as usize].to_vec();
+        let reassembled = if rejected.contains(&k) {
+            None
+        } else {
+            assembled.next()
+        };
+        if reassembled.as_ref() != Some(&original) {
+            mismatches.push(Mismatch {
+                insn,
+                bytes: original,
+                assembled: reassembled,
+            });
+        }
+    }
+
+    Ok(Roundtrip {
+        checked,
+        skipped,
+        mismatches,
+    })
+}
+
+/// Line of the source of `assemble_each` holding the instruction at `index`, counted from 1.
+fn instruction_line(index: usize) -> usize {
+    // `bits 32`, then the section, start label, instruction and end label of each
+    1 + index * 4 + 3
+}
+
+fn rejected_lines(stderr: &str) -> HashSet<usize> {
+    NASM_ERROR_LINE
+        .captures_iter(stderr)
+        .filter_map(|caps| caps[1].parse().ok())
+        .collect()
+}
+
+/// Assembles every instruction at its address, returning the bytes of each.
+fn assemble_each(nasm: &Path, insns: &[&Instruction]) -> Result<Vec<Vec<u8>>, RoundtripError> {
+    let mut source = String::from("bits 32\n");
+    for (k, insn) in insns.iter().enumerate() {
+        source.push_str(&format!(
+            "section insn_{k} vstart={:#X} align=1\ninsn_start_{k}:\n{}\ninsn_end_{k}:\n",
+            insn.start,
+            insn.text,
+            k = k
+        ));
+    }
+    source.push_str("section lengths align=1\n");
+    for k in 0..insns.len() {
+        source.push_str(&format!("dd insn_end_{k} - insn_start_{k}\n", k = k));
+    }
+
+    let output = assemble_source(nasm, &source).map_err(NasmError)?;
+    let code_len = output
+        .len()
+        .checked_sub(4 * insns.len())
+        .ok_or(InvalidOutput)?;
+    let (mut code, lengths) = output.split_at(code_len);
+    let mut assembled = Vec::with_capacity(insns.len());
+    for length in lengths.chunks(4) {
+        let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        if length > code.len() {
+            return Err(InvalidOutput);
+        }
+        let (insn_bytes, rest) = code.split_at(length);
+        assembled.push(insn_bytes.to_vec());
+        code = rest;
+    }
+    if code.is_empty() {
+        Ok(assembled)
+    } else {
+        Err(InvalidOutput)
+    }
+}
+
+/// The instruction in NASM syntax, `None` if a placeholder hides one of its operands.
+fn nasm_syntax(insn: &Instruction) -> Option<String> {
+    let mut text = insn.text.clone();
+    // nasm's `$` is the start of the instruction, the disassembly's the end
+    if let Some(target) = insn.branch_target {
+        replace_jump_target(&mut text, &format!("{:#X}", target));
+    }
+    if let Some(target) = insn.call_target {
+        text = text.replace("<imm_fn>", &format!("{:#X}", target));
+    }
+    if PLACEHOLDERS
+        .iter()
+        .any(|placeholder| text.contains(placeholder))
+    {
+        return None;
+    }
+
+    text = text
+        .replace(" ptr ", " ")
+        .replace("xmmword", "oword")
+        .replace("tbyte", "tword");
+    for k in 0..8 {
+        text = text.replace(&format!("st({})", k), &format!("st{}", k));
+    }
+    for segment in &["cs", "ds", "es", "fs", "gs", "ss"] {
+        text = text.replace(&format!("{}:[", segment), &format!("[{}:", segment));
+    }
+
+    // `rep movsd dword [es:edi], dword [esi]` is written as `rep movsd`
+    let words: Vec<&str> = text.split(' ').collect();
+    if let Some(k) = words
+        .iter()
+        .position(|word| STRING_MNEMONICS.contains(word))
+    {
+        if words[k + 1..].iter().any(|word| word.contains('[')) {
+            text = words[..=k].join(" ");
+        }
+    }
+
+    Some(text)
+}
+
+pub fn print_error(e: &RoundtripError) {
+    match e {
+        NasmError(e) => super::compare_nasm::print_error(e),
+        DisasmError(e) => super::disasm::print_error(e),
+        InvalidOutput => {
+            println!("Error: The nasm output of the reassembled instructions is invalid.")
+        }
+    }
+}
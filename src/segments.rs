@@ -0,0 +1,83 @@
+//! Splitting a disassembly at its `ret` instructions.
+//!
+//! A PDB symbol sometimes covers several small functions laid out after each other. Comparing
+//! the pieces separately shows which of them differ instead of one match percentage for all.
+
+use std::ops::Range;
+
+use super::diff::{diff_lines, DiffStats};
+
+/// Line ranges each ending with a `ret`, the lines after the last `ret` form the last segment.
+pub fn ret_segments(lines: &[&str]) -> Vec<Range<usize>> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for (k, line) in lines.iter().enumerate() {
+        if line.starts_with("ret") {
+            segments.push(start..k + 1);
+            start = k + 1;
+        }
+    }
+    if start < lines.len() {
+        segments.push(start..lines.len());
+    }
+    segments
+}
+
+/// Compares the segments of both sides in order. A side with fewer segments is compared
+/// against empty segments.
+pub fn segment_stats(orig: &[&str], compare: &[&str]) -> Vec<SegmentStats> {
+    let orig_segments = ret_segments(orig);
+    let compare_segments = ret_segments(compare);
+
+    (0..orig_segments.len().max(compare_segments.len()))
+        .map(|k| {
+            let orig_range = orig_segments
+                .get(k)
+                .cloned()
+                .unwrap_or(orig.len()..orig.len());
+            let compare_range = compare_segments
+                .get(k)
+                .cloned()
+                .unwrap_or(compare.len()..compare.len());
+            let orig_lines = &orig[orig_range.clone()];
+            let compare_lines = &compare[compare_range.clone()];
+            let ops = diff_lines(orig_lines, compare_lines);
+            SegmentStats {
+                stats: DiffStats::from_ops(&ops, orig_lines.len(), compare_lines.len()),
+                orig: orig_range,
+                compare: compare_range,
+            }
+        })
+        .collect()
+}
+
+pub struct SegmentStats {
+    pub orig: Range<usize>,
+    pub compare: Range<usize>,
+    pub stats: DiffStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_after_each_ret() {
+        let lines = ["push ebp", "ret", "xor eax, eax", "ret 4", "int3"];
+        assert_eq!(ret_segments(&lines), vec![0..2, 2..4, 4..5]);
+        assert_eq!(ret_segments(&lines[..4]), vec![0..2, 2..4]);
+        assert_eq!(ret_segments(&[]), Vec::<Range<usize>>::new());
+    }
+
+    #[test]
+    fn compares_missing_segments_against_empty_ones() {
+        let orig = ["mov eax, 1", "ret", "mov eax, 2", "ret"];
+        let compare = ["mov eax, 1", "ret"];
+        let segments = segment_stats(&orig, &compare);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].stats.matching_lines, 2);
+        assert_eq!(segments[1].orig, 2..4);
+        assert_eq!(segments[1].compare, 2..2);
+        assert_eq!(segments[1].stats.matching_lines, 0);
+    }
+}
@@ -0,0 +1,108 @@
+//! Checks the environment without needing the Diablo binaries.
+//!
+//! A real PDB can only be produced by the VC6 toolchain, so instead of a bundled test binary
+//! the PDB stage parses a recorded `cvdump -s` line and the disassembly stage a tiny embedded
+//! function with known output.
+
+use self::SelftestError::*;
+use super::comparer_config::*;
+use super::disasm::*;
+use super::pdb::*;
+
+/// `push ebp; mov ebp, esp; xor eax, eax; pop ebp; ret`
+const TEST_FUNCTION: &[u8] = &[0x55, 0x8B, 0xEC, 0x33, 0xC0, 0x5D, 0xC3];
+const TEST_FUNCTION_DISASM: &[&str] =
+    &["push ebp", "mov ebp, esp", "xor eax, eax", "pop ebp", "ret"];
+
+const TEST_CVDUMP_OUTPUT: &str =
+    "(00026C) S_GPROC32: [0001:00000010], Cb: 00000007, Type:             0x1001, main\r\n";
+
+type Check = fn() -> Result<(), String>;
+
+#[derive(Debug)]
+pub enum SelftestError {
+    /// Number of failed stages.
+    StagesFailed(usize),
+}
+
+pub fn run() -> Result<(), SelftestError> {
+    let stages: [(&str, Check); 4] = [
+        ("config", check_config),
+        ("cvdump", check_cvdump),
+        ("pdb parsing", check_pdb_parsing),
+        ("disassembly", check_disasm),
+    ];
+
+    let mut failed = 0;
+    for (name, check) in &stages {
+        match check() {
+            Ok(()) => println!("PASS  {}", name),
+            Err(reason) => {
+                println!("FAIL  {}: {}", name, reason);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(StagesFailed(failed))
+    }
+}
+
+fn check_config() -> Result<(), String> {
+    ComparerConfig::read_default()
+        .map(|_| ())
+        .map_err(|e| format!("{:?}", e))
+}
+
+fn check_cvdump() -> Result<(), String> {
+    let path = cvdump_exe_path().map_err(|e| e.to_string())?;
+    if !path.is_file() {
+        return Err(format!("{} does not exist", path.to_string_lossy()));
+    }
+
+    // cvdump without arguments only prints its usage, this just checks that it can be started
+    cvdump_command()
+        .and_then(|mut command| command.output())
+        .map(|_| ())
+        .map_err(|e| format!("could not start cvdump.exe: {}", e))
+}
+
+fn check_pdb_parsing() -> Result<(), String> {
+    match parse_cvdump_output(TEST_CVDUMP_OUTPUT).next() {
+        Some(FunctionSymbol {
+            name: "main",
+            offset: 0x10,
+            size: 7,
+        }) => Ok(()),
+        other => Err(format!("unexpected symbol {:?}", other)),
+    }
+}
+
+fn check_disasm() -> Result<(), String> {
+    let mut disasm_opts = DisasmOpts::raw();
+
+    let insns = disasm(
+        TEST_FUNCTION,
+        &mut disasm_opts,
+        PDB_SEGMENT_OFFSET,
+        &[],
+        &SymbolMap::new(),
+    )
+    .map_err(|e| format!("{:?}", e))?;
+    let lines: Vec<&str> = insns.iter().map(|insn| insn.text.as_ref()).collect();
+
+    if lines == TEST_FUNCTION_DISASM {
+        Ok(())
+    } else {
+        Err(format!("unexpected output {:?}", lines))
+    }
+}
+
+pub fn print_error(e: &SelftestError) {
+    match e {
+        StagesFailed(n) => println!("{} stage(s) failed.", n),
+    }
+}
@@ -0,0 +1,111 @@
+//! Detection of original function sizes in the config that are off by a few bytes.
+//!
+//! A size that is slightly too large or too small only changes the last instructions of the
+//! original, so everything before them matches.
+
+use super::diff::DiffOp;
+use super::disasm::Instruction;
+
+/// Largest difference between the defined and the suggested size.
+const MAX_SIZE_DELTA: usize = 16;
+
+/// The size the original function probably has if it only differs from the devilution function
+/// at its end. The original is assumed to continue like the devilution function after the last
+/// matching instruction.
+pub fn suggested_orig_size(
+    ops: &[DiffOp],
+    orig_insns: &[Instruction],
+    compare_insns: &[Instruction],
+    orig_size: usize,
+    compare_size: usize,
+) -> Option<usize> {
+    let matching = ops
+        .iter()
+        .take_while(|op| matches!(op, DiffOp::Equal(..)))
+        .count();
+    if matching == 0 || matching == ops.len() {
+        return None;
+    }
+    if ops[matching..]
+        .iter()
+        .any(|op| matches!(op, DiffOp::Equal(..)))
+    {
+        return None;
+    }
+
+    let (orig_last, compare_last) = match ops[matching - 1] {
+        DiffOp::Equal(i, j) => (&orig_insns[i], &compare_insns[j]),
+        _ => unreachable!(),
+    };
+    let orig_matching_size = (orig_last.ip - orig_insns[0].start) as usize;
+    let compare_matching_size = (compare_last.ip - compare_insns[0].start) as usize;
+    let suggested_size = orig_matching_size + compare_size.checked_sub(compare_matching_size)?;
+
+    let delta = suggested_size.abs_diff(orig_size);
+    if delta > 0 && delta <= MAX_SIZE_DELTA {
+        Some(suggested_size)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::disasm::InstructionDetail;
+    use super::*;
+    use DiffOp::*;
+
+    /// Instructions at `start` with the given lengths.
+    fn insns(start: u64, lengths: &[u64]) -> Vec<Instruction> {
+        let mut addr = start;
+        lengths
+            .iter()
+            .map(|len| {
+                addr += len;
+                Instruction {
+                    start: addr - len,
+                    ip: addr,
+                    text: String::new(),
+                    branch_target: None,
+                    call_target: None,
+                    is_label: false,
+                    detail: InstructionDetail::default(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn suggests_the_size_of_a_cut_off_original() {
+        let orig = insns(0x40_1000, &[2, 2, 2]);
+        let compare = insns(0x10_0000, &[2, 2, 5]);
+        let ops = [Equal(0, 0), Equal(1, 1), Delete(2), Insert(2)];
+        assert_eq!(suggested_orig_size(&ops, &orig, &compare, 6, 9), Some(9));
+    }
+
+    #[test]
+    fn no_suggestion_for_other_differences() {
+        let orig = insns(0x40_1000, &[2, 2, 2]);
+        let compare = insns(0x10_0000, &[2, 2, 2]);
+        let all_equal = [Equal(0, 0), Equal(1, 1), Equal(2, 2)];
+        assert_eq!(suggested_orig_size(&all_equal, &orig, &compare, 6, 6), None);
+        let differing_start = [Delete(0), Insert(0), Equal(1, 1), Equal(2, 2)];
+        assert_eq!(
+            suggested_orig_size(&differing_start, &orig, &compare, 6, 6),
+            None
+        );
+        let differing_middle = [Equal(0, 0), Delete(1), Insert(1), Equal(2, 2)];
+        assert_eq!(
+            suggested_orig_size(&differing_middle, &orig, &compare, 6, 6),
+            None
+        );
+    }
+
+    #[test]
+    fn no_suggestion_for_large_size_differences() {
+        let orig = insns(0x40_1000, &[2, 2]);
+        let compare = insns(0x10_0000, &[2, 40]);
+        let ops = [Equal(0, 0), Delete(1), Insert(1)];
+        assert_eq!(suggested_orig_size(&ops, &orig, &compare, 4, 42), None);
+    }
+}
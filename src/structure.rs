@@ -0,0 +1,59 @@
+//! Comparison of the control flow structure instead of the instructions.
+//!
+//! Useful if the instructions differ too much for a line based comparison to say anything,
+//! e.g. with a different compiler, while the blocks, branches and callees should stay the same.
+
+use super::diff::*;
+use super::disasm::*;
+
+#[derive(Debug)]
+pub struct Structure {
+    pub blocks: usize,
+    pub branches: usize,
+    /// Names of the called functions in order, unknown targets are `<unknown>`.
+    pub calls: Vec<String>,
+}
+
+impl Structure {
+    pub fn of(insns: &[Instruction], symbols: &SymbolMap) -> Self {
+        Structure {
+            blocks: if insns.is_empty() {
+                0
+            } else {
+                block_starts(insns).len() + 1
+            },
+            branches: insns
+                .iter()
+                .filter(|insn| insn.branch_target.is_some())
+                .count(),
+            calls: insns
+                .iter()
+                .filter_map(|insn| insn.call_target)
+                .map(|target| match symbols.get(&target) {
+                    Some(name) => name.clone(),
+                    None => "<unknown>".into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Average of the block count ratio, the branch count ratio and the match percentage of the
+/// callee sequences, from 0.0 to 100.0.
+pub fn structural_similarity(orig: &Structure, compare: &Structure) -> f64 {
+    let ops = diff_lines(&orig.calls, &compare.calls);
+    let calls = DiffStats::from_ops(&ops, orig.calls.len(), compare.calls.len());
+
+    (count_ratio(orig.blocks, compare.blocks)
+        + count_ratio(orig.branches, compare.branches)
+        + calls.match_percent())
+        / 3.0
+}
+
+fn count_ratio(a: usize, b: usize) -> f64 {
+    if a == b {
+        100.0
+    } else {
+        a.min(b) as f64 * 100.0 / a.max(b) as f64
+    }
+}
@@ -0,0 +1,87 @@
+//! Detection of compiler generated thunks and adjustors.
+//!
+//! They consist of a single jump, adjustors first move `this` by a constant. There's nothing
+//! to decompile about them, so they only clutter the full disassembly.
+
+use super::disasm::Instruction;
+
+/// Parts of the names VC6 gives to generated functions.
+const THUNK_NAME_PATTERNS: &[&str] = &["[thunk]", "`adjustor{", "`vcall'", "`vtordisp{"];
+
+pub fn is_thunk(name: &str, insns: &[Instruction]) -> bool {
+    if THUNK_NAME_PATTERNS
+        .iter()
+        .any(|pattern| name.contains(pattern))
+    {
+        return true;
+    }
+
+    let padding = insns
+        .iter()
+        .rev()
+        .take_while(|insn| insn.text == "int3" || insn.text == "nop")
+        .count();
+    let code: Vec<&str> = insns[..insns.len() - padding]
+        .iter()
+        .map(|insn| insn.text.as_ref())
+        .collect();
+    match code.len() {
+        1 => code[0].starts_with("jmp "),
+        2 => {
+            (code[0].starts_with("sub ecx, ") || code[0].starts_with("add ecx, "))
+                && code[1].starts_with("jmp ")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::disasm::InstructionDetail;
+    use super::*;
+
+    fn insns(lines: &[&str]) -> Vec<Instruction> {
+        lines
+            .iter()
+            .map(|text| Instruction {
+                start: 0,
+                ip: 0,
+                text: text.to_string(),
+                branch_target: None,
+                call_target: None,
+                is_label: false,
+                detail: InstructionDetail::default(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_thunks_by_name() {
+        assert!(is_thunk(
+            "[thunk]:CFoo::`vcall'{4}'",
+            &insns(&["mov eax, [ecx]"])
+        ));
+        assert!(is_thunk("CFoo::`adjustor{8}'", &[]));
+    }
+
+    #[test]
+    fn detects_single_jumps() {
+        assert!(is_thunk("Foo", &insns(&["jmp 0x401000", "int3", "int3"])));
+        assert!(is_thunk("Foo", &insns(&["sub ecx, 0x8", "jmp 0x401000"])));
+        assert!(is_thunk(
+            "Foo",
+            &insns(&["add ecx, 0x4", "jmp 0x401000", "nop"])
+        ));
+    }
+
+    #[test]
+    fn keeps_other_functions() {
+        assert!(!is_thunk("Foo", &insns(&["ret"])));
+        assert!(!is_thunk("Foo", &insns(&["mov eax, 0x1", "jmp 0x401000"])));
+        assert!(!is_thunk(
+            "Foo",
+            &insns(&["push ebp", "mov ebp, esp", "jmp 0x401000"])
+        ));
+        assert!(!is_thunk("Foo", &insns(&["int3"])));
+    }
+}
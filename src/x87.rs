@@ -0,0 +1,49 @@
+//! Counting the pushes and pops of the x87 register stack.
+//!
+//! Every function has to leave the stack as it found it, apart from a returned float in
+//! `st(0)`. Different numbers of pushes and pops on both sides usually mean a value is kept on
+//! the stack in one version and reloaded in the other.
+
+use super::disasm::Instruction;
+
+/// Instructions pushing one value.
+const PUSHING: &[&str] = &[
+    "fld", "fild", "fbld", "fld1", "fldz", "fldpi", "fldl2e", "fldl2t", "fldlg2", "fldln2",
+];
+
+/// Instructions popping one value.
+const POPPING: &[&str] = &[
+    "fstp", "fistp", "fisttp", "fbstp", "faddp", "fsubp", "fsubrp", "fmulp", "fdivp", "fdivrp",
+    "fcomp", "ficomp", "fucomp", "fcomip", "fucomip", "ffreep",
+];
+
+/// Instructions popping two values.
+const POPPING_TWICE: &[&str] = &["fcompp", "fucompp"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StackOps {
+    pub pushes: usize,
+    pub pops: usize,
+}
+
+impl StackOps {
+    pub fn of(insns: &[Instruction]) -> Self {
+        let mut ops = StackOps { pushes: 0, pops: 0 };
+        for insn in insns {
+            let mnemonic = insn.text.split(' ').next().unwrap_or("");
+            if PUSHING.contains(&mnemonic) {
+                ops.pushes += 1;
+            } else if POPPING.contains(&mnemonic) {
+                ops.pops += 1;
+            } else if POPPING_TWICE.contains(&mnemonic) {
+                ops.pops += 2;
+            }
+        }
+        ops
+    }
+
+    /// Net change of the stack depth over the whole function.
+    pub fn balance(self) -> i64 {
+        self.pushes as i64 - self.pops as i64
+    }
+}